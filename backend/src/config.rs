@@ -0,0 +1,301 @@
+use std::env;
+use std::fmt;
+
+use crate::media::s3::S3Config;
+use crate::security::keys::{KeyRing, KeyRingError};
+use crate::security::oauth::{OAuthProvider, OAuthProviderKind};
+use crate::web3::signature::is_valid_wallet_address;
+
+// Application settings loaded once at startup from environment variables
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    // Keys used to sign and verify JWTs
+    pub jwt_keys: KeyRing,
+    // Lifetime of issued access tokens (JWTs)
+    pub jwt_expiry_secs: i64,
+    // Lifetime of refresh tokens
+    pub refresh_token_ttl_secs: i64,
+    // Domain (host[:port]) that wallet sign-in messages are bound to
+    pub siws_domain: String,
+    // URI of the site requesting the signature
+    pub siws_uri: String,
+    // Solana cluster the sign-in message refers to
+    pub siws_chain_id: String,
+    // How long an issued wallet challenge stays valid
+    pub web3_challenge_ttl_secs: i64,
+    // Public URL of the frontend, used to build links in emails
+    pub app_base_url: String,
+    // Sender address on outgoing mail
+    pub mail_from: String,
+    // Directory to write outgoing mail to instead of logging it
+    pub mail_outbox_dir: Option<String>,
+    // How long a password reset link stays valid
+    pub password_reset_ttl_secs: i64,
+    // How long an email verification link stays valid
+    pub email_verification_ttl_secs: i64,
+    // Whether users must verify their email before enrolling or commenting
+    pub require_verified_email: bool,
+    // Whether admins must have two-factor authentication to use admin endpoints
+    pub require_admin_mfa: bool,
+    // Lifetime of the token exchanged for a session once the second factor is checked
+    pub mfa_token_ttl_secs: i64,
+    // Issuer name shown in authenticator apps
+    pub totp_issuer: String,
+    // Trust X-Forwarded-For/Forwarded for the client IP (only behind a proxy that sets them)
+    pub trust_proxy_headers: bool,
+    // Failed logins for one email and IP before a temporary lockout
+    pub login_max_failures: i32,
+    // How long a lockout lasts
+    pub login_lockout_secs: i64,
+    // Failures older than this no longer count toward a lockout
+    pub login_failure_window_secs: i64,
+    // Social login providers that have a client registration configured
+    pub oauth_providers: Vec<OAuthProvider>,
+    // How long a started social login may take to come back
+    pub oauth_state_ttl_secs: i64,
+    // Directory uploads are stored in when no S3 bucket is configured
+    pub media_dir: String,
+    // URL prefix uploads are served from (e.g. /media or a CDN origin)
+    pub media_public_url: String,
+    // Largest accepted upload, in bytes
+    pub media_max_upload_bytes: usize,
+    // How long an unreferenced upload is kept before garbage collection removes it
+    pub media_gc_grace_secs: i64,
+    // S3-compatible bucket to store uploads in instead of local disk
+    pub media_s3: Option<S3Config>,
+    // JSON-RPC endpoint of the Solana cluster payments are verified on
+    pub solana_rpc_url: String,
+    // Wallet that receives course payments; paid checkout is off when unset
+    pub payment_recipient: Option<String>,
+    // How long a checkout order waits for payment before a new one is needed
+    pub order_ttl_secs: i64,
+}
+
+// A setting that is missing or can't be used
+#[derive(Debug)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<KeyRingError> for ConfigError {
+    fn from(e: KeyRingError) -> Self {
+        ConfigError(e.to_string())
+    }
+}
+
+impl AppConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(AppConfig {
+            jwt_keys: KeyRing::from_env()?,
+            jwt_expiry_secs: env_parse_or("JWT_EXPIRY_SECS", 15 * 60),
+            refresh_token_ttl_secs: env_parse_or("REFRESH_TOKEN_TTL_SECS", 30 * 24 * 60 * 60),
+            siws_domain: env_or("SIWS_DOMAIN", "localhost:5000"),
+            siws_uri: env_or("SIWS_URI", "http://localhost:5000"),
+            siws_chain_id: env_or("SIWS_CHAIN_ID", "mainnet"),
+            web3_challenge_ttl_secs: env_parse_or("WEB3_CHALLENGE_TTL_SECS", 300),
+            app_base_url: env_or("APP_BASE_URL", "http://localhost:5000"),
+            mail_from: env_or("MAIL_FROM", "Hex The Add Hub <noreply@localhost>"),
+            mail_outbox_dir: env::var("MAIL_OUTBOX_DIR").ok(),
+            password_reset_ttl_secs: env_parse_or("PASSWORD_RESET_TTL_SECS", 60 * 60),
+            email_verification_ttl_secs: env_parse_or("EMAIL_VERIFICATION_TTL_SECS", 48 * 60 * 60),
+            require_verified_email: env_parse_or("REQUIRE_VERIFIED_EMAIL", true),
+            require_admin_mfa: env_parse_or("REQUIRE_ADMIN_MFA", true),
+            mfa_token_ttl_secs: env_parse_or("MFA_TOKEN_TTL_SECS", 5 * 60),
+            totp_issuer: env_or("TOTP_ISSUER", "Hex The Add Hub"),
+            trust_proxy_headers: env_parse_or("TRUST_PROXY_HEADERS", false),
+            login_max_failures: env_parse_or("LOGIN_MAX_FAILURES", 10),
+            login_lockout_secs: env_parse_or("LOGIN_LOCKOUT_SECS", 15 * 60),
+            login_failure_window_secs: env_parse_or("LOGIN_FAILURE_WINDOW_SECS", 60 * 60),
+            oauth_providers: [OAuthProviderKind::Google, OAuthProviderKind::GitHub]
+                .into_iter()
+                .filter_map(|kind| oauth_provider_from_env(kind).transpose())
+                .collect::<Result<_, _>>()?,
+            oauth_state_ttl_secs: env_parse_or("OAUTH_STATE_TTL_SECS", 10 * 60),
+            media_dir: env_or("MEDIA_DIR", "./uploads"),
+            media_public_url: env_or("MEDIA_PUBLIC_URL", "/media"),
+            media_max_upload_bytes: env_parse_or("MEDIA_MAX_UPLOAD_BYTES", 5 * 1024 * 1024),
+            media_gc_grace_secs: env_parse_or("MEDIA_GC_GRACE_SECS", 24 * 60 * 60),
+            media_s3: media_s3_from_env()?,
+            solana_rpc_url: env_or("SOLANA_RPC_URL", "https://api.mainnet-beta.solana.com"),
+            payment_recipient: payment_recipient_from_env()?,
+            order_ttl_secs: env_parse_or("ORDER_TTL_SECS", 30 * 60),
+        })
+    }
+
+    // Look up an enabled social login provider by name (e.g. "github")
+    pub fn oauth_provider(&self, name: &str) -> Option<&OAuthProvider> {
+        let kind = OAuthProviderKind::from_name(name)?;
+        self.oauth_providers.iter().find(|provider| provider.kind == kind)
+    }
+
+    // Fixed settings for unit tests that must not depend on the environment
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        AppConfig {
+            jwt_keys: KeyRing::from_secret("test-secret"),
+            jwt_expiry_secs: 3600,
+            refresh_token_ttl_secs: 86400,
+            siws_domain: "localhost:5000".to_string(),
+            siws_uri: "http://localhost:5000".to_string(),
+            siws_chain_id: "mainnet".to_string(),
+            web3_challenge_ttl_secs: 300,
+            app_base_url: "http://localhost:5000".to_string(),
+            mail_from: "noreply@localhost".to_string(),
+            mail_outbox_dir: None,
+            password_reset_ttl_secs: 3600,
+            email_verification_ttl_secs: 48 * 3600,
+            require_verified_email: true,
+            require_admin_mfa: true,
+            mfa_token_ttl_secs: 300,
+            totp_issuer: "Hex The Add Hub".to_string(),
+            trust_proxy_headers: false,
+            login_max_failures: 10,
+            login_lockout_secs: 900,
+            login_failure_window_secs: 3600,
+            oauth_providers: Vec::new(),
+            oauth_state_ttl_secs: 600,
+            media_dir: "./uploads".to_string(),
+            media_public_url: "/media".to_string(),
+            media_max_upload_bytes: 5 * 1024 * 1024,
+            media_gc_grace_secs: 86400,
+            media_s3: None,
+            solana_rpc_url: "http://localhost:8899".to_string(),
+            payment_recipient: Some("GvHeR432g7MjN9uKyX3Dzg66TqwrEWgANLnnFZXMeyyj".to_string()),
+            order_ttl_secs: 1800,
+        }
+    }
+}
+
+// Read an environment variable, falling back to a default when unset
+fn env_or(key: &str, default: &str) -> String {
+    env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+// Read an environment variable that a feature needs once it is enabled
+fn env_required(key: &str) -> Result<String, ConfigError> {
+    env::var(key).map_err(|_| ConfigError(format!("{} must be set", key)))
+}
+
+// Read and parse an environment variable, falling back to a default when unset or invalid
+fn env_parse_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// A provider is enabled by setting <PROVIDER>_CLIENT_ID and <PROVIDER>_CLIENT_SECRET;
+// its endpoints default to the real ones and can be overridden (e.g. for a mock IdP)
+fn oauth_provider_from_env(kind: OAuthProviderKind) -> Result<Option<OAuthProvider>, ConfigError> {
+    let (prefix, authorize_url, token_url, userinfo_url, scopes) = match kind {
+        OAuthProviderKind::Google => (
+            "GOOGLE",
+            "https://accounts.google.com/o/oauth2/v2/auth",
+            "https://oauth2.googleapis.com/token",
+            "https://openidconnect.googleapis.com/v1/userinfo",
+            "openid email profile",
+        ),
+        OAuthProviderKind::GitHub => (
+            "GITHUB",
+            "https://github.com/login/oauth/authorize",
+            "https://github.com/login/oauth/access_token",
+            "https://api.github.com",
+            "read:user user:email",
+        ),
+    };
+
+    let client_id = match env::var(format!("{}_CLIENT_ID", prefix)) {
+        Ok(client_id) => client_id,
+        Err(_) => return Ok(None),
+    };
+    let client_secret = env_required(&format!("{}_CLIENT_SECRET", prefix))?;
+
+    Ok(Some(OAuthProvider {
+        kind,
+        client_id,
+        client_secret,
+        authorize_url: env_or(&format!("{}_AUTHORIZE_URL", prefix), authorize_url),
+        token_url: env_or(&format!("{}_TOKEN_URL", prefix), token_url),
+        userinfo_url: env_or(&format!("{}_USERINFO_URL", prefix), userinfo_url),
+        scopes: scopes.to_string(),
+    }))
+}
+
+// S3 storage is enabled by setting MEDIA_S3_BUCKET; the endpoint can point at any
+// S3-compatible service (MinIO, R2, ...)
+fn media_s3_from_env() -> Result<Option<S3Config>, ConfigError> {
+    let bucket = match env::var("MEDIA_S3_BUCKET") {
+        Ok(bucket) => bucket,
+        Err(_) => return Ok(None),
+    };
+
+    Ok(Some(S3Config {
+        endpoint: env_or("MEDIA_S3_ENDPOINT", "https://s3.amazonaws.com"),
+        bucket,
+        region: env_or("MEDIA_S3_REGION", "us-east-1"),
+        access_key_id: env_required("MEDIA_S3_ACCESS_KEY_ID")?,
+        secret_access_key: env_required("MEDIA_S3_SECRET_ACCESS_KEY")?,
+    }))
+}
+
+// Refuse to start with a recipient that payments could never reach
+fn payment_recipient_from_env() -> Result<Option<String>, ConfigError> {
+    let recipient = match env::var("PAYMENT_RECIPIENT") {
+        Ok(recipient) => recipient,
+        Err(_) => return Ok(None),
+    };
+    if !is_valid_wallet_address(&recipient) {
+        return Err(ConfigError(
+            "PAYMENT_RECIPIENT must be a Solana wallet address".to_string(),
+        ));
+    }
+    Ok(Some(recipient))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only this test reads the MEDIA_S3_* variables, so setting them can't race
+    #[test]
+    fn test_s3_needs_credentials_once_enabled() {
+        env::remove_var("MEDIA_S3_BUCKET");
+        assert!(media_s3_from_env().unwrap().is_none());
+
+        env::set_var("MEDIA_S3_BUCKET", "uploads");
+        env::remove_var("MEDIA_S3_ACCESS_KEY_ID");
+        env::remove_var("MEDIA_S3_SECRET_ACCESS_KEY");
+        let error = media_s3_from_env().unwrap_err();
+        assert_eq!(error.to_string(), "MEDIA_S3_ACCESS_KEY_ID must be set");
+
+        env::set_var("MEDIA_S3_ACCESS_KEY_ID", "key");
+        env::set_var("MEDIA_S3_SECRET_ACCESS_KEY", "secret");
+        let s3 = media_s3_from_env().unwrap().unwrap();
+        assert_eq!(s3.bucket, "uploads");
+
+        env::remove_var("MEDIA_S3_BUCKET");
+        env::remove_var("MEDIA_S3_ACCESS_KEY_ID");
+        env::remove_var("MEDIA_S3_SECRET_ACCESS_KEY");
+    }
+    // Likewise for PAYMENT_RECIPIENT
+    #[test]
+    fn test_payment_recipient_must_be_a_wallet() {
+        env::remove_var("PAYMENT_RECIPIENT");
+        assert_eq!(payment_recipient_from_env().unwrap(), None);
+
+        env::set_var("PAYMENT_RECIPIENT", "not-a-wallet");
+        assert!(payment_recipient_from_env().is_err());
+
+        let wallet = "GvHeR432g7MjN9uKyX3Dzg66TqwrEWgANLnnFZXMeyyj";
+        env::set_var("PAYMENT_RECIPIENT", wallet);
+        assert_eq!(payment_recipient_from_env().unwrap().as_deref(), Some(wallet));
+
+        env::remove_var("PAYMENT_RECIPIENT");
+    }
+}
@@ -0,0 +1,313 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::models::coupon::{Coupon, Discount};
+use crate::models::money::{Money, PRICE_SCALE};
+
+// Coupons with how often each has been redeemed; callers add the filter
+pub const COUPON_QUERY: &str = "SELECT c.id, c.code, c.discount_type, c.percent_off, c.amount_off, c.currency,
+        c.course_id, c.max_redemptions, c.max_redemptions_per_user, c.starts_at, c.expires_at,
+        c.active, c.created_by, c.created_at, c.updated_at,
+        (SELECT COUNT(*) FROM coupon_redemptions r WHERE r.coupon_id = c.id) AS redemptions
+    FROM coupons c";
+
+// Why a coupon can't be used for a purchase
+#[derive(Debug, PartialEq, Eq)]
+pub enum CouponError {
+    NotFound,
+    Inactive,
+    NotStarted,
+    Expired,
+    OtherCourse,
+    CurrencyMismatch,
+    UsedUp,
+    UsedUpByUser,
+}
+
+impl fmt::Display for CouponError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CouponError::NotFound => write!(f, "Coupon not found"),
+            CouponError::Inactive => write!(f, "Coupon is not active"),
+            CouponError::NotStarted => write!(f, "Coupon is not valid yet"),
+            CouponError::Expired => write!(f, "Coupon has expired"),
+            CouponError::OtherCourse => write!(f, "Coupon does not apply to this course"),
+            CouponError::CurrencyMismatch => write!(f, "Coupon is in a different currency than the course"),
+            CouponError::UsedUp => write!(f, "Coupon has been used up"),
+            CouponError::UsedUpByUser => write!(f, "You have already used this coupon"),
+        }
+    }
+}
+
+impl std::error::Error for CouponError {}
+
+// Uses of a coupon counted against its limits: redemptions plus open orders
+// that will redeem it once paid
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CouponUsage {
+    pub total: i64,
+    pub by_user: i64,
+}
+
+// A coupon accepted for a purchase, with the price it leaves to pay
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedCoupon {
+    pub coupon_id: Uuid,
+    pub code: String,
+    pub discount: Money,
+    pub price: Money,
+}
+
+// Check `code` against a purchase of `course_id` at `price` and work out the
+// discounted price. The coupon row is locked, so within a transaction
+// concurrent purchases can't both take its last redemption.
+pub async fn apply_coupon<C: GenericClient>(
+    client: &C,
+    code: &str,
+    user_id: Uuid,
+    course_id: Uuid,
+    price: Money,
+) -> Result<Result<AppliedCoupon, CouponError>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            &format!("{} WHERE c.code = $1 FOR UPDATE OF c", COUPON_QUERY),
+            &[&normalize_code(code)],
+        )
+        .await?;
+    let coupon = match row {
+        Some(row) => coupon_from_row(&row),
+        None => return Ok(Err(CouponError::NotFound)),
+    };
+
+    let usage = load_usage(client, coupon.id, user_id, course_id).await?;
+    if let Err(e) = check_coupon(&coupon, course_id, usage, Utc::now()) {
+        return Ok(Err(e));
+    }
+
+    Ok(apply_discount(&coupon.discount, price).map(|discounted| AppliedCoupon {
+        coupon_id: coupon.id,
+        code: coupon.code,
+        discount: Money::new(price.amount - discounted.amount, price.currency),
+        price: discounted,
+    }))
+}
+
+// The user's own open order for this course doesn't count; it is reused or
+// replaced by the purchase being checked
+async fn load_usage<C: GenericClient>(
+    client: &C,
+    coupon_id: Uuid,
+    user_id: Uuid,
+    course_id: Uuid,
+) -> Result<CouponUsage, tokio_postgres::Error> {
+    let row = client
+        .query_one(
+            "SELECT COUNT(*) AS total, COUNT(*) FILTER (WHERE u.user_id = $2) AS by_user
+             FROM (
+                 SELECT user_id FROM coupon_redemptions WHERE coupon_id = $1
+                 UNION ALL
+                 SELECT user_id FROM orders
+                 WHERE coupon_id = $1 AND status = 'pending' AND expires_at > NOW()
+                   AND NOT (user_id = $2 AND course_id = $3)
+             ) u",
+            &[&coupon_id, &user_id, &course_id],
+        )
+        .await?;
+
+    Ok(CouponUsage {
+        total: row.get("total"),
+        by_user: row.get("by_user"),
+    })
+}
+
+// Note that a coupon was used for an enrollment. Paying for an order redeems
+// its coupon even if the limits were reached in the meantime.
+pub async fn record_redemption<C: GenericClient>(
+    client: &C,
+    coupon_id: Uuid,
+    user_id: Uuid,
+    course_id: Uuid,
+    order_id: Option<Uuid>,
+    discount: Decimal,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "INSERT INTO coupon_redemptions (id, coupon_id, user_id, course_id, order_id, discount)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (coupon_id, user_id, course_id) DO NOTHING",
+            &[&Uuid::new_v4(), &coupon_id, &user_id, &course_id, &order_id, &discount],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn coupon_from_row(row: &tokio_postgres::Row) -> Coupon {
+    let discount_type: String = row.get("discount_type");
+    let discount = match discount_type.as_str() {
+        "percent" => Discount::Percent {
+            percent_off: row.get("percent_off"),
+        },
+        _ => Discount::Fixed {
+            amount_off: Money::new(row.get("amount_off"), row.get("currency")),
+        },
+    };
+
+    Coupon {
+        id: row.get("id"),
+        code: row.get("code"),
+        discount,
+        course_id: row.get("course_id"),
+        max_redemptions: row.get("max_redemptions"),
+        max_redemptions_per_user: row.get("max_redemptions_per_user"),
+        starts_at: row.get("starts_at"),
+        expires_at: row.get("expires_at"),
+        active: row.get("active"),
+        redemptions: row.get("redemptions"),
+        created_by: row.get("created_by"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+// Codes are compared upper-case with surrounding whitespace ignored
+pub fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+pub fn check_coupon(
+    coupon: &Coupon,
+    course_id: Uuid,
+    usage: CouponUsage,
+    now: DateTime<Utc>,
+) -> Result<(), CouponError> {
+    if !coupon.active {
+        return Err(CouponError::Inactive);
+    }
+    if coupon.starts_at.is_some_and(|starts_at| now < starts_at) {
+        return Err(CouponError::NotStarted);
+    }
+    if coupon.expires_at.is_some_and(|expires_at| now >= expires_at) {
+        return Err(CouponError::Expired);
+    }
+    if coupon.course_id.is_some_and(|id| id != course_id) {
+        return Err(CouponError::OtherCourse);
+    }
+    if coupon.max_redemptions.is_some_and(|max| usage.total >= i64::from(max)) {
+        return Err(CouponError::UsedUp);
+    }
+    if coupon.max_redemptions_per_user.is_some_and(|max| usage.by_user >= i64::from(max)) {
+        return Err(CouponError::UsedUpByUser);
+    }
+
+    Ok(())
+}
+
+// The price left after a discount. Percentages round the result down to the
+// cent, in the buyer's favour.
+pub fn apply_discount(discount: &Discount, price: Money) -> Result<Money, CouponError> {
+    let amount = match discount {
+        Discount::Percent { percent_off } => {
+            let remaining = Decimal::from(100 - (*percent_off).clamp(0, 100));
+            (price.amount * remaining / Decimal::from(100))
+                .round_dp_with_strategy(PRICE_SCALE, RoundingStrategy::ToZero)
+        }
+        Discount::Fixed { amount_off } => {
+            if amount_off.currency != price.currency {
+                return Err(CouponError::CurrencyMismatch);
+            }
+            (price.amount - amount_off.amount).max(Decimal::ZERO)
+        }
+    };
+
+    Ok(Money::new(amount, price.currency))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::money::Currency;
+    use chrono::Duration;
+
+    fn sol(amount: &str) -> Money {
+        Money::new(amount.parse().unwrap(), Currency::Sol)
+    }
+
+    fn coupon(discount: Discount) -> Coupon {
+        Coupon {
+            id: Uuid::new_v4(),
+            code: "SPRING25".to_string(),
+            discount,
+            course_id: None,
+            max_redemptions: None,
+            max_redemptions_per_user: None,
+            starts_at: None,
+            expires_at: None,
+            active: true,
+            redemptions: 0,
+            created_by: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_apply_discount() {
+        let quarter = Discount::Percent { percent_off: 25 };
+        assert_eq!(apply_discount(&quarter, sol("40")), Ok(sol("30")));
+        // 19.99 * 0.75 = 14.9925, rounded down for the buyer
+        assert_eq!(apply_discount(&quarter, sol("19.99")), Ok(sol("14.99")));
+        assert_eq!(apply_discount(&Discount::Percent { percent_off: 100 }, sol("19.99")), Ok(sol("0")));
+
+        let five_off = Discount::Fixed { amount_off: sol("5") };
+        assert_eq!(apply_discount(&five_off, sol("12.50")), Ok(sol("7.50")));
+        assert_eq!(apply_discount(&five_off, sol("3")), Ok(sol("0")));
+    }
+
+    #[test]
+    fn test_check_coupon_window_and_scope() {
+        let now = Utc::now();
+        let course_id = Uuid::new_v4();
+        let usage = CouponUsage::default();
+        let mut c = coupon(Discount::Percent { percent_off: 10 });
+        assert_eq!(check_coupon(&c, course_id, usage, now), Ok(()));
+
+        c.starts_at = Some(now + Duration::hours(1));
+        assert_eq!(check_coupon(&c, course_id, usage, now), Err(CouponError::NotStarted));
+        c.starts_at = Some(now - Duration::hours(1));
+        c.expires_at = Some(now);
+        assert_eq!(check_coupon(&c, course_id, usage, now), Err(CouponError::Expired));
+        c.expires_at = Some(now + Duration::hours(1));
+        assert_eq!(check_coupon(&c, course_id, usage, now), Ok(()));
+
+        c.course_id = Some(Uuid::new_v4());
+        assert_eq!(check_coupon(&c, course_id, usage, now), Err(CouponError::OtherCourse));
+        c.course_id = Some(course_id);
+        assert_eq!(check_coupon(&c, course_id, usage, now), Ok(()));
+
+        c.active = false;
+        assert_eq!(check_coupon(&c, course_id, usage, now), Err(CouponError::Inactive));
+    }
+
+    #[test]
+    fn test_check_coupon_limits() {
+        let now = Utc::now();
+        let course_id = Uuid::new_v4();
+        let mut c = coupon(Discount::Fixed { amount_off: sol("1") });
+        c.max_redemptions = Some(3);
+        c.max_redemptions_per_user = Some(1);
+
+        let usage = |total, by_user| CouponUsage { total, by_user };
+        assert_eq!(check_coupon(&c, course_id, usage(2, 0), now), Ok(()));
+        assert_eq!(check_coupon(&c, course_id, usage(3, 0), now), Err(CouponError::UsedUp));
+        assert_eq!(check_coupon(&c, course_id, usage(2, 1), now), Err(CouponError::UsedUpByUser));
+    }
+
+    #[test]
+    fn test_normalize_code() {
+        assert_eq!(normalize_code("  spring25 "), "SPRING25");
+    }
+}
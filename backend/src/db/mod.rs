@@ -1,2 +1,5 @@
 pub mod mongodb;
 pub mod postgres;
+pub mod user_data;
+pub mod progress;
+pub mod coupons;
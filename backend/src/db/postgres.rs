@@ -1,7 +1,9 @@
-use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod, Runtime};
+use deadpool_postgres::{Client, Config, ManagerConfig, Pool, RecyclingMethod, Runtime};
 use std::env;
 use tokio_postgres::NoTls;
 
+use crate::models::money::Currency;
+
 pub async fn init_postgres() -> Result<Pool, Box<dyn std::error::Error>> {
     let mut cfg = Config::new();
     
@@ -93,7 +95,437 @@ pub async fn init_postgres() -> Result<Pool, Box<dyn std::error::Error>> {
             last_accessed TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
             PRIMARY KEY (user_id, lesson_id)
         );
+        
+        -- Outstanding wallet sign-in challenges (single use)
+        CREATE TABLE IF NOT EXISTS web3_challenges (
+            nonce VARCHAR(64) PRIMARY KEY,
+            wallet_address VARCHAR(255) NOT NULL,
+            domain VARCHAR(255) NOT NULL,
+            message TEXT NOT NULL,
+            issued_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            used_at TIMESTAMP WITH TIME ZONE
+        );
+        
+        -- Refresh tokens (hashed); tokens in one family belong to one login session
+        CREATE TABLE IF NOT EXISTS refresh_tokens (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            family_id UUID NOT NULL,
+            token_hash VARCHAR(64) UNIQUE NOT NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            rotated_at TIMESTAMP WITH TIME ZONE,
+            revoked_at TIMESTAMP WITH TIME ZONE
+        );
+        
+        CREATE INDEX IF NOT EXISTS refresh_tokens_family_idx ON refresh_tokens (family_id);
+        
+        -- Access tokens carry the token version they were issued under; bumping it
+        -- invalidates every outstanding token for the user
+        ALTER TABLE users ADD COLUMN IF NOT EXISTS token_version INTEGER NOT NULL DEFAULT 0;
+        
+        CREATE OR REPLACE FUNCTION bump_token_version() RETURNS TRIGGER AS $$
+        BEGIN
+            IF NEW.is_admin IS DISTINCT FROM OLD.is_admin
+                OR NEW.password_hash IS DISTINCT FROM OLD.password_hash THEN
+                NEW.token_version := OLD.token_version + 1;
+            END IF;
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql;
+        
+        DROP TRIGGER IF EXISTS users_bump_token_version ON users;
+        CREATE TRIGGER users_bump_token_version
+            BEFORE UPDATE ON users
+            FOR EACH ROW EXECUTE FUNCTION bump_token_version();
+        
+        -- Password reset tokens (hashed, single use)
+        CREATE TABLE IF NOT EXISTS password_reset_tokens (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token_hash VARCHAR(64) UNIQUE NOT NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            used_at TIMESTAMP WITH TIME ZONE
+        );
+        
+        -- Email verification; changing the email clears it again. Accounts that
+        -- predate verification are treated as verified when the column is
+        -- first added, so requiring it doesn't lock them out.
+        DO $$
+        BEGIN
+            IF NOT EXISTS (
+                SELECT 1 FROM information_schema.columns
+                WHERE table_schema = current_schema() AND table_name = 'users'
+                  AND column_name = 'email_verified_at'
+            ) THEN
+                ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMP WITH TIME ZONE;
+                UPDATE users SET email_verified_at = created_at;
+            END IF;
+        END
+        $$;
+        
+        CREATE OR REPLACE FUNCTION reset_email_verification() RETURNS TRIGGER AS $$
+        BEGIN
+            IF NEW.email IS DISTINCT FROM OLD.email THEN
+                NEW.email_verified_at := NULL;
+            END IF;
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql;
+        
+        DROP TRIGGER IF EXISTS users_reset_email_verification ON users;
+        CREATE TRIGGER users_reset_email_verification
+            BEFORE UPDATE ON users
+            FOR EACH ROW EXECUTE FUNCTION reset_email_verification();
+        
+        -- Emails are unique regardless of case. Accounts whose emails differ
+        -- only in case must be merged by hand before the index can be built.
+        DO $$
+        BEGIN
+            IF NOT EXISTS (
+                SELECT 1 FROM pg_indexes
+                WHERE schemaname = current_schema() AND indexname = 'users_email_lower_idx'
+            ) AND EXISTS (
+                SELECT 1 FROM users GROUP BY LOWER(email) HAVING COUNT(*) > 1
+            ) THEN
+                RAISE EXCEPTION 'Some users have emails that differ only in case; merge those accounts before upgrading';
+            END IF;
+        END
+        $$;
+        CREATE UNIQUE INDEX IF NOT EXISTS users_email_lower_idx ON users (LOWER(email));
+        
+        -- Email verification tokens (hashed, single use), bound to the address they were sent to
+        CREATE TABLE IF NOT EXISTS email_verification_tokens (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            email VARCHAR(255) NOT NULL,
+            token_hash VARCHAR(64) UNIQUE NOT NULL,
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            used_at TIMESTAMP WITH TIME ZONE
+        );
+        
+        -- TOTP two-factor authentication; the secret only counts once confirmed
+        CREATE TABLE IF NOT EXISTS user_totp (
+            user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+            secret VARCHAR(64) NOT NULL,
+            confirmed_at TIMESTAMP WITH TIME ZONE,
+            last_used_step BIGINT,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        );
+        
+        -- Second-factor challenges behind mfa pending tokens; each allows a few
+        -- attempts and is deleted once a code is accepted
+        CREATE TABLE IF NOT EXISTS mfa_challenges (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+        );
+        
+        -- Single-use recovery codes (hashed) for when the authenticator is lost
+        CREATE TABLE IF NOT EXISTS user_recovery_codes (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            code_hash VARCHAR(64) NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            used_at TIMESTAMP WITH TIME ZONE
+        );
+        
+        CREATE INDEX IF NOT EXISTS user_recovery_codes_user_idx ON user_recovery_codes (user_id);
+        
+        -- Wallets linked to an account; users.web3_wallet is kept as the primary one
+        CREATE TABLE IF NOT EXISTS user_wallets (
+            wallet_address VARCHAR(255) PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            linked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        );
+        
+        CREATE INDEX IF NOT EXISTS user_wallets_user_idx ON user_wallets (user_id);
+        
+        INSERT INTO user_wallets (wallet_address, user_id)
+            SELECT web3_wallet, id FROM users WHERE web3_wallet IS NOT NULL
+            ON CONFLICT (wallet_address) DO NOTHING;
+        
+        -- Setting a primary wallet links it; fails if another user already linked it
+        CREATE OR REPLACE FUNCTION link_primary_wallet() RETURNS TRIGGER AS $$
+        BEGIN
+            IF NEW.web3_wallet IS NOT NULL AND NOT EXISTS (
+                SELECT 1 FROM user_wallets WHERE wallet_address = NEW.web3_wallet AND user_id = NEW.id
+            ) THEN
+                INSERT INTO user_wallets (wallet_address, user_id) VALUES (NEW.web3_wallet, NEW.id);
+            END IF;
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql;
+        
+        DROP TRIGGER IF EXISTS users_link_primary_wallet ON users;
+        CREATE TRIGGER users_link_primary_wallet
+            AFTER INSERT OR UPDATE OF web3_wallet ON users
+            FOR EACH ROW EXECUTE FUNCTION link_primary_wallet();
+        
+        -- Failed password logins per (email, client IP), for backoff and lockout
+        CREATE TABLE IF NOT EXISTS login_throttles (
+            email VARCHAR(255) NOT NULL,
+            ip_address VARCHAR(64) NOT NULL,
+            failed_count INTEGER NOT NULL DEFAULT 0,
+            last_failed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            locked_until TIMESTAMP WITH TIME ZONE,
+            PRIMARY KEY (email, ip_address)
+        );
+        
+        -- Security audit trail
+        CREATE TABLE IF NOT EXISTS audit_log (
+            id UUID PRIMARY KEY,
+            event VARCHAR(64) NOT NULL,
+            user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+            actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+            ip_address VARCHAR(64),
+            details JSONB NOT NULL DEFAULT '{}',
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        );
+        
+        CREATE INDEX IF NOT EXISTS audit_log_user_idx ON audit_log (user_id, created_at);
+        
+        -- Social logins in progress (single use); holds the PKCE verifier
+        CREATE TABLE IF NOT EXISTS oauth_states (
+            state VARCHAR(64) PRIMARY KEY,
+            provider VARCHAR(32) NOT NULL,
+            code_verifier VARCHAR(128) NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+        );
+        
+        -- Accounts at external identity providers linked to users
+        CREATE TABLE IF NOT EXISTS user_identities (
+            provider VARCHAR(32) NOT NULL,
+            subject VARCHAR(255) NOT NULL,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            email VARCHAR(255),
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (provider, subject)
+        );
+        
+        CREATE INDEX IF NOT EXISTS user_identities_user_idx ON user_identities (user_id);
+        
+        -- Roles and the permissions they grant. The admin role is held through
+        -- users.is_admin; the others through user_roles.
+        CREATE TABLE IF NOT EXISTS roles (
+            name VARCHAR(32) PRIMARY KEY,
+            description TEXT NOT NULL
+        );
+        
+        CREATE TABLE IF NOT EXISTS role_permissions (
+            role VARCHAR(32) NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+            permission VARCHAR(64) NOT NULL,
+            PRIMARY KEY (role, permission)
+        );
+        
+        INSERT INTO roles (name, description) VALUES
+            ('admin', 'Full access to the site and its users'),
+            ('instructor', 'Creates courses and manages their own'),
+            ('editor', 'Writes and edits blog posts'),
+            ('moderator', 'Removes blog comments')
+        ON CONFLICT (name) DO NOTHING;
+        
+        INSERT INTO role_permissions (role, permission) VALUES
+            ('admin', 'courses:write'),
+            ('admin', 'courses:write_any'),
+            ('admin', 'blog:write'),
+            ('admin', 'comments:moderate'),
+            ('admin', 'users:manage'),
+            ('instructor', 'courses:write'),
+            ('editor', 'blog:write'),
+            ('moderator', 'comments:moderate')
+        ON CONFLICT (role, permission) DO NOTHING;
+        
+        CREATE TABLE IF NOT EXISTS user_roles (
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            role VARCHAR(32) NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+            granted_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            granted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (user_id, role)
+        );
+        
+        -- Personal API keys for scripts; only a hash of the secret is stored
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            name VARCHAR(100) NOT NULL,
+            prefix VARCHAR(16) NOT NULL UNIQUE,
+            secret_hash VARCHAR(64) NOT NULL,
+            scopes TEXT[] NOT NULL DEFAULT '{}',
+            expires_at TIMESTAMP WITH TIME ZONE,
+            last_used_at TIMESTAMP WITH TIME ZONE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            revoked_at TIMESTAMP WITH TIME ZONE
+        );
+        
+        CREATE INDEX IF NOT EXISTS api_keys_user_idx ON api_keys (user_id);
+        
+        -- Device details for each login session (refresh token family)
+        CREATE TABLE IF NOT EXISTS sessions (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            user_agent VARCHAR(512),
+            ip_address VARCHAR(64),
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        );
+        
+        CREATE INDEX IF NOT EXISTS sessions_user_idx ON sessions (user_id);
+        
+        INSERT INTO sessions (id, user_id, created_at, last_seen_at)
+        SELECT family_id, user_id, MIN(created_at), MAX(created_at)
+        FROM refresh_tokens
+        WHERE revoked_at IS NULL
+        GROUP BY family_id, user_id
+        ON CONFLICT (id) DO NOTHING;
+        
+        -- Uploaded files; the owner is cleared when their account is deleted and
+        -- unreferenced uploads are garbage-collected
+        CREATE TABLE IF NOT EXISTS media (
+            id UUID PRIMARY KEY,
+            owner_id UUID REFERENCES users(id) ON DELETE SET NULL,
+            storage_key VARCHAR(255) NOT NULL UNIQUE,
+            thumbnail_key VARCHAR(255) NOT NULL UNIQUE,
+            content_type VARCHAR(100) NOT NULL,
+            size_bytes BIGINT NOT NULL,
+            width INTEGER NOT NULL,
+            height INTEGER NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        );
+        
+        CREATE INDEX IF NOT EXISTS media_owner_idx ON media (owner_id);
+        
+        -- Course checkouts paid through Solana Pay; the enrollment is only
+        -- created once the transfer is found on chain
+        CREATE TABLE IF NOT EXISTS orders (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            course_id UUID NOT NULL REFERENCES courses(id) ON DELETE CASCADE,
+            amount_lamports BIGINT NOT NULL,
+            recipient VARCHAR(64) NOT NULL,
+            reference VARCHAR(64) NOT NULL UNIQUE,
+            status VARCHAR(20) NOT NULL DEFAULT 'pending',
+            signature VARCHAR(128) UNIQUE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            paid_at TIMESTAMP WITH TIME ZONE
+        );
+        
+        CREATE INDEX IF NOT EXISTS orders_user_idx ON orders (user_id, course_id);
+        
+        -- Course prices carry their currency, and a course is free exactly when
+        -- its price is zero. Existing prices are never rewritten: rows where
+        -- price and is_free disagree stop the upgrade until they are fixed by
+        -- hand, and existing rows get their currency in label_course_currencies.
+        ALTER TABLE courses ADD COLUMN IF NOT EXISTS currency VARCHAR(10);
+        DO $$
+        DECLARE
+            conflicting BIGINT;
+        BEGIN
+            SELECT COUNT(*) INTO conflicting FROM courses
+            WHERE price < 0 OR is_free <> (price = 0);
+            IF conflicting > 0 THEN
+                RAISE EXCEPTION '% course(s) have a negative price or one that disagrees with is_free; fix them before upgrading', conflicting;
+            END IF;
+        END $$;
+        ALTER TABLE courses DROP CONSTRAINT IF EXISTS courses_price_matches_is_free;
+        ALTER TABLE courses ADD CONSTRAINT courses_price_matches_is_free
+            CHECK (price >= 0 AND is_free = (price = 0));
+        
+        -- Discount codes, for one course or any paid course when course_id is NULL
+        CREATE TABLE IF NOT EXISTS coupons (
+            id UUID PRIMARY KEY,
+            code VARCHAR(32) NOT NULL UNIQUE,
+            discount_type VARCHAR(10) NOT NULL,
+            percent_off INTEGER,
+            amount_off DECIMAL(10, 2),
+            currency VARCHAR(10),
+            course_id UUID REFERENCES courses(id) ON DELETE CASCADE,
+            max_redemptions INTEGER,
+            max_redemptions_per_user INTEGER,
+            starts_at TIMESTAMP WITH TIME ZONE,
+            expires_at TIMESTAMP WITH TIME ZONE,
+            active BOOLEAN NOT NULL DEFAULT true,
+            created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            CHECK (
+                (discount_type = 'percent' AND percent_off BETWEEN 1 AND 100 AND amount_off IS NULL)
+                OR (discount_type = 'fixed' AND amount_off > 0 AND currency IS NOT NULL AND percent_off IS NULL)
+            )
+        );
+        
+        CREATE TABLE IF NOT EXISTS coupon_redemptions (
+            id UUID PRIMARY KEY,
+            coupon_id UUID NOT NULL REFERENCES coupons(id) ON DELETE CASCADE,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            course_id UUID NOT NULL REFERENCES courses(id) ON DELETE CASCADE,
+            order_id UUID REFERENCES orders(id) ON DELETE SET NULL,
+            discount DECIMAL(10, 2) NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            UNIQUE (coupon_id, user_id, course_id)
+        );
+        
+        -- Orders remember the coupon they were priced with, and enrollments what
+        -- was actually charged for them
+        ALTER TABLE orders ADD COLUMN IF NOT EXISTS coupon_id UUID REFERENCES coupons(id) ON DELETE SET NULL;
+        ALTER TABLE orders ADD COLUMN IF NOT EXISTS discount DECIMAL(10, 2) NOT NULL DEFAULT 0;
+        ALTER TABLE user_enrollments ADD COLUMN IF NOT EXISTS amount_paid DECIMAL(10, 2) NOT NULL DEFAULT 0;
+        ALTER TABLE user_enrollments ADD COLUMN IF NOT EXISTS currency VARCHAR(10) NOT NULL DEFAULT 'SOL';
+        ALTER TABLE user_enrollments ADD COLUMN IF NOT EXISTS coupon_id UUID REFERENCES coupons(id) ON DELETE SET NULL;
+        ALTER TABLE user_enrollments ADD COLUMN IF NOT EXISTS order_id UUID REFERENCES orders(id) ON DELETE SET NULL;
+        
+        -- Enrollments paid for before amounts were recorded
+        UPDATE user_enrollments e SET amount_paid = o.amount_lamports / 1000000000.0, order_id = o.id
+        FROM orders o
+        WHERE e.order_id IS NULL AND o.user_id = e.user_id AND o.course_id = e.course_id AND o.status = 'paid';
     ").await?;
     
+    label_course_currencies(&client).await?;
+    
     Ok(pool)
 }
+
+// Give courses created before prices had a currency one. Free courses can take
+// any; the currency of priced ones has to be named with DEFAULT_COURSE_CURRENCY,
+// since guessing it would silently reprice them.
+async fn label_course_currencies(client: &Client) -> Result<(), Box<dyn std::error::Error>> {
+    client
+        .execute(
+            "UPDATE courses SET currency = $1 WHERE currency IS NULL AND price = 0",
+            &[&Currency::default()],
+        )
+        .await?;
+    
+    let unlabeled: i64 = client
+        .query_one("SELECT COUNT(*) FROM courses WHERE currency IS NULL", &[])
+        .await?
+        .get(0);
+    if unlabeled > 0 {
+        let currency: Currency = match env::var("DEFAULT_COURSE_CURRENCY") {
+            Ok(code) => code.parse()?,
+            Err(_) => {
+                return Err(format!(
+                    "{} priced course(s) have no currency; set DEFAULT_COURSE_CURRENCY to the currency their prices are in",
+                    unlabeled
+                )
+                .into());
+            }
+        };
+        client
+            .execute("UPDATE courses SET currency = $1 WHERE currency IS NULL", &[&currency])
+            .await?;
+    }
+    
+    client
+        .batch_execute("ALTER TABLE courses ALTER COLUMN currency SET NOT NULL")
+        .await?;
+    
+    Ok(())
+}
@@ -0,0 +1,341 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::models::course::{CourseProgress, LessonRef, SectionProgress};
+
+// One row of a course outline with the learner's state for that lesson.
+// Sections without lessons appear once with no lesson.
+#[derive(Debug, Clone)]
+pub struct OutlineEntry {
+    pub section_id: Uuid,
+    pub section_title: String,
+    pub lesson: Option<OutlineLesson>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutlineLesson {
+    pub id: Uuid,
+    pub title: String,
+    pub completed: bool,
+    pub last_accessed: Option<DateTime<Utc>>,
+}
+
+// Progress computed from an outline, before enrollment details are attached
+#[derive(Debug, PartialEq)]
+pub struct ProgressSummary {
+    pub total_lessons: i64,
+    pub completed_lessons: i64,
+    pub percent_complete: i32,
+    pub sections: Vec<SectionProgress>,
+    pub last_accessed_lesson: Option<LessonRef>,
+    pub last_accessed_at: Option<DateTime<Utc>>,
+    pub next_lesson: Option<LessonRef>,
+}
+
+// Sections with their lessons and the progress of user $1; callers add the
+// filter and order
+const OUTLINE_QUERY: &str = "SELECT s.course_id, s.id AS section_id, s.title AS section_title,
+        l.id AS lesson_id, l.title AS lesson_title,
+        COALESCE(p.completed, false) AS completed, p.last_accessed
+    FROM course_sections s
+    LEFT JOIN course_lessons l ON l.section_id = s.id
+    LEFT JOIN user_lesson_progress p ON p.lesson_id = l.id AND p.user_id = $1";
+
+// A learner's progress through a course. Returns None when they aren't enrolled.
+pub async fn load_course_progress<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    course_id: Uuid,
+) -> Result<Option<CourseProgress>, tokio_postgres::Error> {
+    let enrollment = match client
+        .query_opt(
+            "SELECT enrolled_at, completed_at FROM user_enrollments WHERE user_id = $1 AND course_id = $2",
+            &[&user_id, &course_id],
+        )
+        .await?
+    {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let outline = load_outline(client, user_id, course_id).await?;
+    let summary = summarize(&outline);
+
+    Ok(Some(CourseProgress {
+        course_id,
+        enrolled_at: enrollment.get("enrolled_at"),
+        completed_at: enrollment.get("completed_at"),
+        total_lessons: summary.total_lessons,
+        completed_lessons: summary.completed_lessons,
+        percent_complete: summary.percent_complete,
+        sections: summary.sections,
+        last_accessed_lesson: summary.last_accessed_lesson,
+        last_accessed_at: summary.last_accessed_at,
+        next_lesson: summary.next_lesson,
+    }))
+}
+
+// A course's sections and lessons in order, with the learner's progress on each
+pub async fn load_outline<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    course_id: Uuid,
+) -> Result<Vec<OutlineEntry>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            &format!(
+                "{} WHERE s.course_id = $2 ORDER BY s.position, l.position",
+                OUTLINE_QUERY
+            ),
+            &[&user_id, &course_id],
+        )
+        .await?;
+
+    Ok(rows.iter().map(outline_entry_from_row).collect())
+}
+
+// Outlines of every course the learner is enrolled in, keyed by course
+pub async fn load_enrolled_outlines<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<HashMap<Uuid, Vec<OutlineEntry>>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            &format!(
+                "{} JOIN user_enrollments e ON e.course_id = s.course_id AND e.user_id = $1
+                 ORDER BY s.course_id, s.position, l.position",
+                OUTLINE_QUERY
+            ),
+            &[&user_id],
+        )
+        .await?;
+
+    let mut outlines: HashMap<Uuid, Vec<OutlineEntry>> = HashMap::new();
+    for row in &rows {
+        outlines
+            .entry(row.get("course_id"))
+            .or_default()
+            .push(outline_entry_from_row(row));
+    }
+
+    Ok(outlines)
+}
+
+// Stamp the enrollment as completed once every lesson is done, and clear the
+// stamp again if one is un-completed. The first completion time is kept.
+// Returns the enrollment's completion time afterwards.
+pub async fn refresh_course_completion<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    course_id: Uuid,
+) -> Result<Option<DateTime<Utc>>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "UPDATE user_enrollments e SET completed_at = CASE
+                 WHEN t.total > 0 AND t.done = t.total THEN COALESCE(e.completed_at, NOW())
+                 ELSE NULL
+             END
+             FROM (
+                 SELECT COUNT(l.id) AS total, COUNT(l.id) FILTER (WHERE p.completed) AS done
+                 FROM course_sections s
+                 JOIN course_lessons l ON l.section_id = s.id
+                 LEFT JOIN user_lesson_progress p ON p.lesson_id = l.id AND p.user_id = $1
+                 WHERE s.course_id = $2
+             ) t
+             WHERE e.user_id = $1 AND e.course_id = $2
+             RETURNING e.completed_at",
+            &[&user_id, &course_id],
+        )
+        .await?;
+
+    Ok(row.and_then(|row| row.get("completed_at")))
+}
+
+// Re-check completion for every enrollment in a course, after lessons were
+// added or removed. Same rules as refresh_course_completion; only enrollments
+// whose state changes are touched.
+pub async fn refresh_completion_for_course<C: GenericClient>(
+    client: &C,
+    course_id: Uuid,
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            "UPDATE user_enrollments e SET completed_at = CASE
+                 WHEN t.total > 0 AND t.done = t.total THEN COALESCE(e.completed_at, NOW())
+                 ELSE NULL
+             END
+             FROM (
+                 SELECT en.user_id, COUNT(l.id) AS total, COUNT(l.id) FILTER (WHERE p.completed) AS done
+                 FROM user_enrollments en
+                 LEFT JOIN course_sections s ON s.course_id = en.course_id
+                 LEFT JOIN course_lessons l ON l.section_id = s.id
+                 LEFT JOIN user_lesson_progress p ON p.lesson_id = l.id AND p.user_id = en.user_id
+                 WHERE en.course_id = $1
+                 GROUP BY en.user_id
+             ) t
+             WHERE e.course_id = $1 AND e.user_id = t.user_id
+               AND (e.completed_at IS NOT NULL) <> (t.total > 0 AND t.done = t.total)",
+            &[&course_id],
+        )
+        .await
+}
+
+fn outline_entry_from_row(row: &tokio_postgres::Row) -> OutlineEntry {
+    OutlineEntry {
+        section_id: row.get("section_id"),
+        section_title: row.get("section_title"),
+        lesson: row.get::<_, Option<Uuid>>("lesson_id").map(|id| OutlineLesson {
+            id,
+            title: row.get("lesson_title"),
+            completed: row.get("completed"),
+            last_accessed: row.get("last_accessed"),
+        }),
+    }
+}
+
+pub fn summarize(outline: &[OutlineEntry]) -> ProgressSummary {
+    let mut sections: Vec<SectionProgress> = Vec::new();
+    let mut last_accessed: Option<(DateTime<Utc>, LessonRef)> = None;
+    let mut next_lesson = None;
+
+    for entry in outline {
+        if sections.last().map(|section| section.section_id) != Some(entry.section_id) {
+            sections.push(SectionProgress {
+                section_id: entry.section_id,
+                title: entry.section_title.clone(),
+                total_lessons: 0,
+                completed_lessons: 0,
+                percent_complete: 0,
+            });
+        }
+
+        let lesson = match &entry.lesson {
+            Some(lesson) => lesson,
+            None => continue,
+        };
+        let section = sections.last_mut().expect("a section was just pushed");
+        section.total_lessons += 1;
+        if lesson.completed {
+            section.completed_lessons += 1;
+        }
+
+        let lesson_ref = || LessonRef {
+            lesson_id: lesson.id,
+            section_id: entry.section_id,
+            title: lesson.title.clone(),
+        };
+        if !lesson.completed && next_lesson.is_none() {
+            next_lesson = Some(lesson_ref());
+        }
+        if let Some(accessed) = lesson.last_accessed {
+            if last_accessed.as_ref().is_none_or(|(latest, _)| accessed > *latest) {
+                last_accessed = Some((accessed, lesson_ref()));
+            }
+        }
+    }
+
+    for section in &mut sections {
+        section.percent_complete = percent(section.completed_lessons, section.total_lessons);
+    }
+
+    let total_lessons = sections.iter().map(|section| section.total_lessons).sum();
+    let completed_lessons = sections.iter().map(|section| section.completed_lessons).sum();
+    let (last_accessed_at, last_accessed_lesson) = match last_accessed {
+        Some((at, lesson)) => (Some(at), Some(lesson)),
+        None => (None, None),
+    };
+
+    ProgressSummary {
+        total_lessons,
+        completed_lessons,
+        percent_complete: percent(completed_lessons, total_lessons),
+        sections,
+        last_accessed_lesson,
+        last_accessed_at,
+        next_lesson,
+    }
+}
+
+// Whole percent rounded down; nothing to do counts as nothing done
+fn percent(completed: i64, total: i64) -> i32 {
+    if total == 0 {
+        return 0;
+    }
+    (completed * 100 / total) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn lesson(section: (Uuid, &str), title: &str, completed: bool, accessed: Option<DateTime<Utc>>) -> OutlineEntry {
+        OutlineEntry {
+            section_id: section.0,
+            section_title: section.1.to_string(),
+            lesson: Some(OutlineLesson {
+                id: Uuid::new_v4(),
+                title: title.to_string(),
+                completed,
+                last_accessed: accessed,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let now = Utc::now();
+        let intro = (Uuid::new_v4(), "Intro");
+        let advanced = (Uuid::new_v4(), "Advanced");
+        let empty = (Uuid::new_v4(), "Coming soon");
+
+        let outline = vec![
+            lesson(intro, "Welcome", true, Some(now - Duration::days(2))),
+            lesson(intro, "Setup", true, Some(now - Duration::days(1))),
+            lesson(advanced, "Accounts", false, Some(now - Duration::days(3))),
+            lesson(advanced, "Programs", false, None),
+            OutlineEntry {
+                section_id: empty.0,
+                section_title: empty.1.to_string(),
+                lesson: None,
+            },
+        ];
+        let summary = summarize(&outline);
+
+        assert_eq!((summary.total_lessons, summary.completed_lessons), (4, 2));
+        assert_eq!(summary.percent_complete, 50);
+
+        let per_section: Vec<(i64, i64, i32)> = summary
+            .sections
+            .iter()
+            .map(|section| (section.total_lessons, section.completed_lessons, section.percent_complete))
+            .collect();
+        assert_eq!(per_section, vec![(2, 2, 100), (2, 0, 0), (0, 0, 0)]);
+
+        // Resume from the most recently opened lesson, not the furthest one
+        assert_eq!(summary.last_accessed_lesson.unwrap().title, "Setup");
+        assert_eq!(summary.last_accessed_at, Some(now - Duration::days(1)));
+        assert_eq!(summary.next_lesson.unwrap().title, "Accounts");
+    }
+
+    #[test]
+    fn test_percent_rounds_down() {
+        assert_eq!(percent(0, 0), 0);
+        assert_eq!(percent(2, 3), 66);
+        assert_eq!(percent(199, 200), 99);
+        assert_eq!(percent(3, 3), 100);
+    }
+
+    #[test]
+    fn test_finished_course_has_no_next_lesson() {
+        let section = (Uuid::new_v4(), "Only");
+        let summary = summarize(&[lesson(section, "One", true, None)]);
+
+        assert_eq!(summary.percent_complete, 100);
+        assert_eq!(summary.next_lesson, None);
+        assert_eq!(summary.last_accessed_lesson, None);
+        assert!(summarize(&[]).sections.is_empty());
+    }
+}
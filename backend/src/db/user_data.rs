@@ -0,0 +1,292 @@
+use deadpool_postgres::{Client, GenericClient};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use serde_json::{Map, Value};
+use std::fmt;
+use uuid::Uuid;
+
+const MONGO_DATABASE: &str = "hex_the_add_hub";
+
+// Name shown in place of a deleted user's on content that outlives them
+pub const DELETED_USER_NAME: &str = "Deleted user";
+
+// Postgres data included in an export, keyed by section name. Secrets (password
+// and token hashes, TOTP secrets, recovery codes) are deliberately left out.
+const POSTGRES_EXPORT_QUERIES: &[(&str, &str)] = &[
+    (
+        "account",
+        "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at
+         FROM users WHERE id = $1",
+    ),
+    (
+        "wallets",
+        "SELECT wallet_address, linked_at FROM user_wallets WHERE user_id = $1",
+    ),
+    (
+        "linked_identities",
+        "SELECT provider, email, created_at FROM user_identities WHERE user_id = $1",
+    ),
+    ("roles", "SELECT role, granted_at FROM user_roles WHERE user_id = $1"),
+    (
+        "enrollments",
+        "SELECT e.course_id, c.title AS course_title, e.enrolled_at, e.completed_at,
+                e.amount_paid, e.currency, e.order_id
+         FROM user_enrollments e JOIN courses c ON c.id = e.course_id
+         WHERE e.user_id = $1",
+    ),
+    (
+        "orders",
+        "SELECT id, course_id, amount_lamports, discount, reference, status, signature, created_at, paid_at
+         FROM orders WHERE user_id = $1",
+    ),
+    (
+        "coupon_redemptions",
+        "SELECT c.code, r.course_id, r.order_id, r.discount, r.created_at
+         FROM coupon_redemptions r JOIN coupons c ON c.id = r.coupon_id
+         WHERE r.user_id = $1",
+    ),
+    (
+        "lesson_progress",
+        "SELECT lesson_id, completed, last_accessed FROM user_lesson_progress WHERE user_id = $1",
+    ),
+    (
+        "courses_created",
+        "SELECT id, title, created_at FROM courses WHERE created_by = $1",
+    ),
+    (
+        "media",
+        "SELECT id, content_type, size_bytes, width, height, created_at FROM media WHERE owner_id = $1",
+    ),
+    (
+        "sessions",
+        "SELECT id, user_agent, ip_address, created_at, last_seen_at FROM sessions WHERE user_id = $1",
+    ),
+    (
+        "api_keys",
+        "SELECT name, prefix, scopes, expires_at, last_used_at, created_at, revoked_at
+         FROM api_keys WHERE user_id = $1",
+    ),
+    (
+        "security_events",
+        "SELECT event, ip_address, details, created_at FROM audit_log WHERE user_id = $1",
+    ),
+];
+
+// Mongo collections included in an export, with the field that holds the user's id
+const MONGO_EXPORT_COLLECTIONS: &[(&str, &str)] = &[
+    ("user_profiles", "user_id"),
+    ("portfolios", "user_id"),
+    ("blog_posts", "author_id"),
+    ("blog_comments", "user_id"),
+];
+
+#[derive(Debug)]
+pub enum UserDataError {
+    Postgres(tokio_postgres::Error),
+    Mongo(mongodb::error::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for UserDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserDataError::Postgres(e) => write!(f, "Database error: {}", e),
+            UserDataError::Mongo(e) => write!(f, "Database error: {}", e),
+            UserDataError::Json(e) => write!(f, "Failed to encode export: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UserDataError {}
+
+impl From<tokio_postgres::Error> for UserDataError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        UserDataError::Postgres(e)
+    }
+}
+
+impl From<mongodb::error::Error> for UserDataError {
+    fn from(e: mongodb::error::Error) -> Self {
+        UserDataError::Mongo(e)
+    }
+}
+
+impl From<serde_json::Error> for UserDataError {
+    fn from(e: serde_json::Error) -> Self {
+        UserDataError::Json(e)
+    }
+}
+
+// Why an account can't be deleted as things stand
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeletionBlocked {
+    // Courses have enrolled learners, so they are transferred or removed explicitly
+    OwnsCourses(i64),
+}
+
+// Everything stored about a user across Postgres and Mongo, as one JSON document.
+// Returns None when the user doesn't exist.
+pub async fn export_user_data(
+    client: &Client,
+    mongo: &mongodb::Client,
+    user_id: Uuid,
+) -> Result<Option<Value>, UserDataError> {
+    let mut postgres = Map::new();
+    for (section, query) in POSTGRES_EXPORT_QUERIES {
+        postgres.insert(section.to_string(), query_as_json(client, query, user_id).await?);
+    }
+
+    // The account section holds a single row
+    let account = match postgres.remove("account") {
+        Some(Value::Array(mut rows)) if !rows.is_empty() => rows.remove(0),
+        _ => return Ok(None),
+    };
+
+    let db = mongo.database(MONGO_DATABASE);
+    let mut documents = Map::new();
+    for (collection, field) in MONGO_EXPORT_COLLECTIONS {
+        let mut filter = Document::new();
+        filter.insert(*field, user_id.to_string());
+
+        let cursor = db.collection::<Document>(collection).find(filter, None).await?;
+        let found: Vec<Document> = cursor.try_collect().await?;
+
+        documents.insert(
+            collection.to_string(),
+            Value::Array(
+                found
+                    .into_iter()
+                    .map(|document| Bson::Document(document).into_relaxed_extjson())
+                    .collect(),
+            ),
+        );
+    }
+
+    let mut export = Map::new();
+    export.insert("exported_at".to_string(), Value::String(chrono::Utc::now().to_rfc3339()));
+    export.insert("account".to_string(), account);
+    export.extend(postgres);
+    export.insert("content".to_string(), Value::Object(documents));
+
+    Ok(Some(Value::Object(export)))
+}
+
+// Checks that must pass before `delete_user_data` is called
+pub async fn check_deletion_allowed<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Result<(), DeletionBlocked>, UserDataError> {
+    let owned_courses: i64 = client
+        .query_one("SELECT COUNT(*) FROM courses WHERE created_by = $1", &[&user_id])
+        .await?
+        .get(0);
+
+    Ok(deletion_allowed(owned_courses))
+}
+
+fn deletion_allowed(owned_courses: i64) -> Result<(), DeletionBlocked> {
+    if owned_courses > 0 {
+        return Err(DeletionBlocked::OwnsCourses(owned_courses));
+    }
+
+    Ok(())
+}
+
+// Delete a user everywhere. Private content (profile, portfolio) is removed;
+// public contributions (blog posts and comments) stay but are anonymized.
+// Mongo goes first so a failure leaves the Postgres account in place to retry.
+// Returns false when the user doesn't exist.
+pub async fn delete_user_data(
+    client: &mut Client,
+    mongo: &mongodb::Client,
+    user_id: Uuid,
+) -> Result<bool, UserDataError> {
+    let email: String = match client
+        .query_opt("SELECT email FROM users WHERE id = $1", &[&user_id])
+        .await?
+    {
+        Some(row) => row.get("email"),
+        None => return Ok(false),
+    };
+
+    let db = mongo.database(MONGO_DATABASE);
+    let id = user_id.to_string();
+    let anonymous_id = Uuid::nil().to_string();
+
+    db.collection::<Document>("user_profiles")
+        .delete_many(doc! { "user_id": &id }, None)
+        .await?;
+    db.collection::<Document>("portfolios")
+        .delete_many(doc! { "user_id": &id }, None)
+        .await?;
+    db.collection::<Document>("blog_comments")
+        .update_many(
+            doc! { "user_id": &id },
+            doc! { "$set": { "user_id": &anonymous_id, "user_name": DELETED_USER_NAME } },
+            None,
+        )
+        .await?;
+    db.collection::<Document>("blog_posts")
+        .update_many(
+            doc! { "author_id": &id },
+            doc! { "$set": { "author_id": &anonymous_id, "author_name": DELETED_USER_NAME } },
+            None,
+        )
+        .await?;
+
+    let transaction = client.transaction().await?;
+
+    // Audit entries are kept for security, minus anything identifying
+    transaction
+        .execute(
+            "UPDATE audit_log SET details = details - 'email', ip_address = NULL WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+    transaction
+        .execute("DELETE FROM login_throttles WHERE email = LOWER($1)", &[&email])
+        .await?;
+
+    // Everything else keyed on the user cascades
+    let deleted = transaction
+        .execute("DELETE FROM users WHERE id = $1", &[&user_id])
+        .await?;
+
+    transaction.commit().await?;
+    Ok(deleted > 0)
+}
+
+// Run a query and return its rows as a JSON array
+async fn query_as_json<C: GenericClient>(
+    client: &C,
+    query: &str,
+    user_id: Uuid,
+) -> Result<Value, UserDataError> {
+    let wrapped = format!(
+        "SELECT COALESCE(json_agg(t), '[]'::json)::text FROM ({}) t",
+        query
+    );
+    let text: String = client.query_one(&wrapped, &[&user_id]).await?.get(0);
+
+    Ok(serde_json::from_str(&text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_leaves_out_secrets() {
+        for (_, query) in POSTGRES_EXPORT_QUERIES {
+            assert!(!query.contains("password_hash"));
+            assert!(!query.contains("secret"));
+            assert!(!query.contains("token_hash"));
+        }
+    }
+
+    #[test]
+    fn test_course_owners_cannot_be_deleted() {
+        assert_eq!(deletion_allowed(0), Ok(()));
+        assert_eq!(deletion_allowed(2), Err(DeletionBlocked::OwnsCourses(2)));
+    }
+}
@@ -0,0 +1,140 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use log::info;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+
+// Outgoing plain-text email
+#[derive(Debug, Clone)]
+pub struct MailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    Io(std::io::Error),
+}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailError::Io(e) => write!(f, "Failed to send mail: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+impl From<std::io::Error> for MailError {
+    fn from(e: std::io::Error) -> Self {
+        MailError::Io(e)
+    }
+}
+
+// Delivers transactional email. Implementations must not block the executor.
+#[async_trait]
+pub trait MailSender: Send + Sync {
+    async fn send(&self, message: &MailMessage) -> Result<(), MailError>;
+}
+
+// Writes messages to the application log; for local development
+pub struct LogMailSender {
+    from: String,
+}
+
+impl LogMailSender {
+    pub fn new(from: String) -> Self {
+        LogMailSender { from }
+    }
+}
+
+#[async_trait]
+impl MailSender for LogMailSender {
+    async fn send(&self, message: &MailMessage) -> Result<(), MailError> {
+        info!("Mail from {}:\n{}", self.from, render(&self.from, message));
+        Ok(())
+    }
+}
+
+// Drops each message as an .eml file into a directory; for local development and tests
+pub struct FileMailSender {
+    from: String,
+    dir: PathBuf,
+}
+
+impl FileMailSender {
+    pub fn new(from: String, dir: impl Into<PathBuf>) -> Self {
+        FileMailSender {
+            from,
+            dir: dir.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl MailSender for FileMailSender {
+    async fn send(&self, message: &MailMessage) -> Result<(), MailError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let file_name = format!("{}-{}.eml", Utc::now().format("%Y%m%dT%H%M%S"), Uuid::new_v4());
+        tokio::fs::write(self.dir.join(file_name), render(&self.from, message)).await?;
+        Ok(())
+    }
+}
+
+// Pick the mail sender configured by MAIL_OUTBOX_DIR: a file outbox when set,
+// otherwise the log
+pub fn mailer_from_config(config: &AppConfig) -> Arc<dyn MailSender> {
+    match &config.mail_outbox_dir {
+        Some(dir) => Arc::new(FileMailSender::new(config.mail_from.clone(), dir)),
+        None => Arc::new(LogMailSender::new(config.mail_from.clone())),
+    }
+}
+
+// Render a message in RFC 5322 form
+fn render(from: &str, message: &MailMessage) -> String {
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+        from,
+        message.to,
+        message.subject,
+        Utc::now().to_rfc2822(),
+        message.body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_sender_writes_message() {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+        let sender = FileMailSender::new("noreply@example.com".to_string(), &dir);
+
+        sender
+            .send(&MailMessage {
+                to: "learner@example.com".to_string(),
+                subject: "Hello".to_string(),
+                body: "Welcome aboard".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+
+        let contents = std::fs::read_to_string(entries[0].as_ref().unwrap().path()).unwrap();
+        assert!(contents.starts_with("From: noreply@example.com\r\nTo: learner@example.com\r\n"));
+        assert!(contents.contains("Subject: Hello\r\n"));
+        assert!(contents.contains("\r\n\r\nWelcome aboard"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
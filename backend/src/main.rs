@@ -1,18 +1,32 @@
+mod config;
 mod db;
+mod mail;
+mod media;
 mod middleware;
 mod models;
 mod routes;
 mod search;
+mod security;
+mod web3;
 
 use actix_cors::Cors;
 use actix_web::{http, middleware::Logger, web, App, HttpServer};
 use dotenv::dotenv;
 use log::info;
 use std::env;
+use std::sync::Arc;
 
+use crate::config::AppConfig;
 use crate::db::{mongodb::init_mongodb, postgres::init_postgres};
-use crate::routes::{admin, auth, blog, courses, portfolio};
+use crate::mail::{mailer_from_config, MailSender};
+use crate::media::{media_store_from_config, MediaStore};
+use crate::middleware::rate_limit::{InMemoryRateLimitStore, RateLimitStore, RateLimiter};
+use crate::routes::{
+    account, admin, auth, blog, coupons, courses, curriculum, mfa, orders, portfolio, profiles,
+    uploads,
+};
 use crate::search::{SearchState, initialize_search_indices, search_courses, search_portfolio, search_blog, search_all};
+use crate::web3::rpc::{solana_rpc_from_config, SolanaRpc};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -25,9 +39,17 @@ async fn main() -> std::io::Result<()> {
     let mongo_client = init_mongodb().await.expect("Failed to initialize MongoDB");
 
     // Create app data
+    let config = AppConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let http_client = reqwest::Client::new();
     let app_data = web::Data::new(AppState {
         pg_pool: pg_pool.clone(),
         mongo_client: mongo_client.clone(),
+        mailer: mailer_from_config(&config),
+        media_store: media_store_from_config(&config, http_client.clone()),
+        solana_rpc: solana_rpc_from_config(&config),
+        http_client,
+        config,
     });
     
     // Initialize search state
@@ -38,6 +60,9 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to initialize search indices");
 
+    // Shared by all workers so limits hold across the whole process
+    let rate_limit_store: Arc<dyn RateLimitStore> = Arc::new(InMemoryRateLimitStore::new());
+    
     info!("Starting HTTP server at http://0.0.0.0:8000");
 
     // Start HTTP server
@@ -50,19 +75,68 @@ async fn main() -> std::io::Result<()> {
             .max_age(3600);
 
         App::new()
+            .wrap(RateLimiter::new(rate_limit_store.clone()))
             .wrap(cors)
             .wrap(Logger::default())
             .app_data(app_data.clone())
             .app_data(search_state.clone())
+            // Public keys for verifying access tokens
+            .route("/.well-known/jwks.json", web::get().to(auth::jwks))
             // Auth routes
             .service(
                 web::scope("/api/auth")
                     .route("/register", web::post().to(auth::register))
                     .route("/login", web::post().to(auth::login))
+                    .route("/web3/challenge", web::post().to(auth::web3_challenge))
                     .route("/web3/login", web::post().to(auth::web3_login))
+                    .route("/oauth/{provider}/start", web::get().to(auth::oauth_start))
+                    .route("/oauth/{provider}/callback", web::post().to(auth::oauth_callback))
+                    .route("/refresh", web::post().to(auth::refresh))
                     .route("/logout", web::post().to(auth::logout))
+                    .route("/password/forgot", web::post().to(auth::forgot_password))
+                    .route("/password/reset", web::post().to(auth::reset_password))
+                    .route("/verify-email", web::post().to(auth::verify_email))
+                    .route("/verify-email/resend", web::post().to(auth::resend_verification_email))
+                    .route("/mfa/verify", web::post().to(mfa::verify))
+                    .route("/mfa/totp/setup", web::post().to(mfa::setup_totp))
+                    .route("/mfa/totp/confirm", web::post().to(mfa::confirm_totp))
+                    .route("/mfa/totp/disable", web::post().to(mfa::disable))
+                    .route("/mfa/recovery-codes", web::post().to(mfa::regenerate_recovery_codes))
+                    .route("/password", web::put().to(account::set_password))
+                    .route("/wallets", web::get().to(account::list_wallets))
+                    .route("/wallets", web::post().to(account::link_wallet))
+                    .route("/wallets/{wallet_address}", web::delete().to(account::unlink_wallet))
+                    .route("/api-keys", web::get().to(account::list_api_keys))
+                    .route("/api-keys", web::post().to(account::create_api_key))
+                    .route("/api-keys/{id}", web::delete().to(account::revoke_api_key))
+                    .route("/sessions", web::get().to(account::list_sessions))
+                    .route("/sessions", web::delete().to(account::revoke_other_user_sessions))
+                    .route("/sessions/{id}", web::delete().to(account::revoke_user_session))
+                    .route("/account/export", web::get().to(account::export_account))
+                    .route("/account", web::delete().to(account::delete_account))
                     .route("/me", web::get().to(auth::get_current_user)),
             )
+            // Learner dashboard
+            .service(
+                web::scope("/api/me")
+                    .route("/courses", web::get().to(courses::get_my_courses)),
+            )
+            // User profile routes
+            .service(
+                web::scope("/api/users")
+                    .route("/me/profile", web::get().to(profiles::get_my_profile))
+                    .route("/me/profile", web::put().to(profiles::update_my_profile))
+                    .route("/{id}/profile", web::get().to(profiles::get_profile)),
+            )
+            // Media routes
+            .service(
+                web::scope("/api/media")
+                    .route("", web::get().to(uploads::list_media))
+                    .route("", web::post().to(uploads::upload_media))
+                    .route("/{id}", web::delete().to(uploads::delete_media)),
+            )
+            // Uploaded files
+            .route("/media/{key}", web::get().to(uploads::serve_media))
             // Portfolio routes
             .service(
                 web::scope("/api/portfolio")
@@ -81,7 +155,24 @@ async fn main() -> std::io::Result<()> {
                     .route("/{id}", web::put().to(courses::update_course))
                     .route("/{id}", web::delete().to(courses::delete_course))
                     .route("/enroll/{id}", web::post().to(courses::enroll_in_course))
-                    .route("/progress/{id}", web::post().to(courses::update_progress)),
+                    .route("/{id}/checkout", web::post().to(orders::checkout))
+                    .route("/progress/{id}", web::post().to(courses::update_progress))
+                    .route("/{id}/progress", web::get().to(courses::get_course_progress))
+                    .route("/{id}/sections", web::post().to(curriculum::create_section))
+                    .route("/{id}/sections/order", web::put().to(curriculum::reorder_sections))
+                    .route("/sections/{id}", web::put().to(curriculum::update_section))
+                    .route("/sections/{id}", web::delete().to(curriculum::delete_section))
+                    .route("/sections/{id}/lessons", web::post().to(curriculum::create_lesson))
+                    .route("/sections/{id}/lessons/order", web::put().to(curriculum::reorder_lessons))
+                    .route("/lessons/{id}", web::put().to(curriculum::update_lesson))
+                    .route("/lessons/{id}", web::delete().to(curriculum::delete_lesson)),
+            )
+            // Order routes
+            .service(
+                web::scope("/api/orders")
+                    .route("", web::get().to(orders::list_orders))
+                    .route("/{id}", web::get().to(orders::get_order))
+                    .route("/{id}/verify", web::post().to(orders::verify_order)),
             )
             // Blog routes
             .service(
@@ -92,7 +183,8 @@ async fn main() -> std::io::Result<()> {
                     .route("/{id}", web::put().to(blog::update_post))
                     .route("/{id}", web::delete().to(blog::delete_post))
                     .route("/{id}/comments", web::get().to(blog::get_comments))
-                    .route("/{id}/comments", web::post().to(blog::add_comment)),
+                    .route("/{id}/comments", web::post().to(blog::add_comment))
+                    .route("/{id}/comments/{comment_id}", web::delete().to(blog::delete_comment)),
             )
             // Admin routes
             .service(
@@ -101,7 +193,20 @@ async fn main() -> std::io::Result<()> {
                     .route("/users/{id}", web::get().to(admin::get_user_by_id))
                     .route("/users/{id}", web::put().to(admin::update_user))
                     .route("/users/{id}", web::delete().to(admin::delete_user))
-                    .route("/stats", web::get().to(admin::get_stats)),
+                    .route("/users/{id}/logout", web::post().to(admin::force_logout_user))
+                    .route("/users/{id}/roles", web::get().to(admin::get_user_roles))
+                    .route("/users/{id}/roles/{role}", web::put().to(admin::grant_user_role))
+                    .route("/users/{id}/roles/{role}", web::delete().to(admin::revoke_user_role))
+                    .route("/roles", web::get().to(admin::get_roles))
+                    .route("/stats", web::get().to(admin::get_stats))
+                    .route("/lockouts", web::get().to(admin::get_lockouts))
+                    .route("/lockouts/clear", web::post().to(admin::clear_lockout))
+                    .route("/media/gc", web::post().to(admin::collect_media_garbage))
+                    .route("/coupons", web::get().to(coupons::list_coupons))
+                    .route("/coupons", web::post().to(coupons::create_coupon))
+                    .route("/coupons/{id}", web::get().to(coupons::get_coupon))
+                    .route("/coupons/{id}", web::put().to(coupons::update_coupon))
+                    .route("/coupons/{id}", web::delete().to(coupons::delete_coupon)),
             )
             // Search routes
             .service(
@@ -120,4 +225,9 @@ async fn main() -> std::io::Result<()> {
 pub struct AppState {
     pg_pool: deadpool_postgres::Pool,
     mongo_client: mongodb::Client,
+    config: AppConfig,
+    mailer: Arc<dyn MailSender>,
+    media_store: Arc<dyn MediaStore>,
+    solana_rpc: Arc<dyn SolanaRpc>,
+    http_client: reqwest::Client,
 }
@@ -0,0 +1,151 @@
+use deadpool_postgres::GenericClient;
+use futures::TryStreamExt;
+use log::warn;
+use mongodb::bson::{Bson, Document};
+use mongodb::options::FindOptions;
+use serde::Serialize;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::media::{media_ids_in_text, MediaStore};
+
+// Mongo fields that may link to uploads, by collection: URL fields and free
+// text that can embed them. A field holds either one string or an array of them.
+const MEDIA_REFERENCES: &[(&str, &str)] = &[
+    ("user_profiles", "profile_picture"),
+    ("portfolios", "image_urls"),
+    ("portfolios", "description"),
+    ("blog_posts", "featured_image"),
+    ("blog_posts", "content"),
+];
+
+// Postgres columns that may link to uploads, by table
+const COURSE_MEDIA_REFERENCES: &[(&str, &str)] = &[
+    ("courses", "description"),
+    ("course_sections", "description"),
+    ("course_lessons", "content"),
+    ("course_lessons", "video_url"),
+];
+
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    // Uploads old enough to be considered
+    pub scanned: u64,
+    pub deleted: u64,
+    // Uploads whose files could not be removed; they are retried on the next run
+    pub failed: u64,
+}
+
+// Ids of every upload some profile, portfolio item or blog post points at
+pub async fn referenced_media_ids(
+    mongo: &mongodb::Client,
+    config: &AppConfig,
+) -> Result<HashSet<Uuid>, mongodb::error::Error> {
+    let db = mongo.database("hex_the_add_hub");
+    let mut ids = HashSet::new();
+
+    for (collection, field) in MEDIA_REFERENCES {
+        let mut projection = Document::new();
+        projection.insert(*field, 1);
+        let options = FindOptions::builder().projection(projection).build();
+
+        let mut cursor = db.collection::<Document>(collection).find(None, options).await?;
+        while let Some(document) = cursor.try_next().await? {
+            let values = match document.get(*field) {
+                Some(Bson::String(value)) => vec![value.as_str()],
+                Some(Bson::Array(values)) => values.iter().filter_map(Bson::as_str).collect(),
+                _ => Vec::new(),
+            };
+            for value in values {
+                ids.extend(media_ids_in_text(config, value));
+            }
+        }
+    }
+
+    Ok(ids)
+}
+
+// Ids of every upload some course, section or lesson links to
+pub async fn referenced_course_media_ids<C: GenericClient>(
+    client: &C,
+    config: &AppConfig,
+) -> Result<HashSet<Uuid>, tokio_postgres::Error> {
+    let prefix = format!("{}/", config.media_public_url.trim_end_matches('/'));
+    let mut ids = HashSet::new();
+
+    for (table, column) in COURSE_MEDIA_REFERENCES {
+        let rows = client
+            .query(
+                &format!("SELECT {} FROM {} WHERE strpos({}, $1) > 0", column, table, column),
+                &[&prefix],
+            )
+            .await?;
+        for row in rows {
+            ids.extend(media_ids_in_text(config, row.get(0)));
+        }
+    }
+
+    Ok(ids)
+}
+
+// Remove uploads that nothing references any more. Fresh uploads get a grace
+// period so a file isn't collected between its upload and the save that uses it.
+pub async fn collect_garbage<C: GenericClient>(
+    client: &C,
+    mongo: &mongodb::Client,
+    store: &dyn MediaStore,
+    config: &AppConfig,
+) -> Result<GcReport, String> {
+    let mut referenced = referenced_media_ids(mongo, config)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    referenced.extend(
+        referenced_course_media_ids(client, config)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?,
+    );
+
+    let rows = client
+        .query(
+            "SELECT id, storage_key, thumbnail_key FROM media
+             WHERE created_at < NOW() - make_interval(secs => $1)",
+            &[&(config.media_gc_grace_secs as f64)],
+        )
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let mut report = GcReport {
+        scanned: rows.len() as u64,
+        ..GcReport::default()
+    };
+
+    for row in rows {
+        let id: Uuid = row.get("id");
+        if referenced.contains(&id) {
+            continue;
+        }
+
+        let storage_key: String = row.get("storage_key");
+        let thumbnail_key: String = row.get("thumbnail_key");
+
+        // Files go first; the row is only dropped once nothing is left behind
+        let removed = match store.delete(&storage_key).await {
+            Ok(()) => store.delete(&thumbnail_key).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = removed {
+            warn!("Failed to remove media {}: {}", id, e);
+            report.failed += 1;
+            continue;
+        }
+
+        client
+            .execute("DELETE FROM media WHERE id = $1", &[&id])
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+        report.deleted += 1;
+    }
+
+    Ok(report)
+}
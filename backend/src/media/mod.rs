@@ -0,0 +1,360 @@
+pub mod gc;
+pub mod s3;
+
+use async_trait::async_trait;
+use image::io::{Limits, Reader as ImageReader};
+use image::{ImageFormat, ImageOutputFormat};
+use std::fmt;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::media::s3::S3MediaStore;
+
+// Longest side of a generated thumbnail, in pixels
+pub const THUMBNAIL_SIZE: u32 = 320;
+
+// Refuse to decode anything larger than this on either side, so a tiny
+// compressed file can't expand into gigabytes of pixels
+const MAX_IMAGE_DIMENSION: u32 = 8000;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    Backend(String),
+    InvalidKey(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "Storage error: {}", e),
+            StorageError::Backend(e) => write!(f, "Storage error: {}", e),
+            StorageError::InvalidKey(key) => write!(f, "Invalid storage key: {}", key),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+// Where uploaded files live. Keys are flat names generated by the server
+// (see `storage_key`), never user input.
+#[async_trait]
+pub trait MediaStore: Send + Sync {
+    async fn put(&self, key: &str, content_type: &str, bytes: Vec<u8>) -> Result<(), StorageError>;
+    // None when nothing is stored under the key
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError>;
+    // Deleting a missing key succeeds
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+// Keeps files in a directory on local disk
+pub struct LocalDiskStore {
+    root: PathBuf,
+}
+
+impl LocalDiskStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalDiskStore { root: root.into() }
+    }
+
+    fn path_for(&self, key: &str) -> Result<PathBuf, StorageError> {
+        if !is_valid_key(key) {
+            return Err(StorageError::InvalidKey(key.to_string()));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl MediaStore for LocalDiskStore {
+    async fn put(&self, key: &str, _content_type: &str, bytes: Vec<u8>) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        tokio::fs::create_dir_all(&self.root).await?;
+        tokio::fs::write(path, bytes).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match tokio::fs::read(self.path_for(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.path_for(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// Pick the store configured by MEDIA_S3_BUCKET: an S3-compatible bucket when
+// set, otherwise the MEDIA_DIR directory
+pub fn media_store_from_config(config: &AppConfig, http: reqwest::Client) -> Arc<dyn MediaStore> {
+    match &config.media_s3 {
+        Some(s3) => Arc::new(S3MediaStore::new(s3.clone(), http)),
+        None => Arc::new(LocalDiskStore::new(&config.media_dir)),
+    }
+}
+
+// Image types accepted for upload, identified from the file contents rather
+// than the client's Content-Type or file name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl MediaKind {
+    pub fn sniff(bytes: &[u8]) -> Option<MediaKind> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(MediaKind::Png)
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(MediaKind::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(MediaKind::Gif)
+        } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(MediaKind::Webp)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Option<MediaKind> {
+        match content_type {
+            "image/png" => Some(MediaKind::Png),
+            "image/jpeg" => Some(MediaKind::Jpeg),
+            "image/gif" => Some(MediaKind::Gif),
+            "image/webp" => Some(MediaKind::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            MediaKind::Png => "image/png",
+            MediaKind::Jpeg => "image/jpeg",
+            MediaKind::Gif => "image/gif",
+            MediaKind::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            MediaKind::Png => "png",
+            MediaKind::Jpeg => "jpg",
+            MediaKind::Gif => "gif",
+            MediaKind::Webp => "webp",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            MediaKind::Png => ImageFormat::Png,
+            MediaKind::Jpeg => ImageFormat::Jpeg,
+            MediaKind::Gif => ImageFormat::Gif,
+            MediaKind::Webp => ImageFormat::WebP,
+        }
+    }
+
+    // Photos stay JPEG; everything else may have transparency, so PNG
+    pub fn thumbnail_kind(self) -> MediaKind {
+        match self {
+            MediaKind::Jpeg => MediaKind::Jpeg,
+            _ => MediaKind::Png,
+        }
+    }
+}
+
+// A decoded upload: its dimensions and an encoded thumbnail
+#[derive(Debug)]
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    pub thumbnail: Vec<u8>,
+}
+
+// Decode an image and render a thumbnail no larger than THUMBNAIL_SIZE on either
+// side. CPU-bound; call it from `web::block`.
+pub fn process_image(bytes: &[u8], kind: MediaKind) -> Result<ProcessedImage, String> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), kind.image_format());
+    reader.limits(limits);
+    let image = reader
+        .decode()
+        .map_err(|e| format!("Could not read image: {}", e))?;
+
+    let format = match kind.thumbnail_kind() {
+        MediaKind::Jpeg => ImageOutputFormat::Jpeg(85),
+        _ => ImageOutputFormat::Png,
+    };
+    let mut thumbnail = Vec::new();
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut Cursor::new(&mut thumbnail), format)
+        .map_err(|e| format!("Could not create thumbnail: {}", e))?;
+
+    Ok(ProcessedImage {
+        width: image.width(),
+        height: image.height(),
+        thumbnail,
+    })
+}
+
+// Name an upload is stored under
+pub fn storage_key(id: Uuid, kind: MediaKind) -> String {
+    format!("{}.{}", id, kind.extension())
+}
+
+// Name an upload's thumbnail is stored under
+pub fn thumbnail_key(id: Uuid, kind: MediaKind) -> String {
+    format!("{}_thumb.{}", id, kind.thumbnail_kind().extension())
+}
+
+// Public URL a stored file is served from
+pub fn media_url(config: &AppConfig, key: &str) -> String {
+    format!("{}/{}", config.media_public_url.trim_end_matches('/'), key)
+}
+
+// The upload a URL points at, when it is one of ours (either the file itself
+// or its thumbnail)
+pub fn media_id_from_url(config: &AppConfig, url: &str) -> Option<Uuid> {
+    let prefix = format!("{}/", config.media_public_url.trim_end_matches('/'));
+    let key = url.strip_prefix(&prefix)?;
+    let key = key.split(['?', '#']).next()?;
+
+    let (stem, _extension) = key.split_once('.')?;
+    let stem = stem.strip_suffix("_thumb").unwrap_or(stem);
+    Uuid::parse_str(stem).ok()
+}
+
+// Every upload a piece of text links to, e.g. images embedded in markdown or HTML
+pub fn media_ids_in_text(config: &AppConfig, text: &str) -> Vec<Uuid> {
+    let prefix = format!("{}/", config.media_public_url.trim_end_matches('/'));
+
+    text.match_indices(&prefix)
+        .filter_map(|(start, _)| {
+            let url = &text[start..];
+            let end = url[prefix.len()..]
+                .find(|c: char| !is_key_char(c))
+                .map_or(url.len(), |len| prefix.len() + len);
+            media_id_from_url(config, &url[..end])
+        })
+        .collect()
+}
+
+// Keys are flat file names: no separators, no leading dot
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.starts_with('.') && key.chars().all(is_key_char)
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageBuffer, Rgba};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(width, height, Rgba([10, 20, 30, 255])));
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_sniff_ignores_claimed_type() {
+        assert_eq!(MediaKind::sniff(&png(1, 1)), Some(MediaKind::Png));
+        assert_eq!(MediaKind::sniff(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00]), Some(MediaKind::Jpeg));
+        assert_eq!(MediaKind::sniff(b"GIF89a\x01\x00"), Some(MediaKind::Gif));
+        assert_eq!(MediaKind::sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some(MediaKind::Webp));
+
+        assert_eq!(MediaKind::sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), None);
+        assert_eq!(MediaKind::sniff(b"<html><body>"), None);
+        assert_eq!(MediaKind::sniff(b"RIFF"), None);
+        assert_eq!(MediaKind::sniff(&[]), None);
+    }
+
+    #[test]
+    fn test_thumbnail_fits_bounds() {
+        let processed = process_image(&png(1000, 500), MediaKind::Png).unwrap();
+        assert_eq!((processed.width, processed.height), (1000, 500));
+
+        let thumbnail = image::load_from_memory(&processed.thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+
+        // Garbage behind valid magic bytes is rejected
+        assert!(process_image(b"\x89PNG\r\n\x1a\nnot really", MediaKind::Png).is_err());
+    }
+
+    #[test]
+    fn test_media_id_from_url() {
+        let config = AppConfig::for_tests();
+        let id = Uuid::new_v4();
+
+        let url = media_url(&config, &storage_key(id, MediaKind::Png));
+        assert_eq!(media_id_from_url(&config, &url), Some(id));
+
+        let thumbnail = media_url(&config, &thumbnail_key(id, MediaKind::Jpeg));
+        assert_eq!(media_id_from_url(&config, &thumbnail), Some(id));
+        assert_eq!(media_id_from_url(&config, &format!("{}?v=2", url)), Some(id));
+
+        // Somebody else's URL that happens to contain an id is not ours
+        assert_eq!(media_id_from_url(&config, &format!("https://example.com/{}.png", id)), None);
+        assert_eq!(media_id_from_url(&config, "/media/not-an-id.png"), None);
+    }
+
+    #[test]
+    fn test_media_ids_in_text() {
+        let config = AppConfig::for_tests();
+        let (image, thumbnail) = (Uuid::new_v4(), Uuid::new_v4());
+        let text = format!(
+            "Intro ![diagram]({}) and <img src=\"{}\"> but not https://example.com/{}.png",
+            media_url(&config, &storage_key(image, MediaKind::Png)),
+            media_url(&config, &thumbnail_key(thumbnail, MediaKind::Jpeg)),
+            Uuid::new_v4(),
+        );
+
+        assert_eq!(media_ids_in_text(&config, &text), vec![image, thumbnail]);
+        assert!(media_ids_in_text(&config, "no links here").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_local_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("media-{}", Uuid::new_v4()));
+        let store = LocalDiskStore::new(&dir);
+
+        store.put("a.png", "image/png", vec![1, 2, 3]).await.unwrap();
+        assert_eq!(store.get("a.png").await.unwrap(), Some(vec![1, 2, 3]));
+
+        store.delete("a.png").await.unwrap();
+        assert_eq!(store.get("a.png").await.unwrap(), None);
+        store.delete("a.png").await.unwrap();
+
+        assert!(store.get("../etc/passwd").await.is_err());
+        assert!(store.put(".hidden", "image/png", Vec::new()).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
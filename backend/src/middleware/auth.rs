@@ -1,24 +1,492 @@
 use actix_web::{
-    dev::ServiceRequest,
+    dev::{Payload, ServiceRequest},
     error::Error,
-    web,
-    HttpMessage,
+    http::{header, StatusCode},
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
 };
 use actix_web_httpauth::extractors::{
     bearer::{BearerAuth, Config},
     AuthenticationError,
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use chrono::Utc;
+use deadpool_postgres::Client;
+use futures::future::LocalBoxFuture;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::fmt;
+use std::marker::PhantomData;
+use tokio_postgres::Row;
+use uuid::Uuid;
 
+use crate::config::AppConfig;
+use crate::models::user::User;
+use crate::security::api_keys::{effective_permissions, verify_api_key, API_KEY_PREFIX};
+use crate::security::keys::KeyRing;
+use crate::security::refresh::touch_session;
+use crate::security::roles::Permission;
 use crate::AppState;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    pub iat: usize,
     pub role: String,
+    // Session (refresh token family) the token was issued for
+    pub sid: String,
+    // Must match `users.token_version`; bumped when privileges or credentials change
+    pub ver: i32,
+}
+
+// Role carried in the token's `role` claim
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    fn from_claim(role: &str) -> Result<Self, AuthError> {
+        match role {
+            "user" => Ok(Role::User),
+            "admin" => Ok(Role::Admin),
+            _ => Err(AuthError::InvalidToken),
+        }
+    }
+}
+
+// User identified by a valid Bearer token
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub session_id: Uuid,
+    pub email_verified: bool,
+    pub mfa_enabled: bool,
+    // Granted through roles; admins implicitly hold every permission
+    pub permissions: Vec<Permission>,
+    // Set when the request authenticated with a personal API key instead of a JWT
+    pub api_key_id: Option<Uuid>,
+}
+
+impl AuthenticatedUser {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.role == Role::Admin || self.permissions.contains(&permission)
+    }
+
+    // Credential and key management needs a signed-in session, so a leaked
+    // API key can't be used to take over the account
+    pub fn require_session(&self) -> Result<(), AuthError> {
+        match self.api_key_id {
+            Some(_) => Err(AuthError::SessionRequired),
+            None => Ok(()),
+        }
+    }
+}
+
+// Authenticated user whose token carries the admin role and who has two-factor
+// authentication enabled, when the `require_admin_mfa` policy is on
+#[derive(Debug, Clone)]
+pub struct AdminUser {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub session_id: Uuid,
+}
+
+// Authenticated user who has verified their email, when the
+// `require_verified_email` policy is on
+#[derive(Debug, Clone)]
+pub struct VerifiedUser {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub session_id: Uuid,
+}
+
+// Marker for the permission an `Authorized<P>` extractor demands
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+#[derive(Debug, Clone)]
+pub struct CanManageCourses;
+
+#[derive(Debug, Clone)]
+pub struct CanWriteBlog;
+
+#[derive(Debug, Clone)]
+pub struct CanModerateComments;
+
+impl RequiredPermission for CanManageCourses {
+    const PERMISSION: Permission = Permission::ManageOwnCourses;
+}
+
+impl RequiredPermission for CanWriteBlog {
+    const PERMISSION: Permission = Permission::WriteBlog;
+}
+
+impl RequiredPermission for CanModerateComments {
+    const PERMISSION: Permission = Permission::ModerateComments;
+}
+
+// Authenticated user holding the permission named by `P`, e.g.
+// `Authorized<CanWriteBlog>`. Admins pass subject to the admin MFA policy.
+#[derive(Debug, Clone)]
+pub struct Authorized<P> {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub session_id: Uuid,
+    permissions: Vec<Permission>,
+    _permission: PhantomData<P>,
+}
+
+impl<P> Authorized<P> {
+    // For checks beyond the one the extractor made, such as managing other users' courses
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.role == Role::Admin || self.permissions.contains(&permission)
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingToken,
+    InvalidToken,
+    Forbidden,
+    MissingPermission(Permission),
+    EmailNotVerified,
+    MfaRequired,
+    SessionRequired,
+    Misconfigured,
+    Database(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::MissingToken => write!(f, "Missing bearer token"),
+            AuthError::InvalidToken => write!(f, "Invalid or expired token"),
+            AuthError::Forbidden => write!(f, "Admin access required"),
+            AuthError::MissingPermission(permission) => {
+                write!(f, "Missing permission: {}", permission.as_str())
+            }
+            AuthError::EmailNotVerified => write!(f, "Please verify your email address first"),
+            AuthError::MfaRequired => {
+                write!(f, "Two-factor authentication must be enabled for admin accounts")
+            }
+            AuthError::SessionRequired => write!(f, "API keys cannot be used for this action"),
+            AuthError::Misconfigured => write!(f, "Authentication is not configured"),
+            AuthError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::MissingToken | AuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden
+            | AuthError::MissingPermission(_)
+            | AuthError::EmailNotVerified
+            | AuthError::MfaRequired
+            | AuthError::SessionRequired => StatusCode::FORBIDDEN,
+            AuthError::Misconfigured | AuthError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
+
+impl FromRequest for AdminUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let user = authenticate(&req).await?;
+            let require_admin_mfa = req
+                .app_data::<web::Data<AppState>>()
+                .map(|state| state.config.require_admin_mfa)
+                .ok_or(AuthError::Misconfigured)?;
+            check_admin_mfa(&user, require_admin_mfa)?;
+            AdminUser::try_from(user)
+        })
+    }
+}
+
+impl<P: RequiredPermission + 'static> FromRequest for Authorized<P> {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let user = authenticate(&req).await?;
+            let require_admin_mfa = req
+                .app_data::<web::Data<AppState>>()
+                .map(|state| state.config.require_admin_mfa)
+                .ok_or(AuthError::Misconfigured)?;
+            check_admin_mfa(&user, require_admin_mfa)?;
+            Authorized::check(user)
+        })
+    }
+}
+
+impl FromRequest for VerifiedUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let user = authenticate(&req).await?;
+            let require_verified_email = req
+                .app_data::<web::Data<AppState>>()
+                .map(|state| state.config.require_verified_email)
+                .ok_or(AuthError::Misconfigured)?;
+            VerifiedUser::check(user, require_verified_email)
+        })
+    }
+}
+
+impl VerifiedUser {
+    fn check(user: AuthenticatedUser, require_verified_email: bool) -> Result<Self, AuthError> {
+        if require_verified_email && !user.email_verified {
+            return Err(AuthError::EmailNotVerified);
+        }
+
+        Ok(VerifiedUser {
+            user_id: user.user_id,
+            role: user.role,
+            session_id: user.session_id,
+        })
+    }
+}
+
+impl<P: RequiredPermission> Authorized<P> {
+    fn check(user: AuthenticatedUser) -> Result<Self, AuthError> {
+        if !user.has_permission(P::PERMISSION) {
+            return Err(AuthError::MissingPermission(P::PERMISSION));
+        }
+
+        Ok(Authorized {
+            user_id: user.user_id,
+            role: user.role,
+            session_id: user.session_id,
+            permissions: user.permissions,
+            _permission: PhantomData,
+        })
+    }
+}
+
+impl TryFrom<AuthenticatedUser> for AdminUser {
+    type Error = AuthError;
+
+    fn try_from(user: AuthenticatedUser) -> Result<Self, Self::Error> {
+        match user.role {
+            Role::Admin => Ok(AdminUser {
+                user_id: user.user_id,
+                role: user.role,
+                session_id: user.session_id,
+            }),
+            Role::User => Err(AuthError::Forbidden),
+        }
+    }
+}
+
+// Admins without a second factor are refused once the role check passes
+fn check_admin_mfa(user: &AuthenticatedUser, require_admin_mfa: bool) -> Result<(), AuthError> {
+    if require_admin_mfa && user.role == Role::Admin && !user.mfa_enabled {
+        return Err(AuthError::MfaRequired);
+    }
+
+    Ok(())
+}
+
+// Decode the Bearer token on a request into an authenticated user, rejecting
+// tokens issued before the user's privileges or credentials last changed.
+// Personal API keys are accepted in the same header.
+async fn authenticate(req: &HttpRequest) -> Result<AuthenticatedUser, AuthError> {
+    let state = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or(AuthError::Misconfigured)?;
+
+    let token = bearer_token(req).ok_or(AuthError::MissingToken)?;
+
+    let client = state
+        .pg_pool
+        .get()
+        .await
+        .map_err(|e| AuthError::Database(e.to_string()))?;
+
+    if token.starts_with(API_KEY_PREFIX) {
+        return authenticate_api_key(&client, token).await;
+    }
+
+    let claims =
+        validate_token(token, &state.config.jwt_keys).map_err(|_| AuthError::InvalidToken)?;
+    let mut user = user_from_claims(&claims)?;
+
+    let row = load_user_state(&client, user.user_id, Some(user.session_id)).await?;
+
+    check_token_version(claims.ver, row.as_ref().map(|row| row.get("token_version")))?;
+    if let Some(row) = row {
+        // Logging out or revoking the session ends its access tokens too
+        if !row.get::<_, bool>("session_active") {
+            return Err(AuthError::InvalidToken);
+        }
+
+        user.email_verified = row.get("email_verified");
+        user.mfa_enabled = row.get("mfa_enabled");
+        user.permissions = granted_permissions(&row);
+
+        if let Err(e) = touch_session(&client, user.session_id, row.get("session_last_seen_at")).await {
+            warn!("Failed to update session last seen time: {}", e);
+        }
+    }
+
+    Ok(user)
+}
+
+// API keys act as their owner, narrowed to the key's scopes. They never carry
+// the admin role, so admin-only endpoints still need an interactive login.
+async fn authenticate_api_key(client: &Client, key: &str) -> Result<AuthenticatedUser, AuthError> {
+    let credential = verify_api_key(client, key)
+        .await
+        .map_err(|e| AuthError::Database(e.to_string()))?
+        .ok_or(AuthError::InvalidToken)?;
+
+    let row = load_user_state(client, credential.user_id, None)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+
+    Ok(AuthenticatedUser {
+        user_id: credential.user_id,
+        role: Role::User,
+        // Keys have no refresh token family; the key itself stands in for the session
+        session_id: credential.id,
+        email_verified: row.get("email_verified"),
+        mfa_enabled: row.get("mfa_enabled"),
+        permissions: effective_permissions(&credential.scopes, &granted_permissions(&row)),
+        api_key_id: Some(credential.id),
+    })
+}
+
+// User flags and permissions, plus the state of the token's session when given
+async fn load_user_state(
+    client: &Client,
+    user_id: Uuid,
+    session_id: Option<Uuid>,
+) -> Result<Option<Row>, AuthError> {
+    client
+        .query_opt(
+            "SELECT token_version, is_admin, email_verified_at IS NOT NULL AS email_verified,
+                    EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.confirmed_at IS NOT NULL) AS mfa_enabled,
+                    ARRAY(SELECT DISTINCT rp.permission FROM user_roles ur
+                          JOIN role_permissions rp ON rp.role = ur.role
+                          WHERE ur.user_id = users.id)::text[] AS permissions,
+                    EXISTS(SELECT 1 FROM refresh_tokens rt
+                           WHERE rt.family_id = $2 AND rt.user_id = users.id AND rt.revoked_at IS NULL) AS session_active,
+                    (SELECT s.last_seen_at FROM sessions s WHERE s.id = $2) AS session_last_seen_at
+             FROM users WHERE id = $1",
+            &[&user_id, &session_id],
+        )
+        .await
+        .map_err(|e| AuthError::Database(e.to_string()))
+}
+
+// Permissions from the user's roles; admins hold every permission
+fn granted_permissions(row: &Row) -> Vec<Permission> {
+    if row.get::<_, bool>("is_admin") {
+        return Permission::ALL.to_vec();
+    }
+
+    // Permission names this build doesn't know about are ignored
+    row.get::<_, Vec<String>>("permissions")
+        .iter()
+        .filter_map(|name| Permission::from_name(name))
+        .collect()
+}
+
+// A token is only valid while the user exists and its version is current
+fn check_token_version(token_version: i32, current_version: Option<i32>) -> Result<(), AuthError> {
+    match current_version {
+        Some(current) if current == token_version => Ok(()),
+        _ => Err(AuthError::InvalidToken),
+    }
+}
+
+// Pull the token out of an `Authorization: Bearer <token>` header
+pub(crate) fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+fn user_from_claims(claims: &Claims) -> Result<AuthenticatedUser, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let role = Role::from_claim(&claims.role)?;
+    let session_id = Uuid::parse_str(&claims.sid).map_err(|_| AuthError::InvalidToken)?;
+
+    Ok(AuthenticatedUser {
+        user_id,
+        role,
+        session_id,
+        email_verified: false,
+        mfa_enabled: false,
+        permissions: Vec::new(),
+        api_key_id: None,
+    })
+}
+
+// Issue a signed, short-lived access token for a user's session
+pub fn generate_token(
+    user: &User,
+    session_id: Uuid,
+    token_version: i32,
+    config: &AppConfig,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now().timestamp();
+    let role = if user.is_admin { Role::Admin } else { Role::User };
+
+    let claims = Claims {
+        sub: user.id.to_string(),
+        exp: (now + config.jwt_expiry_secs) as usize,
+        iat: now as usize,
+        role: role.as_str().to_string(),
+        sid: session_id.to_string(),
+        ver: token_version,
+    };
+
+    config.jwt_keys.encode(&claims)
 }
 
 // Middleware validator function for JWT token authentication
@@ -26,13 +494,17 @@ pub async fn jwt_validator(
     req: ServiceRequest,
     credentials: BearerAuth,
 ) -> Result<ServiceRequest, Error> {
-    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     let config = req
         .app_data::<Config>()
         .cloned()
         .unwrap_or_else(Default::default);
 
-    match validate_token(credentials.token(), &jwt_secret) {
+    let result = match req.app_data::<web::Data<AppState>>() {
+        Some(state) => validate_token(credentials.token(), &state.config.jwt_keys),
+        None => return Err(AuthError::Misconfigured.into()),
+    };
+
+    match result {
         Ok(claims) => {
             req.extensions_mut().insert(claims);
             Ok(req)
@@ -44,11 +516,8 @@ pub async fn jwt_validator(
 }
 
 // Validate JWT token and extract claims
-fn validate_token(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let validation = Validation::default();
-    let key = DecodingKey::from_secret(secret.as_bytes());
-    let token_data = decode::<Claims>(token, &key, &validation)?;
-    Ok(token_data.claims)
+pub fn validate_token(token: &str, keys: &KeyRing) -> Result<Claims, jsonwebtoken::errors::Error> {
+    keys.decode::<Claims>(token)
 }
 
 // Extract user ID from request
@@ -62,4 +531,156 @@ pub fn is_admin(req: &ServiceRequest) -> bool {
         .get::<Claims>()
         .map(|claims| claims.role == "admin")
         .unwrap_or(false)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn test_user(is_admin: bool) -> User {
+        let mut user = User::new(
+            "learner@example.com".to_string(),
+            None,
+            "Test Learner".to_string(),
+            None,
+        );
+        user.is_admin = is_admin;
+        user
+    }
+
+    #[test]
+    fn test_generate_token_encodes_role_and_expiry() {
+        let config = AppConfig::for_tests();
+        let admin = test_user(true);
+
+        let token = generate_token(&admin, Uuid::new_v4(), 0, &config).unwrap();
+        let claims = validate_token(&token, &config.jwt_keys).unwrap();
+
+        assert_eq!(claims.sub, admin.id.to_string());
+        assert_eq!(claims.role, "admin");
+        assert_eq!(claims.exp - claims.iat, config.jwt_expiry_secs as usize);
+
+        let user = user_from_claims(&claims).unwrap();
+        assert_eq!(user.user_id, admin.id);
+        assert_eq!(user.session_id.to_string(), claims.sid);
+        assert_eq!(user.role, Role::Admin);
+    }
+
+    #[test]
+    fn test_rejects_bad_tokens() {
+        let config = AppConfig::for_tests();
+        let token = generate_token(&test_user(false), Uuid::new_v4(), 0, &config).unwrap();
+
+        assert!(validate_token(&token, &KeyRing::from_secret("some-other-secret")).is_err());
+
+        let mut expired = config.clone();
+        expired.jwt_expiry_secs = -3600;
+        let token = generate_token(&test_user(false), Uuid::new_v4(), 0, &expired).unwrap();
+        assert!(validate_token(&token, &config.jwt_keys).is_err());
+    }
+
+    #[test]
+    fn test_admin_requires_admin_role() {
+        let config = AppConfig::for_tests();
+
+        let token = generate_token(&test_user(false), Uuid::new_v4(), 0, &config).unwrap();
+        let user = user_from_claims(&validate_token(&token, &config.jwt_keys).unwrap()).unwrap();
+        let err = AdminUser::try_from(user).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+
+        let token = generate_token(&test_user(true), Uuid::new_v4(), 0, &config).unwrap();
+        let user = user_from_claims(&validate_token(&token, &config.jwt_keys).unwrap()).unwrap();
+        assert!(AdminUser::try_from(user).is_ok());
+    }
+
+    #[test]
+    fn test_admin_mfa_policy() {
+        let config = AppConfig::for_tests();
+        let token = generate_token(&test_user(true), Uuid::new_v4(), 0, &config).unwrap();
+        let mut admin = user_from_claims(&validate_token(&token, &config.jwt_keys).unwrap()).unwrap();
+
+        let err = check_admin_mfa(&admin, true).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        assert!(check_admin_mfa(&admin, false).is_ok());
+
+        admin.mfa_enabled = true;
+        assert!(check_admin_mfa(&admin, true).is_ok());
+
+        // Regular users are not affected by the admin policy
+        let token = generate_token(&test_user(false), Uuid::new_v4(), 0, &config).unwrap();
+        let user = user_from_claims(&validate_token(&token, &config.jwt_keys).unwrap()).unwrap();
+        assert!(check_admin_mfa(&user, true).is_ok());
+    }
+
+    #[test]
+    fn test_verified_user_policy() {
+        let config = AppConfig::for_tests();
+        let token = generate_token(&test_user(false), Uuid::new_v4(), 0, &config).unwrap();
+        let mut user = user_from_claims(&validate_token(&token, &config.jwt_keys).unwrap()).unwrap();
+
+        let err = VerifiedUser::check(user.clone(), true).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+
+        // Policy switched off
+        assert!(VerifiedUser::check(user.clone(), false).is_ok());
+
+        user.email_verified = true;
+        assert!(VerifiedUser::check(user, true).is_ok());
+    }
+
+    #[test]
+    fn test_permission_extractor() {
+        let config = AppConfig::for_tests();
+        let token = generate_token(&test_user(false), Uuid::new_v4(), 0, &config).unwrap();
+        let mut user = user_from_claims(&validate_token(&token, &config.jwt_keys).unwrap()).unwrap();
+
+        let err = Authorized::<CanWriteBlog>::check(user.clone()).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(err.to_string(), "Missing permission: blog:write");
+
+        // An editor can write posts but not manage courses
+        user.permissions = vec![Permission::WriteBlog];
+        assert!(Authorized::<CanWriteBlog>::check(user.clone()).is_ok());
+        assert!(Authorized::<CanManageCourses>::check(user.clone()).is_err());
+
+        // Instructors manage courses, but only their own
+        user.permissions = vec![Permission::ManageOwnCourses];
+        let instructor = Authorized::<CanManageCourses>::check(user).unwrap();
+        assert!(!instructor.has_permission(Permission::ManageAllCourses));
+
+        // Admins hold every permission
+        let token = generate_token(&test_user(true), Uuid::new_v4(), 0, &config).unwrap();
+        let admin = user_from_claims(&validate_token(&token, &config.jwt_keys).unwrap()).unwrap();
+        let admin = Authorized::<CanModerateComments>::check(admin).unwrap();
+        assert!(admin.has_permission(Permission::ManageAllCourses));
+    }
+
+    #[test]
+    fn test_token_version_must_be_current() {
+        assert!(check_token_version(3, Some(3)).is_ok());
+
+        // Role or password changed since the token was issued
+        assert!(matches!(check_token_version(3, Some(4)), Err(AuthError::InvalidToken)));
+
+        // User was deleted
+        assert!(matches!(check_token_version(3, None), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_bearer_token_parsing() {
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer abc.def.ghi"))
+            .to_http_request();
+        assert_eq!(bearer_token(&req), Some("abc.def.ghi"));
+
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Basic dXNlcjpwYXNz"))
+            .to_http_request();
+        assert_eq!(bearer_token(&req), None);
+
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(bearer_token(&req), None);
+        assert_eq!(AuthError::MissingToken.status_code(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,527 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue, RETRY_AFTER},
+    web, Error, HttpResponse,
+};
+use async_trait::async_trait;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::middleware::auth::{bearer_token, validate_token};
+use crate::security::throttle::client_ip;
+use crate::AppState;
+
+// Token bucket: up to `capacity` requests in a burst, refilled at a steady rate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitPolicy {
+    pub name: &'static str,
+    pub capacity: u32,
+    pub refill_per_minute: u32,
+}
+
+impl RateLimitPolicy {
+    fn refill_per_sec(&self) -> f64 {
+        self.refill_per_minute as f64 / 60.0
+    }
+}
+
+struct RateLimitRule {
+    // Only requests with this method match, when set
+    method: Option<&'static str>,
+    // Path segments; `*` matches one segment, a trailing `**` any remainder
+    pattern: &'static str,
+    policy: RateLimitPolicy,
+}
+
+const DEFAULT_POLICY: RateLimitPolicy = RateLimitPolicy {
+    name: "default",
+    capacity: 120,
+    refill_per_minute: 120,
+};
+
+// Shared by enrollment and checkout so both draw from one bucket
+const CHECKOUT_POLICY: RateLimitPolicy = RateLimitPolicy {
+    name: "checkout",
+    capacity: 10,
+    refill_per_minute: 10,
+};
+
+// Shared by every endpoint that accepts a credential or code
+const AUTH_POLICY: RateLimitPolicy = RateLimitPolicy {
+    name: "auth",
+    capacity: 20,
+    refill_per_minute: 10,
+};
+
+// Every rate limit in the API. The first matching rule wins; anything
+// unmatched falls back to DEFAULT_POLICY.
+const RULES: &[RateLimitRule] = &[
+    // Writing comments is the easiest thing to spam
+    RateLimitRule {
+        method: Some("POST"),
+        pattern: "/api/blog/*/comments",
+        policy: RateLimitPolicy {
+            name: "comments",
+            capacity: 5,
+            refill_per_minute: 5,
+        },
+    },
+    // Each upload is decoded and thumbnailed
+    RateLimitRule {
+        method: Some("POST"),
+        pattern: "/api/media",
+        policy: RateLimitPolicy {
+            name: "uploads",
+            capacity: 10,
+            refill_per_minute: 10,
+        },
+    },
+    // Each check makes Solana RPC calls
+    RateLimitRule {
+        method: Some("POST"),
+        pattern: "/api/orders/*/verify",
+        policy: RateLimitPolicy {
+            name: "payments",
+            capacity: 20,
+            refill_per_minute: 20,
+        },
+    },
+    // Purchases take coupon codes, which could otherwise be guessed
+    RateLimitRule {
+        method: Some("POST"),
+        pattern: "/api/courses/*/checkout",
+        policy: CHECKOUT_POLICY,
+    },
+    RateLimitRule {
+        method: Some("POST"),
+        pattern: "/api/courses/enroll/*",
+        policy: CHECKOUT_POLICY,
+    },
+    // Search scores every document in memory while holding a lock
+    RateLimitRule {
+        method: None,
+        pattern: "/api/search/**",
+        policy: RateLimitPolicy {
+            name: "search",
+            capacity: 30,
+            refill_per_minute: 30,
+        },
+    },
+    // Credential endpoints; failed logins are additionally throttled per account.
+    // The rest of /api/auth is ordinary account management and uses the default.
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/login",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/register",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/refresh",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/web3/*",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/oauth/**",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/password/*",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/verify-email/**",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/mfa/verify",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/mfa/totp/disable",
+        policy: AUTH_POLICY,
+    },
+    RateLimitRule {
+        method: None,
+        pattern: "/api/auth/mfa/recovery-codes",
+        policy: AUTH_POLICY,
+    },
+];
+
+// Policy that applies to a request
+pub fn policy_for(method: &str, path: &str) -> RateLimitPolicy {
+    RULES
+        .iter()
+        .find(|rule| {
+            (rule.method.is_none() || rule.method == Some(method)) && path_matches(rule.pattern, path)
+        })
+        .map(|rule| rule.policy)
+        .unwrap_or(DEFAULT_POLICY)
+}
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern = pattern.trim_matches('/').split('/');
+    let mut path = path.trim_matches('/').split('/');
+
+    loop {
+        match (pattern.next(), path.next()) {
+            (Some("**"), _) => return true,
+            (Some("*"), Some(segment)) if !segment.is_empty() => (),
+            (Some(expected), Some(segment)) if expected == segment => (),
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+// Outcome of taking a token for one request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // Seconds until the bucket is full again
+    pub reset_secs: u64,
+    // Seconds until a request would be allowed; zero when allowed
+    pub retry_after_secs: u64,
+}
+
+// Backend holding the buckets; the in-process store works for a single
+// instance, a shared store (e.g. Redis) is needed once there are several
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn acquire(&self, key: &str, policy: &RateLimitPolicy) -> RateLimitDecision;
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn full(policy: &RateLimitPolicy, now: Instant) -> Self {
+        Bucket {
+            tokens: policy.capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, policy: &RateLimitPolicy, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * policy.refill_per_sec()).min(policy.capacity as f64);
+        self.updated_at = now;
+    }
+
+    fn take(&mut self, policy: &RateLimitPolicy, now: Instant) -> RateLimitDecision {
+        self.refill(policy, now);
+
+        let rate = policy.refill_per_sec();
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+
+        let retry_after_secs = if allowed {
+            0
+        } else {
+            ((1.0 - self.tokens) / rate).ceil() as u64
+        };
+
+        RateLimitDecision {
+            allowed,
+            limit: policy.capacity,
+            remaining: self.tokens.floor() as u32,
+            reset_secs: ((policy.capacity as f64 - self.tokens) / rate).ceil() as u64,
+            retry_after_secs,
+        }
+    }
+}
+
+// Buckets kept in process memory
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    // Idle buckets are dropped once the map grows past this
+    max_buckets: usize,
+}
+
+impl InMemoryRateLimitStore {
+    pub fn new() -> Self {
+        InMemoryRateLimitStore {
+            buckets: Mutex::new(HashMap::new()),
+            max_buckets: 100_000,
+        }
+    }
+}
+
+impl Default for InMemoryRateLimitStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn acquire(&self, key: &str, policy: &RateLimitPolicy) -> RateLimitDecision {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= self.max_buckets && !buckets.contains_key(key) {
+            // A bucket that has refilled completely carries no information
+            buckets.retain(|name, bucket| {
+                let policy = policy_named(name).unwrap_or(DEFAULT_POLICY);
+                bucket.refill(&policy, now);
+                bucket.tokens < policy.capacity as f64
+            });
+        }
+
+        buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::full(policy, now))
+            .take(policy, now)
+    }
+}
+
+fn policy_named(key: &str) -> Option<RateLimitPolicy> {
+    let name = key.split(':').next()?;
+    RULES
+        .iter()
+        .map(|rule| rule.policy)
+        .chain(std::iter::once(DEFAULT_POLICY))
+        .find(|policy| policy.name == name)
+}
+
+// Middleware applying the rate limit policies to every request. Requests are
+// counted per user when they carry a valid access token, otherwise per client IP.
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        RateLimiter { store }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            store: self.store.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+
+        Box::pin(async move {
+            let policy = policy_for(req.method().as_str(), req.path());
+            let key = format!("{}:{}", policy.name, client_key(&req));
+            let decision = store.acquire(&key, &policy).await;
+
+            if !decision.allowed {
+                let mut response = HttpResponse::TooManyRequests().json(serde_json::json!({
+                    "error": "Too many requests, please slow down"
+                }));
+                set_headers(&mut response, &policy, &decision);
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let mut response = service.call(req).await?;
+            set_headers(response.response_mut(), &policy, &decision);
+            Ok(response.map_into_left_body())
+        })
+    }
+}
+
+// `user:<id>` for requests with a valid access token, `ip:<addr>` otherwise
+fn client_key(req: &ServiceRequest) -> String {
+    let state = match req.app_data::<web::Data<AppState>>() {
+        Some(state) => state,
+        None => {
+            let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+            return format!("ip:{}", ip.as_deref().unwrap_or("unknown"));
+        }
+    };
+
+    let user_id = bearer_token(req.request())
+        .and_then(|token| validate_token(token, &state.config.jwt_keys).ok())
+        .map(|claims| claims.sub);
+
+    match user_id {
+        Some(user_id) => format!("user:{}", user_id),
+        None => format!("ip:{}", client_ip(req.request(), &state.config)),
+    }
+}
+
+// IETF RateLimit header fields, plus Retry-After on rejections
+fn set_headers<B>(
+    response: &mut actix_web::HttpResponse<B>,
+    policy: &RateLimitPolicy,
+    decision: &RateLimitDecision,
+) {
+    let window_secs = (policy.capacity as u64 * 60) / policy.refill_per_minute.max(1) as u64;
+    let headers = response.headers_mut();
+    let mut insert = |name: &'static str, value: String| {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    };
+
+    insert("ratelimit-limit", decision.limit.to_string());
+    insert("ratelimit-remaining", decision.remaining.to_string());
+    insert("ratelimit-reset", decision.reset_secs.to_string());
+    insert("ratelimit-policy", format!("{};w={}", policy.capacity, window_secs));
+
+    if !decision.allowed {
+        headers.insert(RETRY_AFTER, HeaderValue::from(decision.retry_after_secs));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{http::StatusCode, App};
+    use std::time::Duration;
+
+    const POLICY: RateLimitPolicy = RateLimitPolicy {
+        name: "test",
+        capacity: 3,
+        refill_per_minute: 60,
+    };
+
+    #[test]
+    fn test_bucket_allows_burst_then_refills() {
+        let start = Instant::now();
+        let mut bucket = Bucket::full(&POLICY, start);
+
+        for remaining in [2, 1, 0] {
+            let decision = bucket.take(&POLICY, start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+
+        let decision = bucket.take(&POLICY, start);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after_secs, 1);
+        assert_eq!(decision.reset_secs, 3);
+
+        // One token per second comes back
+        let decision = bucket.take(&POLICY, start + Duration::from_secs(1));
+        assert!(decision.allowed);
+
+        // Never refills past capacity
+        let decision = bucket.take(&POLICY, start + Duration::from_secs(3600));
+        assert_eq!(decision.remaining, POLICY.capacity - 1);
+    }
+
+    #[test]
+    fn test_policy_lookup() {
+        assert_eq!(policy_for("POST", "/api/blog/abc123/comments").name, "comments");
+        assert_eq!(policy_for("GET", "/api/blog/abc123/comments").name, "default");
+        assert_eq!(policy_for("POST", "/api/search/courses").name, "search");
+        assert_eq!(policy_for("POST", "/api/media").name, "uploads");
+        assert_eq!(policy_for("GET", "/api/media").name, "default");
+        assert_eq!(policy_for("POST", "/api/orders/abc123/verify").name, "payments");
+        assert_eq!(policy_for("POST", "/api/courses/abc123/checkout").name, "checkout");
+        assert_eq!(policy_for("POST", "/api/courses/enroll/abc123").name, "checkout");
+        assert_eq!(policy_for("POST", "/api/auth/login").name, "auth");
+        assert_eq!(policy_for("POST", "/api/auth/web3/login").name, "auth");
+        assert_eq!(policy_for("GET", "/api/auth/oauth/github/start").name, "auth");
+        assert_eq!(policy_for("POST", "/api/auth/password/reset").name, "auth");
+        assert_eq!(policy_for("POST", "/api/auth/verify-email").name, "auth");
+        assert_eq!(policy_for("POST", "/api/auth/verify-email/resend").name, "auth");
+        assert_eq!(policy_for("POST", "/api/auth/mfa/totp/disable").name, "auth");
+        assert_eq!(policy_for("GET", "/api/auth/me").name, "default");
+        assert_eq!(policy_for("GET", "/api/auth/sessions").name, "default");
+        assert_eq!(policy_for("PUT", "/api/auth/password").name, "default");
+        assert_eq!(policy_for("POST", "/api/auth/mfa/totp/setup").name, "default");
+        assert_eq!(policy_for("GET", "/api/courses").name, "default");
+        assert_eq!(policy_for("GET", "/api/searchable").name, "default");
+
+        assert!(path_matches("/api/blog/*/comments", "/api/blog/1/comments/"));
+        assert!(!path_matches("/api/blog/*/comments", "/api/blog/comments"));
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_rejects_with_headers() {
+        let store: Arc<dyn RateLimitStore> = Arc::new(InMemoryRateLimitStore::new());
+        let app = init_service(
+            App::new()
+                .wrap(RateLimiter::new(store))
+                .route("/api/blog/{id}/comments", web::post().to(HttpResponse::Created)),
+        )
+        .await;
+
+        let comment = || {
+            TestRequest::post()
+                .uri("/api/blog/abc/comments")
+                .peer_addr("192.0.2.1:4000".parse().unwrap())
+                .to_request()
+        };
+
+        for _ in 0..5 {
+            let response = call_service(&app, comment()).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+            assert_eq!(response.headers().get("ratelimit-limit").unwrap(), "5");
+        }
+
+        let response = call_service(&app, comment()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get("ratelimit-remaining").unwrap(), "0");
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "12");
+
+        // Other clients have their own bucket
+        let other = TestRequest::post()
+            .uri("/api/blog/abc/comments")
+            .peer_addr("192.0.2.2:4000".parse().unwrap())
+            .to_request();
+        assert_eq!(call_service(&app, other).await.status(), StatusCode::CREATED);
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+use crate::models::money::Money;
+
+// How much a coupon takes off the course price
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Discount {
+    // Whole percent of the price, 1-100
+    Percent { percent_off: i32 },
+    // A fixed amount, never taking the price below zero
+    Fixed { amount_off: Money },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Coupon {
+    pub id: Uuid,
+    // Stored upper-case; codes are matched case-insensitively
+    pub code: String,
+    pub discount: Discount,
+    // Only valid for this course; any paid course when None
+    pub course_id: Option<Uuid>,
+    // Redemptions allowed across all users; unlimited when None
+    pub max_redemptions: Option<i32>,
+    // Redemptions allowed per user; unlimited when None
+    pub max_redemptions_per_user: Option<i32>,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub active: bool,
+    pub redemptions: i64,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateCouponRequest {
+    pub code: String,
+    pub discount: Discount,
+    pub course_id: Option<Uuid>,
+    pub max_redemptions: Option<i32>,
+    pub max_redemptions_per_user: Option<i32>,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub active: Option<bool>,
+}
+
+// Limits and dates left out are kept; ones sent as null are cleared
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateCouponRequest {
+    pub discount: Option<Discount>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_redemptions: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_redemptions_per_user: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub starts_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub active: Option<bool>,
+}
+
+// A field that is present, even as null, becomes Some; absent ones stay None
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// Optional `?coupon=CODE` on enrollment and checkout
+#[derive(Debug, Deserialize)]
+pub struct CouponQuery {
+    pub coupon: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_update_request_can_clear_limits() {
+        let update: UpdateCouponRequest = serde_json::from_value(json!({
+            "max_redemptions": null,
+            "max_redemptions_per_user": 2,
+            "active": false
+        }))
+        .unwrap();
+
+        assert_eq!(update.max_redemptions, Some(None));
+        assert_eq!(update.max_redemptions_per_user, Some(Some(2)));
+        assert_eq!(update.starts_at, None);
+        assert_eq!(update.expires_at, None);
+        assert_eq!(update.active, Some(false));
+    }
+}
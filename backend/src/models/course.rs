@@ -2,12 +2,14 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::models::money::Money;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Course {
     pub id: Uuid,
     pub title: String,
     pub description: String,
-    pub price: f64,
+    pub price: Money,
     pub is_free: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -57,7 +59,7 @@ pub struct UserLessonProgress {
 pub struct CreateCourseRequest {
     pub title: String,
     pub description: String,
-    pub price: f64,
+    pub price: Money,
     pub is_free: bool,
 }
 
@@ -65,25 +67,44 @@ pub struct CreateCourseRequest {
 pub struct UpdateCourseRequest {
     pub title: Option<String>,
     pub description: Option<String>,
-    pub price: Option<f64>,
+    pub price: Option<Money>,
     pub is_free: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateSectionRequest {
-    pub course_id: Uuid,
     pub title: String,
     pub description: Option<String>,
-    pub position: i32,
+    // Zero-based slot to insert at; appended at the end when omitted
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateSectionRequest {
+    pub title: Option<String>,
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateLessonRequest {
-    pub section_id: Uuid,
     pub title: String,
     pub content: Option<String>,
     pub video_url: Option<String>,
-    pub position: i32,
+    // Zero-based slot to insert at; appended at the end when omitted
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateLessonRequest {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub video_url: Option<String>,
+}
+
+// New order for all sections of a course, or all lessons of a section
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReorderRequest {
+    pub ids: Vec<Uuid>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -91,6 +112,69 @@ pub struct UpdateProgressRequest {
     pub completed: bool,
 }
 
+// A lesson as referenced from progress summaries
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LessonRef {
+    pub lesson_id: Uuid,
+    pub section_id: Uuid,
+    pub title: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SectionProgress {
+    pub section_id: Uuid,
+    pub title: String,
+    pub total_lessons: i64,
+    pub completed_lessons: i64,
+    // Whole percent, rounded down so only a finished section shows 100
+    pub percent_complete: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CourseProgress {
+    pub course_id: Uuid,
+    pub enrolled_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub total_lessons: i64,
+    pub completed_lessons: i64,
+    pub percent_complete: i32,
+    pub sections: Vec<SectionProgress>,
+    // Where the learner left off
+    pub last_accessed_lesson: Option<LessonRef>,
+    pub last_accessed_at: Option<DateTime<Utc>>,
+    // First lesson, in course order, that isn't completed yet
+    pub next_lesson: Option<LessonRef>,
+}
+
+// Which enrollments the learner dashboard lists
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EnrollmentStatus {
+    InProgress,
+    Completed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MyCoursesQuery {
+    // All enrollments when omitted
+    pub status: Option<EnrollmentStatus>,
+}
+
+// A course on the learner's dashboard
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnrolledCourse {
+    pub course: Course,
+    pub enrolled_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    // What was charged, after any coupon
+    pub amount_paid: Money,
+    pub total_lessons: i64,
+    pub completed_lessons: i64,
+    pub percent_complete: i32,
+    pub last_accessed_at: Option<DateTime<Utc>>,
+    pub next_lesson: Option<LessonRef>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CourseWithSections {
     pub course: Course,
@@ -107,7 +191,7 @@ impl Course {
     pub fn new(
         title: String,
         description: String,
-        price: f64,
+        price: Money,
         is_free: bool,
         created_by: Uuid,
     ) -> Self {
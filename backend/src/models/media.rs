@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MediaItem {
+    pub id: Uuid,
+    // Where the file is served from; use this in profiles, portfolios and posts
+    pub url: String,
+    pub thumbnail_url: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub width: i32,
+    pub height: i32,
+    pub created_at: DateTime<Utc>,
+}
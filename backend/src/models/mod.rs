@@ -2,3 +2,7 @@ pub mod user;
 pub mod portfolio;
 pub mod course;
 pub mod blog;
+pub mod media;
+pub mod order;
+pub mod money;
+pub mod coupon;
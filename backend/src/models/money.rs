@@ -0,0 +1,200 @@
+use bytes::BytesMut;
+use postgres_types::{accepts, to_sql_checked, FromSql, IsNull, ToSql, Type};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// Digits after the decimal point a stored price may have (courses.price is DECIMAL(10, 2))
+pub const PRICE_SCALE: u32 = 2;
+// Prices must stay below this to fit DECIMAL(10, 2)
+const MAX_PRICE: i64 = 100_000_000;
+
+// Currencies prices can be set in. Checkout settles in native SOL through
+// Solana Pay, so that is the only one for now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Currency {
+    #[default]
+    #[serde(rename = "SOL")]
+    Sol,
+}
+
+impl Currency {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Sol => "SOL",
+        }
+    }
+
+    // Decimal places of the currency's smallest unit (lamports for SOL)
+    pub fn decimals(&self) -> u32 {
+        match self {
+            Currency::Sol => 9,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownCurrency(pub String);
+
+impl fmt::Display for UnknownCurrency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown currency: {}", self.0)
+    }
+}
+
+impl Error for UnknownCurrency {}
+
+impl FromStr for Currency {
+    type Err = UnknownCurrency;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        match code {
+            "SOL" => Ok(Currency::Sol),
+            _ => Err(UnknownCurrency(code.to_string())),
+        }
+    }
+}
+
+// Stored as its code in a text column
+impl<'a> FromSql<'a> for Currency {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let code = <&str as FromSql>::from_sql(ty, raw)?;
+        Ok(code.parse()?)
+    }
+
+    accepts!(VARCHAR, TEXT);
+}
+
+impl ToSql for Currency {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.code().to_sql(ty, out)
+    }
+
+    accepts!(VARCHAR, TEXT);
+    to_sql_checked!();
+}
+
+// An exact amount in a currency. Amounts serialize as decimal strings
+// ("12.50") so clients never round-trip them through floats; numbers are
+// accepted on input too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Money {
+    pub amount: Decimal,
+    #[serde(default)]
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: Decimal, currency: Currency) -> Self {
+        Money { amount, currency }
+    }
+
+    // An amount given in the currency's smallest unit, e.g. lamports
+    pub fn from_minor_units(units: i64, currency: Currency) -> Self {
+        Money::new(Decimal::new(units, currency.decimals()).normalize(), currency)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.amount.is_zero()
+    }
+
+    // The amount in the currency's smallest unit, e.g. lamports. None when it
+    // is negative or has more precision than that unit can express.
+    pub fn to_minor_units(self) -> Option<u64> {
+        let scaled = self.amount * Decimal::from(10u64.pow(self.currency.decimals()));
+        if scaled.fract() != Decimal::ZERO {
+            return None;
+        }
+        scaled.to_u64()
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount.round_dp(PRICE_SCALE), self.currency)
+    }
+}
+
+// Check a course price can be stored and agrees with the course's free flag:
+// free courses cost nothing and paid ones cost something
+pub fn validate_price(price: &Money, is_free: bool) -> Result<(), String> {
+    if price.amount < Decimal::ZERO {
+        return Err("Price cannot be negative".to_string());
+    }
+    if price.amount.normalize().scale() > PRICE_SCALE {
+        return Err(format!("Price can have at most {} decimal places", PRICE_SCALE));
+    }
+    if price.amount >= Decimal::from(MAX_PRICE) {
+        return Err(format!("Price must be less than {} {}", MAX_PRICE, price.currency));
+    }
+
+    match (is_free, price.is_zero()) {
+        (true, false) => Err("A free course cannot have a price".to_string()),
+        (false, true) => Err("A paid course needs a price above zero".to_string()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sol(amount: &str) -> Money {
+        Money::new(amount.parse().unwrap(), Currency::Sol)
+    }
+
+    #[test]
+    fn test_serde_keeps_exact_amounts() {
+        let price: Money = serde_json::from_value(json!({ "amount": "19.99", "currency": "SOL" })).unwrap();
+        assert_eq!(price, sol("19.99"));
+        assert_eq!(serde_json::to_value(price).unwrap(), json!({ "amount": "19.99", "currency": "SOL" }));
+
+        // Numbers are accepted and the currency defaults to SOL
+        let price: Money = serde_json::from_value(json!({ "amount": 0.1 })).unwrap();
+        assert_eq!(price, sol("0.1"));
+
+        assert!(serde_json::from_value::<Money>(json!({ "amount": "1", "currency": "USD" })).is_err());
+    }
+
+    #[test]
+    fn test_validate_price() {
+        assert_eq!(validate_price(&sol("0"), true), Ok(()));
+        assert_eq!(validate_price(&sol("0.00"), true), Ok(()));
+        assert_eq!(validate_price(&sol("12.50"), false), Ok(()));
+        assert_eq!(validate_price(&sol("12.500"), false), Ok(()));
+
+        assert!(validate_price(&sol("5"), true).is_err());
+        assert!(validate_price(&sol("0"), false).is_err());
+        assert!(validate_price(&sol("-1"), false).is_err());
+        assert!(validate_price(&sol("0.001"), false).is_err());
+        assert!(validate_price(&sol("100000000"), false).is_err());
+        assert_eq!(validate_price(&sol("99999999.99"), false), Ok(()));
+    }
+
+    #[test]
+    fn test_minor_units() {
+        assert_eq!(sol("1").to_minor_units(), Some(1_000_000_000));
+        assert_eq!(sol("0.25").to_minor_units(), Some(250_000_000));
+        assert_eq!(sol("19.99").to_minor_units(), Some(19_990_000_000));
+        assert_eq!(sol("0.0000000001").to_minor_units(), None);
+        assert_eq!(sol("-1").to_minor_units(), None);
+        assert_eq!(Money::from_minor_units(19_990_000_000, Currency::Sol), sol("19.99"));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(sol("12.5").to_string(), "12.5 SOL");
+        assert_eq!("SOL".parse::<Currency>(), Ok(Currency::Sol));
+        assert!("sol".parse::<Currency>().is_err());
+    }
+}
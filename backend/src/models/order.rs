@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::money::Money;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    // Waiting for an on-chain payment
+    Pending,
+    // Payment verified and the buyer enrolled
+    Paid,
+    // Not paid in time; a new checkout is needed
+    Expired,
+}
+
+// A checkout for one course, paid with a Solana Pay transfer request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Order {
+    pub id: Uuid,
+    pub course_id: Uuid,
+    pub status: OrderStatus,
+    pub amount_lamports: i64,
+    // The same amount in SOL, as a decimal string
+    pub amount_sol: String,
+    // Coupon the order was priced with, and what it took off
+    pub coupon_code: Option<String>,
+    pub discount: Money,
+    pub recipient: String,
+    // Public key the paying transaction must include, to find it on chain
+    pub reference: String,
+    // solana: URL for wallets (also what goes in the QR code)
+    pub payment_url: String,
+    // Signature of the paying transaction, once verified
+    pub signature: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub paid_at: Option<DateTime<Utc>>,
+}
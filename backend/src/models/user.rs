@@ -2,6 +2,9 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::models::blog::BlogPost;
+use crate::models::portfolio::PortfolioItem;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub id: Uuid,
@@ -13,6 +16,7 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub web3_wallet: Option<String>,
+    pub email_verified_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,6 +25,10 @@ pub struct CreateUserRequest {
     pub password: Option<String>,
     pub full_name: String,
     pub web3_wallet: Option<String>,
+    // Sign-in challenge for `web3_wallet` and the wallet's signature of it,
+    // proving the registrant owns the wallet
+    pub wallet_message: Option<String>,
+    pub wallet_signature: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -36,12 +44,181 @@ pub struct Web3LoginRequest {
     pub signature: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Web3ChallengeRequest {
+    pub wallet_address: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Web3ChallengeResponse {
+    pub message: String,
+    pub nonce: String,
+    pub expires_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: User,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+// Returned by login instead of tokens when a second factor is still required
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaVerifyRequest {
+    pub mfa_token: String,
+    // TOTP code or recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkedWallet {
+    pub wallet_address: String,
+    pub is_primary: bool,
+    pub linked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetPasswordRequest {
+    // Required when the account already has a password
+    pub current_password: Option<String>,
+    // Required when it doesn't: a freshly signed challenge from one of its wallets
+    pub wallet_proof: Option<Web3LoginRequest>,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginLockout {
+    pub email: String,
+    pub ip_address: String,
+    pub failed_count: i32,
+    pub last_failed_at: DateTime<Utc>,
+    pub locked_until: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClearLockoutRequest {
+    pub email: String,
+    // Clears the lockout for every IP when omitted
+    pub ip_address: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthStartResponse {
+    pub authorization_url: String,
+    pub state: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthCallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoleSummary {
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserRoles {
+    pub user_id: Uuid,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    // First part of the key, to tell keys apart
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    // Never expires when omitted
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyCreatedResponse {
+    // The full key; it is not shown again
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    // The session making this request
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteAccountRequest {
+    // Required when the account has a password
+    pub password: Option<String>,
+    // Required for accounts without a password; must match the account email
+    pub confirm_email: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserProfile {
     pub user_id: Uuid,
@@ -51,7 +228,32 @@ pub struct UserProfile {
     pub skills: Vec<String>,
 }
 
+// Replaces the whole profile; omitted fields are cleared
 #[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateProfileRequest {
+    pub bio: Option<String>,
+    pub profile_picture: Option<String>,
+    #[serde(default)]
+    pub social_links: SocialLinks,
+    #[serde(default)]
+    pub skills: Vec<String>,
+}
+
+// What anyone can see about a user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublicProfile {
+    pub user_id: Uuid,
+    pub full_name: String,
+    pub bio: Option<String>,
+    pub profile_picture: Option<String>,
+    pub social_links: SocialLinks,
+    pub skills: Vec<String>,
+    pub portfolio: Vec<PortfolioItem>,
+    // Published posts only
+    pub blog_posts: Vec<BlogPost>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct SocialLinks {
     pub twitter: Option<String>,
     pub linkedin: Option<String>,
@@ -75,6 +277,7 @@ impl User {
             created_at: Utc::now(),
             updated_at: Utc::now(),
             web3_wallet,
+            email_verified_at: None,
         }
     }
 }
@@ -0,0 +1,955 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder, ResponseError};
+use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::{Duration, Utc};
+use deadpool_postgres::Client;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::db::user_data::{
+    check_deletion_allowed, delete_user_data, export_user_data, DeletionBlocked,
+};
+use crate::middleware::auth::{generate_token, AuthenticatedUser};
+use crate::models::user::{
+    ApiKey, ApiKeyCreatedResponse, CreateApiKeyRequest, DeleteAccountRequest, LinkedWallet,
+    SessionInfo, SetPasswordRequest, User, Web3LoginRequest,
+};
+use crate::security::api_keys::{
+    generate_api_key, insert_api_key, parse_scopes, revoke_user_api_keys, MAX_API_KEY_TTL_DAYS,
+};
+use crate::security::audit::{self, AuditEntry};
+use crate::security::password_reset::validate_new_password;
+use crate::routes::auth::too_many_attempts;
+use crate::security::refresh::{revoke_other_sessions, revoke_session};
+use crate::security::throttle::{
+    check_login_allowed, client_ip, record_login_failure, record_login_success,
+};
+use crate::web3::signature::verify_wallet_proof;
+use crate::AppState;
+
+#[derive(Debug, PartialEq, Eq)]
+enum UnlinkOutcome {
+    Unlinked,
+    NotLinked,
+    // The wallet is the account's only way to sign in
+    LastSignInMethod,
+}
+
+// How the caller proves they own the account before its password is set
+#[derive(Debug, PartialEq, Eq)]
+enum Reauthentication {
+    // The current password, given its hash
+    Password(String),
+    // A fresh signature from one of the account's wallets
+    Wallet,
+    // Nothing to prove it with here; the password reset email sets the first password
+    ResetEmail,
+}
+
+// List the wallets linked to the current user
+pub async fn list_wallets(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match linked_wallets(&client, auth_user.user_id).await {
+        Ok(wallets) => HttpResponse::Ok().json(wallets),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Link a wallet to the current user; ownership is proven by signing a challenge
+// from `/api/auth/web3/challenge`
+pub async fn link_wallet(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    link_data: web::Json<Web3LoginRequest>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    if let Err(e) = verify_wallet_proof(
+        &client,
+        &data.config,
+        &link_data.wallet_address,
+        &link_data.message,
+        &link_data.signature,
+    )
+    .await
+    {
+        return e.error_response();
+    }
+    
+    let owner = match client
+        .query_one(
+            "WITH inserted AS (
+                 INSERT INTO user_wallets (wallet_address, user_id) VALUES ($1, $2)
+                 ON CONFLICT (wallet_address) DO NOTHING
+                 RETURNING user_id
+             )
+             SELECT user_id FROM inserted
+             UNION ALL
+             SELECT user_id FROM user_wallets WHERE wallet_address = $1
+             LIMIT 1",
+            &[&link_data.wallet_address, &auth_user.user_id],
+        )
+        .await
+    {
+        Ok(row) => row.get::<_, Uuid>("user_id"),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    if owner != auth_user.user_id {
+        return HttpResponse::Conflict().json(json!({
+            "error": "Wallet is already linked to another account"
+        }));
+    }
+    
+    // The first linked wallet becomes the primary one
+    if let Err(e) = client
+        .execute(
+            "UPDATE users SET web3_wallet = $1, updated_at = NOW() WHERE id = $2 AND web3_wallet IS NULL",
+            &[&link_data.wallet_address, &auth_user.user_id],
+        )
+        .await
+    {
+        return HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
+    
+    match linked_wallets(&client, auth_user.user_id).await {
+        Ok(wallets) => HttpResponse::Created().json(wallets),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Unlink a wallet from the current user
+pub async fn unlink_wallet(
+    auth_user: AuthenticatedUser,
+    wallet_address: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match unlink(&mut client, auth_user.user_id, &wallet_address).await {
+        Ok(UnlinkOutcome::Unlinked) => HttpResponse::Ok().json(json!({
+            "message": "Wallet unlinked"
+        })),
+        Ok(UnlinkOutcome::NotLinked) => HttpResponse::NotFound().json(json!({
+            "error": "Wallet is not linked to this account"
+        })),
+        Ok(UnlinkOutcome::LastSignInMethod) => HttpResponse::BadRequest().json(json!({
+            "error": "Set a password or link another wallet before removing your only wallet"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Set a password on a wallet-only account, or change the existing one. The
+// caller proves ownership with the current password or a wallet signature.
+// Other sessions are signed out; the caller gets a fresh access token.
+pub async fn set_password(
+    req: HttpRequest,
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    password_data: web::Json<SetPasswordRequest>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    if let Err(message) = validate_new_password(&password_data.new_password) {
+        return HttpResponse::BadRequest().json(json!({
+            "error": message
+        }));
+    }
+    
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let row = match client
+        .query_one(
+            "SELECT email, password_hash,
+                    (SELECT COUNT(*) FROM user_wallets w WHERE w.user_id = users.id) AS wallet_count
+             FROM users WHERE id = $1",
+            &[&auth_user.user_id],
+        )
+        .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // A stolen access token alone must not be enough to take over the account
+    match reauthentication_for(row.get("password_hash"), row.get("wallet_count")) {
+        Reauthentication::Password(current_hash) => {
+            // Guesses count against the same lockout as password logins
+            let email: String = row.get("email");
+            let ip_address = client_ip(&req, &data.config);
+            match check_login_allowed(&client, &email, &ip_address).await {
+                Ok(Ok(())) => (),
+                Ok(Err(throttled)) => return too_many_attempts(&throttled),
+                Err(e) => {
+                    return HttpResponse::InternalServerError().json(json!({
+                        "error": format!("Database error: {}", e)
+                    }));
+                }
+            }
+            
+            match current_password_matches(&current_hash, password_data.current_password.as_deref()) {
+                Ok(true) => {
+                    if let Err(e) = record_login_success(&client, &email, &ip_address).await {
+                        return HttpResponse::InternalServerError().json(json!({
+                            "error": format!("Database error: {}", e)
+                        }));
+                    }
+                }
+                Ok(false) => {
+                    if let Err(e) = record_login_failure(&client, &email, &ip_address, &data.config).await {
+                        return HttpResponse::InternalServerError().json(json!({
+                            "error": format!("Database error: {}", e)
+                        }));
+                    }
+                    return HttpResponse::BadRequest().json(json!({
+                        "error": "Current password is incorrect"
+                    }));
+                }
+                Err(_) => {
+                    return HttpResponse::InternalServerError().json(json!({
+                        "error": "Password verification failed"
+                    }));
+                }
+            }
+        }
+        Reauthentication::Wallet => {
+            let proof = match &password_data.wallet_proof {
+                Some(proof) => proof,
+                None => {
+                    return HttpResponse::BadRequest().json(json!({
+                        "error": "Sign a new challenge with one of your wallets to set a password"
+                    }));
+                }
+            };
+            
+            match client
+                .query_one(
+                    "SELECT EXISTS(SELECT 1 FROM user_wallets WHERE wallet_address = $1 AND user_id = $2)",
+                    &[&proof.wallet_address, &auth_user.user_id],
+                )
+                .await
+            {
+                Ok(row) if row.get::<_, bool>(0) => (),
+                Ok(_) => {
+                    return HttpResponse::BadRequest().json(json!({
+                        "error": "Wallet is not linked to this account"
+                    }));
+                }
+                Err(e) => {
+                    return HttpResponse::InternalServerError().json(json!({
+                        "error": format!("Database error: {}", e)
+                    }));
+                }
+            }
+            
+            if let Err(e) = verify_wallet_proof(
+                &client,
+                &data.config,
+                &proof.wallet_address,
+                &proof.message,
+                &proof.signature,
+            )
+            .await
+            {
+                return e.error_response();
+            }
+        }
+        Reauthentication::ResetEmail => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Use the password reset email to set a password on this account"
+            }));
+        }
+    }
+    
+    let password_hash = match hash(&password_data.new_password, DEFAULT_COST) {
+        Ok(hashed) => hashed,
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": "Failed to hash password"
+            }));
+        }
+    };
+    
+    match update_password(&mut client, &auth_user, &password_hash, &data).await {
+        Ok(token) => HttpResponse::Ok().json(json!({
+            "message": "Password updated; other sessions have been signed out",
+            "token": token
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": e
+        })),
+    }
+}
+
+// List the current user's API keys, including revoked and expired ones
+pub async fn list_api_keys(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match client
+        .query(
+            "SELECT id, name, prefix, scopes, expires_at, last_used_at, created_at, revoked_at
+             FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC",
+            &[&auth_user.user_id],
+        )
+        .await
+    {
+        Ok(rows) => {
+            let keys: Vec<ApiKey> = rows.iter().map(api_key_from_row).collect();
+            HttpResponse::Ok().json(keys)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Create an API key. Scopes are permission names and can't exceed what the user holds.
+pub async fn create_api_key(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    key_data: web::Json<CreateApiKeyRequest>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let name = key_data.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "Name must be between 1 and 100 characters"
+        }));
+    }
+    
+    let scopes = match parse_scopes(&key_data.scopes) {
+        Ok(scopes) => scopes,
+        Err(scope) => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": format!("Unknown scope: {}", scope)
+            }));
+        }
+    };
+    
+    if let Some(scope) = scopes.iter().find(|scope| !auth_user.has_permission(**scope)) {
+        return HttpResponse::Forbidden().json(json!({
+            "error": format!("You don't have the {} permission", scope.as_str())
+        }));
+    }
+    
+    let expires_at = match key_data.expires_in_days {
+        None => None,
+        Some(days) if (1..=MAX_API_KEY_TTL_DAYS).contains(&days) => Some(Utc::now() + Duration::days(days)),
+        Some(_) => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": format!("Expiry must be between 1 and {} days", MAX_API_KEY_TTL_DAYS)
+            }));
+        }
+    };
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let new_key = generate_api_key();
+    let id = match insert_api_key(&client, auth_user.user_id, name, &new_key, &scopes, expires_at).await {
+        Ok(id) => id,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match client
+        .query_one(
+            "SELECT id, name, prefix, scopes, expires_at, last_used_at, created_at, revoked_at
+             FROM api_keys WHERE id = $1",
+            &[&id],
+        )
+        .await
+    {
+        Ok(row) => HttpResponse::Created().json(ApiKeyCreatedResponse {
+            key: new_key.key,
+            api_key: api_key_from_row(&row),
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Revoke one of the current user's API keys
+pub async fn revoke_api_key(
+    auth_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match client
+        .execute(
+            "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            &[&id.into_inner(), &auth_user.user_id],
+        )
+        .await
+    {
+        Ok(0) => HttpResponse::NotFound().json(json!({
+            "error": "API key not found"
+        })),
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "API key revoked"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// List the current user's active sessions, most recently used first
+pub async fn list_sessions(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // A session is active while its family still has an unrevoked, unexpired refresh token
+    match client
+        .query(
+            "SELECT s.id, s.user_agent, s.ip_address, s.created_at, s.last_seen_at
+             FROM sessions s
+             WHERE s.user_id = $1 AND EXISTS(
+                 SELECT 1 FROM refresh_tokens rt
+                 WHERE rt.family_id = s.id AND rt.revoked_at IS NULL AND rt.rotated_at IS NULL AND rt.expires_at > NOW()
+             )
+             ORDER BY s.last_seen_at DESC",
+            &[&auth_user.user_id],
+        )
+        .await
+    {
+        Ok(rows) => {
+            let sessions: Vec<SessionInfo> = rows
+                .iter()
+                .map(|row| {
+                    let id: Uuid = row.get("id");
+                    SessionInfo {
+                        id,
+                        user_agent: row.get("user_agent"),
+                        ip_address: row.get("ip_address"),
+                        created_at: row.get("created_at"),
+                        last_seen_at: row.get("last_seen_at"),
+                        current: id == auth_user.session_id,
+                    }
+                })
+                .collect();
+            
+            HttpResponse::Ok().json(sessions)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Sign out one of the current user's sessions
+pub async fn revoke_user_session(
+    auth_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match revoke_session(&client, auth_user.user_id, id.into_inner()).await {
+        Ok(0) => HttpResponse::NotFound().json(json!({
+            "error": "Session not found"
+        })),
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Session revoked"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": e.to_string()
+        })),
+    }
+}
+
+// Sign out every session except the one making the request, and revoke all API keys
+pub async fn revoke_other_user_sessions(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    if let Err(e) = revoke_other_sessions(&client, auth_user.user_id, Some(auth_user.session_id)).await {
+        return HttpResponse::InternalServerError().json(json!({
+            "error": e.to_string()
+        }));
+    }
+    
+    match revoke_user_api_keys(&client, auth_user.user_id).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Other sessions have been signed out and API keys revoked"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Download everything stored about the current user as a JSON file
+pub async fn export_account(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match export_user_data(&client, &data.mongo_client, auth_user.user_id).await {
+        Ok(Some(export)) => HttpResponse::Ok()
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"account-export-{}.json\"", auth_user.user_id),
+            ))
+            .json(export),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": e.to_string()
+        })),
+    }
+}
+
+// Permanently delete the current user's account and personal data
+pub async fn delete_account(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    delete_data: web::Json<DeleteAccountRequest>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let user_row = match client
+        .query_opt(
+            "SELECT email, password_hash, is_admin FROM users WHERE id = $1",
+            &[&auth_user.user_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "User not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // Admins are demoted by another admin first, so the site is never left without one
+    if user_row.get::<_, bool>("is_admin") {
+        return HttpResponse::Conflict().json(json!({
+            "error": "Admin accounts cannot be deleted; ask another admin to remove your admin role first"
+        }));
+    }
+    
+    // Confirm with the password, or the email for accounts without one
+    let confirmed = match user_row.get::<_, Option<String>>("password_hash") {
+        Some(password_hash) => {
+            let password = delete_data.password.as_deref().unwrap_or_default();
+            verify(password, &password_hash).unwrap_or(false)
+        }
+        None => {
+            let email: String = user_row.get("email");
+            delete_data
+                .confirm_email
+                .as_deref()
+                .is_some_and(|confirm| confirm.trim().eq_ignore_ascii_case(&email))
+        }
+    };
+    
+    if !confirmed {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "Confirm with your password, or your email address if you don't have one"
+        }));
+    }
+    
+    match check_deletion_allowed(&client, auth_user.user_id).await {
+        Ok(Ok(())) => (),
+        Ok(Err(DeletionBlocked::OwnsCourses(count))) => {
+            return HttpResponse::Conflict().json(json!({
+                "error": format!("You still own {} course(s); transfer or delete them first", count)
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": e.to_string()
+            }));
+        }
+    }
+    
+    if let Err(e) = delete_user_data(&mut client, &data.mongo_client, auth_user.user_id).await {
+        return HttpResponse::InternalServerError().json(json!({
+            "error": e.to_string()
+        }));
+    }
+    
+    // The user row is gone, so the id is only kept in the details
+    let entry = AuditEntry {
+        event: "account_deleted",
+        user_id: None,
+        actor_id: None,
+        ip_address: None,
+        details: json!({ "user_id": auth_user.user_id }),
+    };
+    
+    match audit::record(&client, entry).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Your account has been deleted"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+fn api_key_from_row(row: &tokio_postgres::Row) -> ApiKey {
+    ApiKey {
+        id: row.get("id"),
+        name: row.get("name"),
+        prefix: row.get("prefix"),
+        scopes: row.get("scopes"),
+        expires_at: row.get("expires_at"),
+        last_used_at: row.get("last_used_at"),
+        created_at: row.get("created_at"),
+        revoked_at: row.get("revoked_at"),
+    }
+}
+
+async fn linked_wallets(
+    client: &Client,
+    user_id: Uuid,
+) -> Result<Vec<LinkedWallet>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            "SELECT w.wallet_address, w.linked_at, u.web3_wallet IS NOT DISTINCT FROM w.wallet_address AS is_primary
+             FROM user_wallets w
+             JOIN users u ON u.id = w.user_id
+             WHERE w.user_id = $1
+             ORDER BY w.linked_at",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| LinkedWallet {
+            wallet_address: row.get("wallet_address"),
+            is_primary: row.get("is_primary"),
+            linked_at: row.get("linked_at"),
+        })
+        .collect())
+}
+
+async fn unlink(
+    client: &mut Client,
+    user_id: Uuid,
+    wallet_address: &str,
+) -> Result<UnlinkOutcome, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    // Lock the user so concurrent unlinks cannot both pass the last-method check
+    let user_row = transaction
+        .query_one(
+            "SELECT password_hash IS NOT NULL AS has_password, web3_wallet FROM users WHERE id = $1 FOR UPDATE",
+            &[&user_id],
+        )
+        .await?;
+    let has_password: bool = user_row.get("has_password");
+    let primary_wallet: Option<String> = user_row.get("web3_wallet");
+
+    let wallet_count: i64 = transaction
+        .query_one(
+            "SELECT COUNT(*) FROM user_wallets WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?
+        .get(0);
+
+    let deleted = transaction
+        .execute(
+            "DELETE FROM user_wallets WHERE wallet_address = $1 AND user_id = $2",
+            &[&wallet_address, &user_id],
+        )
+        .await?;
+
+    match unlink_outcome(deleted, has_password, wallet_count) {
+        UnlinkOutcome::Unlinked => (),
+        outcome => return Ok(outcome),
+    }
+
+    // Promote the most recently linked remaining wallet, if any
+    if primary_wallet.as_deref() == Some(wallet_address) {
+        transaction
+            .execute(
+                "UPDATE users SET updated_at = NOW(), web3_wallet = (
+                     SELECT wallet_address FROM user_wallets WHERE user_id = $1 ORDER BY linked_at DESC LIMIT 1
+                 ) WHERE id = $1",
+                &[&user_id],
+            )
+            .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(UnlinkOutcome::Unlinked)
+}
+
+// `wallet_count` counts the account's wallets before `deleted` were removed
+fn unlink_outcome(deleted: u64, has_password: bool, wallet_count: i64) -> UnlinkOutcome {
+    if deleted == 0 {
+        UnlinkOutcome::NotLinked
+    } else if !has_password && wallet_count <= 1 {
+        UnlinkOutcome::LastSignInMethod
+    } else {
+        UnlinkOutcome::Unlinked
+    }
+}
+
+fn reauthentication_for(password_hash: Option<String>, wallet_count: i64) -> Reauthentication {
+    match password_hash {
+        Some(password_hash) => Reauthentication::Password(password_hash),
+        None if wallet_count > 0 => Reauthentication::Wallet,
+        None => Reauthentication::ResetEmail,
+    }
+}
+
+// Changing an existing password requires knowing it
+fn current_password_matches(
+    current_hash: &str,
+    presented: Option<&str>,
+) -> Result<bool, bcrypt::BcryptError> {
+    match presented {
+        Some(password) => verify(password, current_hash),
+        None => Ok(false),
+    }
+}
+
+// Store the new password hash (which bumps token_version), revoke every other
+// session and all API keys, and issue a new access token for the current one
+async fn update_password(
+    client: &mut Client,
+    auth_user: &AuthenticatedUser,
+    password_hash: &str,
+    data: &AppState,
+) -> Result<String, String> {
+    let transaction = client.transaction().await.map_err(|e| format!("Database error: {}", e))?;
+
+    let row = transaction
+        .query_one(
+            "UPDATE users SET password_hash = $1, updated_at = NOW() WHERE id = $2
+             RETURNING id, email, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at, token_version",
+            &[&password_hash, &auth_user.user_id],
+        )
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    revoke_other_sessions(&transaction, auth_user.user_id, Some(auth_user.session_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    revoke_user_api_keys(&transaction, auth_user.user_id)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let user = User {
+        id: row.get("id"),
+        email: row.get("email"),
+        password_hash: None, // Don't return password hash
+        full_name: row.get("full_name"),
+        is_admin: row.get("is_admin"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+        web3_wallet: row.get("web3_wallet"),
+        email_verified_at: row.get("email_verified_at"),
+    };
+    let token = generate_token(&user, auth_user.session_id, row.get("token_version"), &data.config)
+        .map_err(|e| format!("Failed to generate token: {}", e))?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlink_keeps_a_way_to_sign_in() {
+        assert_eq!(unlink_outcome(0, false, 1), UnlinkOutcome::NotLinked);
+        assert_eq!(unlink_outcome(1, false, 1), UnlinkOutcome::LastSignInMethod);
+        assert_eq!(unlink_outcome(1, false, 2), UnlinkOutcome::Unlinked);
+        assert_eq!(unlink_outcome(1, true, 1), UnlinkOutcome::Unlinked);
+    }
+
+    #[test]
+    fn test_setting_a_password_needs_reauthentication() {
+        assert_eq!(
+            reauthentication_for(Some("hash".to_string()), 2),
+            Reauthentication::Password("hash".to_string())
+        );
+        assert_eq!(reauthentication_for(None, 1), Reauthentication::Wallet);
+        assert_eq!(reauthentication_for(None, 0), Reauthentication::ResetEmail);
+    }
+
+    #[test]
+    fn test_current_password_is_required() {
+        let current_hash = hash("correct horse", 4).unwrap();
+
+        assert_eq!(current_password_matches(&current_hash, Some("correct horse")).ok(), Some(true));
+        assert_eq!(current_password_matches(&current_hash, Some("wrong horse")).ok(), Some(false));
+        assert_eq!(current_password_matches(&current_hash, None).ok(), Some(false));
+    }
+}
@@ -2,8 +2,16 @@ use actix_web::{web, HttpResponse, Responder};
 use serde_json::json;
 use uuid::Uuid;
 
+use crate::db::user_data::{check_deletion_allowed, delete_user_data, DeletionBlocked};
+use crate::media::gc::collect_garbage;
 use crate::middleware::auth::AdminUser;
-use crate::models::user::User;
+use crate::models::user::{ClearLockoutRequest, LoginLockout, RoleSummary, User, UserRoles};
+use crate::security::api_keys::revoke_user_api_keys;
+use crate::security::audit::{self, AuditEntry};
+use crate::security::email_verification::is_valid_email;
+use crate::security::refresh::revoke_other_sessions;
+use crate::security::roles::{self, ADMIN_ROLE};
+use crate::security::throttle::normalize_email;
 use crate::AppState;
 
 // Get all users (admin only)
@@ -25,7 +33,7 @@ pub async fn get_all_users(
     // Query for all users
     match client
         .query(
-            "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet FROM users ORDER BY created_at DESC",
+            "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at FROM users ORDER BY created_at DESC",
             &[],
         )
         .await
@@ -42,6 +50,7 @@ pub async fn get_all_users(
                     created_at: row.get("created_at"),
                     updated_at: row.get("updated_at"),
                     web3_wallet: row.get("web3_wallet"),
+                    email_verified_at: row.get("email_verified_at"),
                 })
                 .collect();
             
@@ -75,7 +84,7 @@ pub async fn get_user_by_id(
     // Query for the user
     match client
         .query_opt(
-            "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet FROM users WHERE id = $1",
+            "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at FROM users WHERE id = $1",
             &[&id.into_inner()],
         )
         .await
@@ -91,6 +100,7 @@ pub async fn get_user_by_id(
                     created_at: row.get("created_at"),
                     updated_at: row.get("updated_at"),
                     web3_wallet: row.get("web3_wallet"),
+                    email_verified_at: row.get("email_verified_at"),
                 };
                 
                 HttpResponse::Ok().json(user)
@@ -117,6 +127,7 @@ pub async fn update_user(
     data: web::Data<AppState>,
 ) -> impl Responder {
     let db = &data.pg_pool;
+    let id = id.into_inner();
     
     let client = match db.get().await {
         Ok(client) => client,
@@ -131,7 +142,7 @@ pub async fn update_user(
     let user_exists = match client
         .query_one(
             "SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)",
-            &[&id.into_inner()],
+            &[&id],
         )
         .await
     {
@@ -149,52 +160,88 @@ pub async fn update_user(
         }));
     }
     
-    // Build update query
+    let full_name = user_data.get("full_name").and_then(|v| v.as_str());
+    let email = user_data.get("email").and_then(|v| v.as_str()).map(normalize_email);
+    let is_admin = user_data.get("is_admin").and_then(|v| v.as_bool());
+    
+    if let Err(message) = check_admin_update(&user_data) {
+        return HttpResponse::BadRequest().json(json!({ "error": message }));
+    }
+    
+    if let Some(email) = &email {
+        if !is_valid_email(email) {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Invalid email address"
+            }));
+        }
+        
+        let email_taken = match client
+            .query_one(
+                "SELECT EXISTS(SELECT 1 FROM users WHERE LOWER(email) = $1 AND id <> $2)",
+                &[email, &id],
+            )
+            .await
+        {
+            Ok(row) => row.get::<_, bool>(0),
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "error": format!("Database error: {}", e)
+                }));
+            }
+        };
+        
+        if email_taken {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Email already exists"
+            }));
+        }
+    }
+    
+    // Build update query. Changing `is_admin` bumps the user's token_version
+    // (see the users_bump_token_version trigger), so existing tokens stop working.
     let mut query = String::from("UPDATE users SET updated_at = NOW()");
     let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = Vec::new();
     let mut param_count = 1;
     
-    if let Some(full_name) = user_data.get("full_name").and_then(|v| v.as_str()) {
+    if let Some(full_name) = &full_name {
         query.push_str(&format!(", full_name = ${}", param_count));
-        params.push(&full_name);
+        params.push(full_name);
         param_count += 1;
     }
     
-    if let Some(email) = user_data.get("email").and_then(|v| v.as_str()) {
+    if let Some(email) = &email {
         query.push_str(&format!(", email = ${}", param_count));
-        params.push(&email);
+        params.push(email);
         param_count += 1;
     }
     
-    if let Some(is_admin) = user_data.get("is_admin").and_then(|v| v.as_bool()) {
+    if let Some(is_admin) = &is_admin {
         query.push_str(&format!(", is_admin = ${}", param_count));
-        params.push(&is_admin);
-        param_count += 1;
-    }
-    
-    if let Some(web3_wallet) = user_data.get("web3_wallet").and_then(|v| v.as_str()) {
-        query.push_str(&format!(", web3_wallet = ${}", param_count));
-        params.push(&web3_wallet);
+        params.push(is_admin);
         param_count += 1;
     }
     
     query.push_str(&format!(" WHERE id = ${}", param_count));
     params.push(&id);
     
-    // Execute update
-    match client.execute(&query, &params).await {
-        Ok(_) => (),
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "error": format!("Database error: {}", e)
-            }));
+    // Execute update. A role change also revokes the user's API keys.
+    let updated = async {
+        client.execute(&query, &params).await?;
+        if is_admin.is_some() {
+            revoke_user_api_keys(&client, id).await?;
         }
+        Ok::<_, tokio_postgres::Error>(())
     };
+    if let Err(e) = updated.await {
+        return HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
     
     // Query for the updated user to return
     match client
         .query_one(
-            "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet FROM users WHERE id = $1",
+            "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at FROM users WHERE id = $1",
             &[&id],
         )
         .await
@@ -209,6 +256,7 @@ pub async fn update_user(
                 created_at: row.get("created_at"),
                 updated_at: row.get("updated_at"),
                 web3_wallet: row.get("web3_wallet"),
+                email_verified_at: row.get("email_verified_at"),
             };
             
             HttpResponse::Ok().json(user)
@@ -221,15 +269,15 @@ pub async fn update_user(
     }
 }
 
-// Delete user (admin only)
+// Delete user (admin only), along with their documents in MongoDB
 pub async fn delete_user(
-    _admin_user: AdminUser,
+    admin_user: AdminUser,
     id: web::Path<Uuid>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let db = &data.pg_pool;
     
-    let client = match db.get().await {
+    let mut client = match db.get().await {
         Ok(client) => client,
         Err(e) => {
             return HttpResponse::InternalServerError().json(json!({
@@ -238,28 +286,53 @@ pub async fn delete_user(
         }
     };
     
-    // Delete user
-    match client
-        .execute("DELETE FROM users WHERE id = $1", &[&id.into_inner()])
-        .await
-    {
-        Ok(count) => {
-            if count == 0 {
-                HttpResponse::NotFound().json(json!({
-                    "error": "User not found"
-                }))
-            } else {
-                HttpResponse::Ok().json(json!({
-                    "message": "User deleted successfully"
-                }))
-            }
+    let user_id = id.into_inner();
+    
+    match check_deletion_allowed(&client, user_id).await {
+        Ok(Ok(())) => (),
+        Ok(Err(DeletionBlocked::OwnsCourses(count))) => {
+            return HttpResponse::Conflict().json(json!({
+                "error": format!("User still owns {} course(s); transfer or delete them first", count)
+            }));
         }
         Err(e) => {
-            HttpResponse::InternalServerError().json(json!({
-                "error": format!("Database error: {}", e)
-            }))
+            return HttpResponse::InternalServerError().json(json!({
+                "error": e.to_string()
+            }));
+        }
+    }
+    
+    // Delete user; any tokens they still hold fail the extractor's user lookup
+    match delete_user_data(&mut client, &data.mongo_client, user_id).await {
+        Ok(false) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "User not found"
+            }));
+        }
+        Ok(true) => (),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": e.to_string()
+            }));
         }
     }
+    
+    let entry = AuditEntry {
+        event: "account_deleted",
+        user_id: None,
+        actor_id: Some(admin_user.user_id),
+        ip_address: None,
+        details: json!({ "user_id": user_id }),
+    };
+    
+    match audit::record(&client, entry).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "User deleted successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
 }
 
 // Get admin statistics
@@ -369,3 +442,464 @@ pub async fn get_stats(
         "comment_count": comment_count
     }))
 }
+
+// List active login lockouts (admin only)
+pub async fn get_lockouts(
+    _admin_user: AdminUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match client
+        .query(
+            "SELECT email, ip_address, failed_count, last_failed_at, locked_until FROM login_throttles
+             WHERE locked_until > NOW() ORDER BY locked_until DESC",
+            &[],
+        )
+        .await
+    {
+        Ok(rows) => {
+            let lockouts: Vec<LoginLockout> = rows
+                .iter()
+                .map(|row| LoginLockout {
+                    email: row.get("email"),
+                    ip_address: row.get("ip_address"),
+                    failed_count: row.get("failed_count"),
+                    last_failed_at: row.get("last_failed_at"),
+                    locked_until: row.get("locked_until"),
+                })
+                .collect();
+            
+            HttpResponse::Ok().json(lockouts)
+        }
+        Err(e) => {
+            HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }))
+        }
+    }
+}
+
+// Clear a login lockout and its failure count (admin only)
+pub async fn clear_lockout(
+    admin_user: AdminUser,
+    clear_data: web::Json<ClearLockoutRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let email = normalize_email(&clear_data.email);
+    let cleared = match client
+        .execute(
+            "DELETE FROM login_throttles WHERE email = $1 AND ($2::VARCHAR IS NULL OR ip_address = $2)",
+            &[&email, &clear_data.ip_address],
+        )
+        .await
+    {
+        Ok(count) => count,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    if cleared == 0 {
+        return HttpResponse::NotFound().json(json!({
+            "error": "No lockout found"
+        }));
+    }
+    
+    let user_id = match client
+        .query_opt("SELECT id FROM users WHERE LOWER(email) = $1", &[&email])
+        .await
+    {
+        Ok(row) => row.map(|row| row.get("id")),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let entry = AuditEntry {
+        event: "login_lockout_cleared",
+        user_id,
+        actor_id: Some(admin_user.user_id),
+        ip_address: None,
+        details: json!({
+            "email": email,
+            "ip_address": clear_data.ip_address,
+        }),
+    };
+    
+    match audit::record(&client, entry).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Lockout cleared"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// List roles and the permissions they grant (admin only)
+pub async fn get_roles(
+    _admin_user: AdminUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match client
+        .query(
+            "SELECT r.name, r.description,
+                    ARRAY(SELECT permission FROM role_permissions WHERE role = r.name ORDER BY permission)::text[] AS permissions
+             FROM roles r ORDER BY r.name",
+            &[],
+        )
+        .await
+    {
+        Ok(rows) => {
+            let roles: Vec<RoleSummary> = rows
+                .iter()
+                .map(|row| RoleSummary {
+                    name: row.get("name"),
+                    description: row.get("description"),
+                    permissions: row.get("permissions"),
+                })
+                .collect();
+            
+            HttpResponse::Ok().json(roles)
+        }
+        Err(e) => {
+            HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }))
+        }
+    }
+}
+
+// Get the roles a user holds (admin only)
+pub async fn get_user_roles(
+    _admin_user: AdminUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let user_id = id.into_inner();
+    
+    match roles::roles_for_user(&client, user_id).await {
+        Ok(Some(roles)) => HttpResponse::Ok().json(UserRoles { user_id, roles }),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Give a user a role (admin only)
+pub async fn grant_user_role(
+    admin_user: AdminUser,
+    path: web::Path<(Uuid, String)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let (user_id, role) = path.into_inner();
+    change_user_role(&admin_user, user_id, &role, true, &data).await
+}
+
+// Take a role away from a user (admin only)
+pub async fn revoke_user_role(
+    admin_user: AdminUser,
+    path: web::Path<(Uuid, String)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let (user_id, role) = path.into_inner();
+    
+    // Keep at least the acting admin able to undo mistakes
+    if role == ADMIN_ROLE && user_id == admin_user.user_id {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "You cannot remove your own admin role"
+        }));
+    }
+    
+    change_user_role(&admin_user, user_id, &role, false, &data).await
+}
+
+async fn change_user_role(
+    admin_user: &AdminUser,
+    user_id: Uuid,
+    role: &str,
+    grant: bool,
+    data: &web::Data<AppState>,
+) -> HttpResponse {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match roles::role_exists(&client, role).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Role not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    }
+    
+    let user_exists = match client
+        .query_one("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)", &[&user_id])
+        .await
+    {
+        Ok(row) => row.get::<_, bool>(0),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    if !user_exists {
+        return HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        }));
+    }
+    
+    let changed = if grant {
+        roles::grant_role(&client, user_id, role, admin_user.user_id).await
+    } else {
+        roles::revoke_role(&client, user_id, role).await
+    };
+    
+    match changed {
+        Ok(true) => {
+            // Keys carry permissions from the old set of roles
+            if let Err(e) = revoke_user_api_keys(&client, user_id).await {
+                return HttpResponse::InternalServerError().json(json!({
+                    "error": format!("Database error: {}", e)
+                }));
+            }
+            
+            let entry = AuditEntry {
+                event: if grant { "role_granted" } else { "role_revoked" },
+                user_id: Some(user_id),
+                actor_id: Some(admin_user.user_id),
+                ip_address: None,
+                details: json!({ "role": role }),
+            };
+            
+            if let Err(e) = audit::record(&client, entry).await {
+                return HttpResponse::InternalServerError().json(json!({
+                    "error": format!("Database error: {}", e)
+                }));
+            }
+        }
+        Ok(false) => (),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    }
+    
+    match roles::roles_for_user(&client, user_id).await {
+        Ok(Some(roles)) => HttpResponse::Ok().json(UserRoles { user_id, roles }),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Sign a user out of every session and revoke their API keys (admin only)
+pub async fn force_logout_user(
+    admin_user: AdminUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let user_id = id.into_inner();
+    
+    let user_exists = match client
+        .query_one("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)", &[&user_id])
+        .await
+    {
+        Ok(row) => row.get::<_, bool>(0),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    if !user_exists {
+        return HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        }));
+    }
+    
+    let revoked = match revoke_other_sessions(&client, user_id, None).await {
+        Ok(count) => count,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+    
+    let api_keys_revoked = match revoke_user_api_keys(&client, user_id).await {
+        Ok(count) => count,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let entry = AuditEntry {
+        event: "sessions_revoked",
+        user_id: Some(user_id),
+        actor_id: Some(admin_user.user_id),
+        ip_address: None,
+        details: json!({ "refresh_tokens_revoked": revoked, "api_keys_revoked": api_keys_revoked }),
+    };
+    
+    match audit::record(&client, entry).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "User has been signed out of all sessions and their API keys revoked"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Remove uploads nothing references any more (admin only)
+pub async fn collect_media_garbage(
+    admin_user: AdminUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let report = match collect_garbage(
+        &client,
+        &data.mongo_client,
+        data.media_store.as_ref(),
+        &data.config,
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": e
+            }));
+        }
+    };
+    
+    let entry = AuditEntry {
+        event: "media_collected",
+        user_id: None,
+        actor_id: Some(admin_user.user_id),
+        ip_address: None,
+        details: json!({ "deleted": report.deleted, "failed": report.failed }),
+    };
+    
+    match audit::record(&client, entry).await {
+        Ok(_) => HttpResponse::Ok().json(report),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Wallets are only linked by their owner signing a challenge (see
+// user_wallets), so an admin update can't set one directly
+fn check_admin_update(user_data: &serde_json::Value) -> Result<(), &'static str> {
+    if user_data.get("web3_wallet").is_some() {
+        return Err("A wallet can only be linked by its owner with a signed challenge");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_update_cannot_set_wallets() {
+        assert!(check_admin_update(&json!({ "full_name": "Ada", "is_admin": true })).is_ok());
+        assert!(check_admin_update(&json!({ "web3_wallet": "GvHeR432g7MjN9uKyX3Dzg66TqwrEWgANLnnFZXMeyyj" })).is_err());
+        assert!(check_admin_update(&json!({ "web3_wallet": null })).is_err());
+    }
+}
@@ -1,19 +1,48 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder, ResponseError};
 use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
+use log::error;
 use uuid::Uuid;
 
+use crate::config::AppConfig;
 use crate::models::user::{
-    AuthResponse, CreateUserRequest, LoginRequest, User, Web3LoginRequest,
+    AuthResponse, CreateUserRequest, ForgotPasswordRequest, LoginRequest, MfaChallengeResponse,
+    OAuthCallbackRequest, OAuthStartResponse, RefreshTokenRequest, ResetPasswordRequest, User,
+    VerifyEmailRequest, Web3ChallengeRequest, Web3ChallengeResponse, Web3LoginRequest,
 };
-use crate::middleware::auth::generate_token;
+use crate::middleware::auth::AuthenticatedUser;
+use crate::security::email_verification::{
+    is_valid_email, issue_verification_token, verification_email, verify_email_with_token,
+};
+use crate::security::mfa::{is_mfa_enabled, start_mfa_challenge};
+use crate::security::oauth::{resolve_user, OAuthProviderKind, UnresolvedIdentity};
+use crate::security::password_reset::{
+    issue_reset_token, reset_email, reset_password_with_token, validate_new_password,
+};
+use crate::security::refresh::{revoke_session, rotate_session, start_session, SessionDevice};
+use crate::security::throttle::{
+    check_login_allowed, client_ip, normalize_email, record_login_failure, record_login_success,
+    Throttled,
+};
+use crate::security::tokens::generate_opaque_token;
+use crate::web3::signature::{is_valid_wallet_address, verify_wallet_proof};
+use crate::web3::siws::SiwsMessage;
 use crate::AppState;
 
 // Register a new user
 pub async fn register(
+    req: HttpRequest,
     data: web::Data<AppState>,
     user_data: web::Json<CreateUserRequest>,
 ) -> impl Responder {
+    // Stored lowercased; addresses are unique regardless of case
+    let email = normalize_email(&user_data.email);
+    if !is_valid_email(&email) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid email address"
+        }));
+    }
+    
     let db = &data.pg_pool;
     
     let client = match db.get().await {
@@ -28,8 +57,8 @@ pub async fn register(
     // Check if user already exists
     let email_exists = match client
         .query_one(
-            "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)",
-            &[&user_data.email],
+            "SELECT EXISTS(SELECT 1 FROM users WHERE LOWER(email) = $1)",
+            &[&email],
         )
         .await
     {
@@ -49,9 +78,15 @@ pub async fn register(
     
     // Check if wallet already exists (if provided)
     if let Some(wallet) = &user_data.web3_wallet {
+        if !is_valid_wallet_address(wallet) {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid wallet address"
+            }));
+        }
+        
         let wallet_exists = match client
             .query_one(
-                "SELECT EXISTS(SELECT 1 FROM users WHERE web3_wallet = $1)",
+                "SELECT EXISTS(SELECT 1 FROM user_wallets WHERE wallet_address = $1)",
                 &[&wallet],
             )
             .await
@@ -69,6 +104,19 @@ pub async fn register(
                 "error": "Wallet address already registered"
             }));
         }
+        
+        // Only the wallet's owner may register with it
+        let (message, signature) = match (&user_data.wallet_message, &user_data.wallet_signature) {
+            (Some(message), Some(signature)) => (message, signature),
+            _ => {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "A wallet must be proven with a signed sign-in challenge"
+                }));
+            }
+        };
+        if let Err(e) = verify_wallet_proof(&client, &data.config, wallet, message, signature).await {
+            return e.error_response();
+        }
     }
     
     // Hash password if provided
@@ -103,7 +151,7 @@ pub async fn register(
             "INSERT INTO users (id, email, password_hash, full_name, is_admin, created_at, updated_at, web3_wallet) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
             &[
                 &user_id,
-                &user_data.email,
+                &email,
                 &password_hash,
                 &user_data.full_name,
                 &false, // is_admin
@@ -125,34 +173,57 @@ pub async fn register(
     // Create user object
     let user = User {
         id: user_id,
-        email: user_data.email.clone(),
+        email,
         password_hash: None, // Don't return password hash
         full_name: user_data.full_name.clone(),
         is_admin: false,
         created_at: now,
         updated_at: now,
         web3_wallet: user_data.web3_wallet.clone(),
+        email_verified_at: None,
     };
     
-    // Generate JWT token
-    let token = match generate_token(&user) {
-        Ok(token) => token,
-        Err(_) => {
+    // Ask the user to confirm their address; they can request another link later
+    match issue_verification_token(&client, user.id, &user.email, &data.config).await {
+        Ok(token) => {
+            let message = verification_email(&user.email, &token, &data.config);
+            if let Err(e) = data.mailer.send(&message).await {
+                error!("Failed to send verification email to user {}: {}", user.id, e);
+            }
+        }
+        Err(e) => {
             return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to generate token"
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    }
+    
+    // Start a new session and issue its tokens
+    let device = SessionDevice::from_request(&req, &data.config);
+    let tokens = match start_session(&client, &user, &device, &data.config).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
             }));
         }
     };
     
-    HttpResponse::Created().json(AuthResponse { token, user })
+    HttpResponse::Created().json(AuthResponse {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        user,
+    })
 }
 
 // Login with email and password
 pub async fn login(
+    req: HttpRequest,
     data: web::Data<AppState>,
     login_data: web::Json<LoginRequest>,
 ) -> impl Responder {
     let db = &data.pg_pool;
+    let ip_address = client_ip(&req, &data.config);
     
     let client = match db.get().await {
         Ok(client) => client,
@@ -163,20 +234,29 @@ pub async fn login(
         }
     };
     
+    // Refuse early while this email and IP are backing off or locked out
+    match check_login_allowed(&client, &login_data.email, &ip_address).await {
+        Ok(Ok(())) => (),
+        Ok(Err(throttled)) => return too_many_attempts(&throttled),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    }
+    
     // Find user by email
     let user_row = match client
         .query_opt(
-            "SELECT id, email, password_hash, full_name, is_admin, created_at, updated_at, web3_wallet FROM users WHERE email = $1",
-            &[&login_data.email],
+            "SELECT id, email, password_hash, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at FROM users WHERE LOWER(email) = $1",
+            &[&normalize_email(&login_data.email)],
         )
         .await
     {
         Ok(maybe_row) => match maybe_row {
             Some(row) => row,
             None => {
-                return HttpResponse::Unauthorized().json(serde_json::json!({
-                    "error": "Invalid email or password"
-                }));
+                return login_failed(&client, &login_data.email, &ip_address, &data.config).await;
             }
         },
         Err(e) => {
@@ -201,9 +281,7 @@ pub async fn login(
             };
             
             if !is_valid {
-                return HttpResponse::Unauthorized().json(serde_json::json!({
-                    "error": "Invalid email or password"
-                }));
+                return login_failed(&client, &login_data.email, &ip_address, &data.config).await;
             }
         }
         None => {
@@ -223,32 +301,185 @@ pub async fn login(
         created_at: user_row.get("created_at"),
         updated_at: user_row.get("updated_at"),
         web3_wallet: user_row.get("web3_wallet"),
+        email_verified_at: user_row.get("email_verified_at"),
     };
     
-    // Generate JWT token
-    let token = match generate_token(&user) {
-        Ok(token) => token,
-        Err(_) => {
+    if let Err(e) = record_login_success(&client, &login_data.email, &ip_address).await {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
+    
+    finish_login(&req, &client, user, &data).await
+}
+
+// Count a failed password login and answer with the usual vague error
+async fn login_failed(
+    client: &deadpool_postgres::Client,
+    email: &str,
+    ip_address: &str,
+    config: &AppConfig,
+) -> HttpResponse {
+    if let Err(e) = record_login_failure(client, email, ip_address, config).await {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
+    
+    HttpResponse::Unauthorized().json(serde_json::json!({
+        "error": "Invalid email or password"
+    }))
+}
+
+pub fn too_many_attempts(throttled: &Throttled) -> HttpResponse {
+    let retry_after = throttled.retry_after_secs();
+    let error = match throttled {
+        Throttled::LockedOut { .. } => format!(
+            "Too many failed login attempts; sign-in is locked for {} seconds",
+            retry_after
+        ),
+        Throttled::Backoff { .. } => format!(
+            "Too many failed login attempts; try again in {} seconds",
+            retry_after
+        ),
+    };
+    
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(serde_json::json!({
+            "error": error
+        }))
+}
+
+// Complete a successful first-factor login: start a session, or hand out an
+// mfa pending token when the user has two-factor authentication enabled
+async fn finish_login(
+    req: &HttpRequest,
+    client: &deadpool_postgres::Client,
+    user: User,
+    data: &AppState,
+) -> HttpResponse {
+    let mfa_enabled = match is_mfa_enabled(client, user.id).await {
+        Ok(enabled) => enabled,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    if mfa_enabled {
+        return match start_mfa_challenge(client, user.id, &data.config).await {
+            Ok(mfa_token) => HttpResponse::Ok().json(MfaChallengeResponse {
+                mfa_required: true,
+                mfa_token,
+            }),
+            Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
+            })),
+        };
+    }
+    
+    // Start a new session and issue its tokens
+    let device = SessionDevice::from_request(req, &data.config);
+    let tokens = match start_session(client, &user, &device, &data.config).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+    
+    HttpResponse::Ok().json(AuthResponse {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        user,
+    })
+}
+
+// Issue a single-use sign-in challenge for a Solana wallet
+pub async fn web3_challenge(
+    data: web::Data<AppState>,
+    challenge_data: web::Json<Web3ChallengeRequest>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    let config = &data.config;
+    
+    if !is_valid_wallet_address(&challenge_data.wallet_address) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid wallet address"
+        }));
+    }
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // Drop challenges that can no longer be used
+    if let Err(e) = client
+        .execute(
+            "DELETE FROM web3_challenges WHERE expires_at < NOW() OR used_at IS NOT NULL",
+            &[],
+        )
+        .await
+    {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
+    
+    let challenge = SiwsMessage::new_challenge(
+        &config.siws_domain,
+        &challenge_data.wallet_address,
+        &config.siws_uri,
+        &config.siws_chain_id,
+        Duration::seconds(config.web3_challenge_ttl_secs),
+    );
+    let message = challenge.to_string();
+    
+    match client
+        .execute(
+            "INSERT INTO web3_challenges (nonce, wallet_address, domain, message, issued_at, expires_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &challenge.nonce,
+                &challenge.address,
+                &challenge.domain,
+                &message,
+                &challenge.issued_at,
+                &challenge.expiration_time,
+            ],
+        )
+        .await
+    {
+        Ok(_) => (),
+        Err(e) => {
             return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to generate token"
+                "error": format!("Database error: {}", e)
             }));
         }
     };
     
-    HttpResponse::Ok().json(AuthResponse { token, user })
+    HttpResponse::Ok().json(Web3ChallengeResponse {
+        message,
+        nonce: challenge.nonce,
+        expires_at: challenge.expiration_time,
+    })
 }
 
 // Web3 login with Solana wallet
 pub async fn web3_login(
+    req: HttpRequest,
     data: web::Data<AppState>,
     login_data: web::Json<Web3LoginRequest>,
 ) -> impl Responder {
     let db = &data.pg_pool;
     
-    // This is a simplified web3 login that would normally validate a signed message
-    // In a real implementation, you would use Solana's libraries to verify the signature
-    
-    // For now, we're just checking if the wallet exists in our database
     let client = match db.get().await {
         Ok(client) => client,
         Err(e) => {
@@ -258,10 +489,25 @@ pub async fn web3_login(
         }
     };
     
-    // Find user by wallet address
+    if let Err(e) = verify_wallet_proof(
+        &client,
+        &data.config,
+        &login_data.wallet_address,
+        &login_data.message,
+        &login_data.signature,
+    )
+    .await
+    {
+        return e.error_response();
+    }
+    
+    // Find the user the wallet is linked to
     let user_row = match client
         .query_opt(
-            "SELECT id, email, password_hash, full_name, is_admin, created_at, updated_at, web3_wallet FROM users WHERE web3_wallet = $1",
+            "SELECT u.id, u.email, u.full_name, u.is_admin, u.created_at, u.updated_at, u.web3_wallet, u.email_verified_at
+             FROM user_wallets w
+             JOIN users u ON u.id = w.user_id
+             WHERE w.wallet_address = $1",
             &[&login_data.wallet_address],
         )
         .await
@@ -281,10 +527,6 @@ pub async fn web3_login(
         }
     };
     
-    // In a real-world application, you would verify the signature here
-    // This would involve using Solana's libraries to verify that the signature
-    // was created by the wallet address and matches the expected message
-    
     // Create user object
     let user = User {
         id: user_row.get("id"),
@@ -295,32 +537,470 @@ pub async fn web3_login(
         created_at: user_row.get("created_at"),
         updated_at: user_row.get("updated_at"),
         web3_wallet: user_row.get("web3_wallet"),
+        email_verified_at: user_row.get("email_verified_at"),
     };
     
-    // Generate JWT token
-    let token = match generate_token(&user) {
-        Ok(token) => token,
+    finish_login(&req, &client, user, &data).await
+}
+
+// Begin a social login: returns the provider URL to send the browser to.
+// The provider redirects back to the frontend, which posts the code to the callback.
+pub async fn oauth_start(
+    provider: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let provider = match data.config.oauth_provider(&provider) {
+        Some(provider) => provider,
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Unknown login provider"
+            }));
+        }
+    };
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let state = generate_opaque_token();
+    let code_verifier = generate_opaque_token();
+    let expires_at = Utc::now() + Duration::seconds(data.config.oauth_state_ttl_secs);
+    
+    let authorization_url = match provider.authorization_url(
+        &oauth_redirect_uri(&data.config, provider.kind),
+        &state,
+        &code_verifier,
+    ) {
+        Ok(url) => url,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+    
+    // Clean up abandoned attempts while we're here
+    if let Err(e) = client
+        .execute("DELETE FROM oauth_states WHERE expires_at <= NOW()", &[])
+        .await
+    {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
+    
+    match client
+        .execute(
+            "INSERT INTO oauth_states (state, provider, code_verifier, expires_at) VALUES ($1, $2, $3, $4)",
+            &[&state, &provider.kind.as_str(), &code_verifier, &expires_at],
+        )
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().json(OAuthStartResponse {
+            authorization_url,
+            state,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Finish a social login with the code the provider sent back
+pub async fn oauth_callback(
+    req: HttpRequest,
+    provider: web::Path<String>,
+    data: web::Data<AppState>,
+    callback_data: web::Json<OAuthCallbackRequest>,
+) -> impl Responder {
+    let provider = match data.config.oauth_provider(&provider) {
+        Some(provider) => provider,
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Unknown login provider"
+            }));
+        }
+    };
+    
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // The state must be one we issued for this provider, and is spent here
+    let code_verifier: String = match client
+        .query_opt(
+            "DELETE FROM oauth_states WHERE state = $1 AND provider = $2 AND expires_at > NOW()
+             RETURNING code_verifier",
+            &[&callback_data.state, &provider.kind.as_str()],
+        )
+        .await
+    {
+        Ok(Some(row)) => row.get("code_verifier"),
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Login attempt is invalid or has expired, please try again"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let redirect_uri = oauth_redirect_uri(&data.config, provider.kind);
+    let identity = match provider
+        .exchange_code(&data.http_client, &callback_data.code, &redirect_uri, &code_verifier)
+        .await
+    {
+        Ok(access_token) => provider.fetch_identity(&data.http_client, &access_token).await,
+        Err(e) => Err(e),
+    };
+    let identity = match identity {
+        Ok(identity) => identity,
+        Err(e) => {
+            return HttpResponse::BadGateway().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+    
+    let user_id = match resolve_user(&mut client, provider.kind, &identity).await {
+        Ok(Ok(user_id)) => user_id,
+        Ok(Err(e @ UnresolvedIdentity::UnverifiedEmail)) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+        Ok(Err(e @ UnresolvedIdentity::UnverifiedAccount)) => {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": e.to_string()
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let user_row = match client
+        .query_one(
+            "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at FROM users WHERE id = $1",
+            &[&user_id],
+        )
+        .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let user = User {
+        id: user_row.get("id"),
+        email: user_row.get("email"),
+        password_hash: None, // Don't return password hash
+        full_name: user_row.get("full_name"),
+        is_admin: user_row.get("is_admin"),
+        created_at: user_row.get("created_at"),
+        updated_at: user_row.get("updated_at"),
+        web3_wallet: user_row.get("web3_wallet"),
+        email_verified_at: user_row.get("email_verified_at"),
+    };
+    
+    finish_login(&req, &client, user, &data).await
+}
+
+// Frontend route the provider redirects back to
+fn oauth_redirect_uri(config: &AppConfig, provider: OAuthProviderKind) -> String {
+    format!(
+        "{}/oauth/{}/callback",
+        config.app_base_url.trim_end_matches('/'),
+        provider.as_str()
+    )
+}
+
+// Exchange a refresh token for a new access token, rotating the refresh token
+pub async fn refresh(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    refresh_data: web::Json<RefreshTokenRequest>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let mut client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let device = SessionDevice::from_request(&req, &data.config);
+    match rotate_session(&mut client, &refresh_data.refresh_token, &device, &data.config).await {
+        Ok((user, tokens)) => HttpResponse::Ok().json(AuthResponse {
+            token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            user,
+        }),
+        Err(e) if e.is_unauthorized() => {
+            HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": e.to_string()
+            }))
+        }
+        Err(e) => {
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
+            }))
+        }
+    }
+}
+
+// Logout: revoke the current session so its refresh token stops working
+pub async fn logout(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match revoke_session(&client, auth_user.user_id, auth_user.session_id).await {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "Logged out successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        })),
+    }
+}
+
+// Email a password reset link. Always answers the same way so the endpoint
+// cannot be used to find out which emails have accounts.
+pub async fn forgot_password(
+    data: web::Data<AppState>,
+    request_data: web::Json<ForgotPasswordRequest>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let accepted = HttpResponse::Ok().json(serde_json::json!({
+        "message": "If an account exists for that email, a reset link has been sent"
+    }));
+    
+    let user_row = match client
+        .query_opt(
+            "SELECT id, email FROM users WHERE LOWER(email) = $1",
+            &[&normalize_email(&request_data.email)],
+        )
+        .await
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => return accepted,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let user_id: Uuid = user_row.get("id");
+    let email: String = user_row.get("email");
+    
+    // The token is issued and mailed off the request path, so the response
+    // takes as long whether or not the account exists. Failures are logged
+    // rather than reported, for the same reason.
+    let pool = data.pg_pool.clone();
+    let mailer = data.mailer.clone();
+    let config = data.config.clone();
+    actix_web::rt::spawn(async move {
+        let client = match pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to issue password reset for user {}: {}", user_id, e);
+                return;
+            }
+        };
+        let token = match issue_reset_token(&client, user_id, &config).await {
+            Ok(token) => token,
+            Err(e) => {
+                error!("Failed to issue password reset for user {}: {}", user_id, e);
+                return;
+            }
+        };
+        if let Err(e) = mailer.send(&reset_email(&email, &token, &config)).await {
+            error!("Failed to send password reset email to user {}: {}", user_id, e);
+        }
+    });
+    
+    accepted
+}
+
+// Set a new password using a reset token, signing the user out everywhere
+pub async fn reset_password(
+    data: web::Data<AppState>,
+    reset_data: web::Json<ResetPasswordRequest>,
+) -> impl Responder {
+    if let Err(message) = validate_new_password(&reset_data.new_password) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": message
+        }));
+    }
+    
+    let password_hash = match hash(&reset_data.new_password, DEFAULT_COST) {
+        Ok(hashed) => hashed,
         Err(_) => {
             return HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to generate token"
+                "error": "Failed to hash password"
+            }));
+        }
+    };
+    
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
             }));
         }
     };
     
-    HttpResponse::Ok().json(AuthResponse { token, user })
+    match reset_password_with_token(&mut client, &reset_data.token, &password_hash).await {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "Password has been reset"
+        })),
+        Ok(false) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid or expired reset token"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
 }
 
-// Logout (client-side only)
-pub async fn logout() -> impl Responder {
-    // This is a client-side logout - just return success
-    HttpResponse::Ok().json(serde_json::json!({
-        "message": "Logged out successfully"
-    }))
+// Confirm an email address using the token from the verification email
+pub async fn verify_email(
+    data: web::Data<AppState>,
+    verify_data: web::Json<VerifyEmailRequest>,
+) -> impl Responder {
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match verify_email_with_token(&mut client, &verify_data.token).await {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "Email address verified"
+        })),
+        Ok(false) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid or expired verification token"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Send a fresh verification link to the current user's email
+pub async fn resend_verification_email(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let user_row = match client
+        .query_opt(
+            "SELECT email, email_verified_at FROM users WHERE id = $1",
+            &[&auth_user.user_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "User not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let email: String = user_row.get("email");
+    let verified_at: Option<DateTime<Utc>> = user_row.get("email_verified_at");
+    
+    if verified_at.is_some() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Email address is already verified"
+        }));
+    }
+    
+    let token = match issue_verification_token(&client, auth_user.user_id, &email, &data.config).await {
+        Ok(token) => token,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match data.mailer.send(&verification_email(&email, &token, &data.config)).await {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "Verification email sent"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        })),
+    }
 }
 
 // Get current user
 pub async fn get_current_user(
-    auth_user: crate::middleware::auth::AuthenticatedUser,
+    auth_user: AuthenticatedUser,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let db = &data.pg_pool;
@@ -337,7 +1017,7 @@ pub async fn get_current_user(
     // Find user by ID
     let user_row = match client
         .query_opt(
-            "SELECT id, email, password_hash, full_name, is_admin, created_at, updated_at, web3_wallet FROM users WHERE id = $1",
+            "SELECT id, email, password_hash, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at FROM users WHERE id = $1",
             &[&auth_user.user_id],
         )
         .await
@@ -367,7 +1047,15 @@ pub async fn get_current_user(
         created_at: user_row.get("created_at"),
         updated_at: user_row.get("updated_at"),
         web3_wallet: user_row.get("web3_wallet"),
+        email_verified_at: user_row.get("email_verified_at"),
     };
     
     HttpResponse::Ok().json(user)
 }
+
+// Publish the public keys that verify our access tokens
+pub async fn jwks(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(data.config.jwt_keys.jwks())
+}
@@ -3,7 +3,7 @@ use mongodb::bson::{self, doc, oid::ObjectId};
 use serde_json::json;
 
 use crate::models::blog::{BlogComment, BlogPost, CreateBlogPostRequest, CreateCommentRequest, UpdateBlogPostRequest};
-use crate::middleware::auth::{AdminUser, AuthenticatedUser};
+use crate::middleware::auth::{Authorized, CanModerateComments, CanWriteBlog, VerifiedUser};
 use crate::AppState;
 
 // Get all published blog posts
@@ -77,9 +77,9 @@ pub async fn get_post_by_id(id: web::Path<String>, data: web::Data<AppState>) ->
     }
 }
 
-// Create a new blog post (admin only)
+// Create a new blog post (editors and admins)
 pub async fn create_post(
-    admin_user: AdminUser,
+    editor: Authorized<CanWriteBlog>,
     post_data: web::Json<CreateBlogPostRequest>,
     data: web::Data<AppState>,
 ) -> impl Responder {
@@ -88,7 +88,7 @@ pub async fn create_post(
     
     let pg_db = &data.pg_pool;
     
-    // Get admin user details
+    // Get author details
     let client = match pg_db.get().await {
         Ok(client) => client,
         Err(e) => {
@@ -98,11 +98,11 @@ pub async fn create_post(
         }
     };
     
-    // Get admin user's name
+    // Get author's name
     let user_row = match client
         .query_opt(
             "SELECT full_name FROM users WHERE id = $1",
-            &[&admin_user.user_id],
+            &[&editor.user_id],
         )
         .await
     {
@@ -110,7 +110,7 @@ pub async fn create_post(
             Some(row) => row,
             None => {
                 return HttpResponse::InternalServerError().json(json!({
-                    "error": "Admin user not found"
+                    "error": "Author not found"
                 }));
             }
         },
@@ -127,7 +127,7 @@ pub async fn create_post(
     let blog_post = BlogPost::new(
         post_data.title.clone(),
         post_data.content.clone(),
-        admin_user.user_id,
+        editor.user_id,
         author_name,
         post_data.tags.clone(),
         post_data.published,
@@ -166,9 +166,9 @@ pub async fn create_post(
     }
 }
 
-// Update a blog post (admin only)
+// Update a blog post (editors and admins)
 pub async fn update_post(
-    admin_user: AdminUser,
+    _editor: Authorized<CanWriteBlog>,
     id: web::Path<String>,
     update_data: web::Json<UpdateBlogPostRequest>,
     data: web::Data<AppState>,
@@ -268,9 +268,9 @@ pub async fn update_post(
     }
 }
 
-// Delete a blog post (admin only)
+// Delete a blog post (editors and admins)
 pub async fn delete_post(
-    admin_user: AdminUser,
+    _editor: Authorized<CanWriteBlog>,
     id: web::Path<String>,
     data: web::Data<AppState>,
 ) -> impl Responder {
@@ -382,7 +382,7 @@ pub async fn get_comments(id: web::Path<String>, data: web::Data<AppState>) -> i
 
 // Add a comment to a blog post
 pub async fn add_comment(
-    auth_user: AuthenticatedUser,
+    auth_user: VerifiedUser,
     id: web::Path<String>,
     comment_data: web::Json<CreateCommentRequest>,
     data: web::Data<AppState>,
@@ -497,3 +497,49 @@ pub async fn add_comment(
         }
     }
 }
+
+// Remove a comment from a blog post (moderators and admins)
+pub async fn delete_comment(
+    _moderator: Authorized<CanModerateComments>,
+    path: web::Path<(String, String)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.mongo_client.database("hex_the_add_hub");
+    let collection = db.collection::<BlogComment>("blog_comments");
+    
+    let (post_id, comment_id) = path.into_inner();
+    
+    // Parse ObjectIds from path
+    let (post_id, comment_id) = match (ObjectId::parse_str(&post_id), ObjectId::parse_str(&comment_id)) {
+        (Ok(post_id), Ok(comment_id)) => (post_id, comment_id),
+        _ => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Invalid ID format"
+            }));
+        }
+    };
+    
+    let filter = doc! {
+        "_id": comment_id,
+        "post_id": post_id
+    };
+    
+    match collection.delete_one(filter, None).await {
+        Ok(result) => {
+            if result.deleted_count == 0 {
+                HttpResponse::NotFound().json(json!({
+                    "error": "Comment not found"
+                }))
+            } else {
+                HttpResponse::Ok().json(json!({
+                    "message": "Comment deleted successfully"
+                }))
+            }
+        },
+        Err(e) => {
+            HttpResponse::InternalServerError().json(json!({
+                "error": format!("Failed to delete comment: {}", e)
+            }))
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use rust_decimal::Decimal;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::db::coupons::{coupon_from_row, normalize_code, COUPON_QUERY};
+use crate::middleware::auth::AdminUser;
+use crate::models::coupon::{Coupon, CreateCouponRequest, Discount, UpdateCouponRequest};
+use crate::models::money::{validate_price, Currency};
+use crate::security::audit::{self, AuditEntry};
+use crate::AppState;
+
+const MIN_CODE_LENGTH: usize = 3;
+const MAX_CODE_LENGTH: usize = 32;
+
+// List every coupon, newest first (admin only)
+pub async fn list_coupons(
+    _admin_user: AdminUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+
+    match client
+        .query(&format!("{} ORDER BY c.created_at DESC", COUPON_QUERY), &[])
+        .await
+    {
+        Ok(rows) => {
+            let coupons: Vec<Coupon> = rows.iter().map(coupon_from_row).collect();
+            HttpResponse::Ok().json(coupons)
+        }
+        Err(e) => database_error(e),
+    }
+}
+
+// Get one coupon with its redemption count (admin only)
+pub async fn get_coupon(
+    _admin_user: AdminUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+
+    match client
+        .query_opt(&format!("{} WHERE c.id = $1", COUPON_QUERY), &[&id.into_inner()])
+        .await
+    {
+        Ok(Some(row)) => HttpResponse::Ok().json(coupon_from_row(&row)),
+        Ok(None) => coupon_not_found(),
+        Err(e) => database_error(e),
+    }
+}
+
+// Create a coupon (admin only)
+pub async fn create_coupon(
+    admin_user: AdminUser,
+    coupon_data: web::Json<CreateCouponRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let coupon_data = coupon_data.into_inner();
+    let now = Utc::now();
+    let coupon = Coupon {
+        id: Uuid::new_v4(),
+        code: normalize_code(&coupon_data.code),
+        discount: coupon_data.discount,
+        course_id: coupon_data.course_id,
+        max_redemptions: coupon_data.max_redemptions,
+        max_redemptions_per_user: coupon_data.max_redemptions_per_user,
+        starts_at: coupon_data.starts_at,
+        expires_at: coupon_data.expires_at,
+        active: coupon_data.active.unwrap_or(true),
+        redemptions: 0,
+        created_by: Some(admin_user.user_id),
+        created_at: now,
+        updated_at: now,
+    };
+
+    if let Err(message) = validate_code(&coupon.code).and_then(|_| validate_coupon(&coupon)) {
+        return HttpResponse::BadRequest().json(json!({
+            "error": message
+        }));
+    }
+
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+
+    // Course coupons only make sense for a course that is sold
+    if let Some(course_id) = coupon.course_id {
+        match client
+            .query_opt("SELECT is_free FROM courses WHERE id = $1", &[&course_id])
+            .await
+        {
+            Ok(Some(row)) if row.get::<_, bool>("is_free") => {
+                return HttpResponse::BadRequest().json(json!({
+                    "error": "Coupons only apply to paid courses"
+                }));
+            }
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "error": "Course not found"
+                }));
+            }
+            Err(e) => return database_error(e),
+        }
+    }
+
+    let (discount_type, percent_off, amount_off, currency) = discount_columns(&coupon.discount);
+    let inserted = client
+        .execute(
+            "INSERT INTO coupons (id, code, discount_type, percent_off, amount_off, currency, course_id,
+                 max_redemptions, max_redemptions_per_user, starts_at, expires_at, active, created_by,
+                 created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $14)
+             ON CONFLICT (code) DO NOTHING",
+            &[
+                &coupon.id,
+                &coupon.code,
+                &discount_type,
+                &percent_off,
+                &amount_off,
+                &currency,
+                &coupon.course_id,
+                &coupon.max_redemptions,
+                &coupon.max_redemptions_per_user,
+                &coupon.starts_at,
+                &coupon.expires_at,
+                &coupon.active,
+                &coupon.created_by,
+                &now,
+            ],
+        )
+        .await;
+    match inserted {
+        Ok(0) => {
+            return HttpResponse::Conflict().json(json!({
+                "error": "A coupon with this code already exists"
+            }));
+        }
+        Ok(_) => (),
+        Err(e) => return database_error(e),
+    }
+
+    let entry = AuditEntry {
+        event: "coupon_created",
+        user_id: None,
+        actor_id: Some(admin_user.user_id),
+        ip_address: None,
+        details: json!({ "coupon_id": coupon.id, "code": coupon.code }),
+    };
+
+    match audit::record(&client, entry).await {
+        Ok(_) => HttpResponse::Created().json(coupon),
+        Err(e) => database_error(e),
+    }
+}
+
+// Change a coupon's discount, limits, window or active flag (admin only).
+// The code and course stay fixed once created.
+pub async fn update_coupon(
+    admin_user: AdminUser,
+    id: web::Path<Uuid>,
+    update_data: web::Json<UpdateCouponRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+
+    let coupon_id = id.into_inner();
+
+    let mut coupon = match client
+        .query_opt(&format!("{} WHERE c.id = $1", COUPON_QUERY), &[&coupon_id])
+        .await
+    {
+        Ok(Some(row)) => coupon_from_row(&row),
+        Ok(None) => return coupon_not_found(),
+        Err(e) => return database_error(e),
+    };
+
+    let update_data = update_data.into_inner();
+    if let Some(discount) = update_data.discount {
+        coupon.discount = discount;
+    }
+    if let Some(max_redemptions) = update_data.max_redemptions {
+        coupon.max_redemptions = max_redemptions;
+    }
+    if let Some(max_redemptions_per_user) = update_data.max_redemptions_per_user {
+        coupon.max_redemptions_per_user = max_redemptions_per_user;
+    }
+    if let Some(starts_at) = update_data.starts_at {
+        coupon.starts_at = starts_at;
+    }
+    if let Some(expires_at) = update_data.expires_at {
+        coupon.expires_at = expires_at;
+    }
+    if let Some(active) = update_data.active {
+        coupon.active = active;
+    }
+
+    if let Err(message) = validate_coupon(&coupon) {
+        return HttpResponse::BadRequest().json(json!({
+            "error": message
+        }));
+    }
+
+    let (discount_type, percent_off, amount_off, currency) = discount_columns(&coupon.discount);
+    match client
+        .execute(
+            "UPDATE coupons SET discount_type = $2, percent_off = $3, amount_off = $4, currency = $5,
+                 max_redemptions = $6, max_redemptions_per_user = $7, starts_at = $8, expires_at = $9,
+                 active = $10, updated_at = NOW()
+             WHERE id = $1",
+            &[
+                &coupon_id,
+                &discount_type,
+                &percent_off,
+                &amount_off,
+                &currency,
+                &coupon.max_redemptions,
+                &coupon.max_redemptions_per_user,
+                &coupon.starts_at,
+                &coupon.expires_at,
+                &coupon.active,
+            ],
+        )
+        .await
+    {
+        Ok(0) => return coupon_not_found(),
+        Ok(_) => (),
+        Err(e) => return database_error(e),
+    }
+
+    let entry = AuditEntry {
+        event: "coupon_updated",
+        user_id: None,
+        actor_id: Some(admin_user.user_id),
+        ip_address: None,
+        details: json!({ "coupon_id": coupon_id, "code": coupon.code }),
+    };
+    if let Err(e) = audit::record(&client, entry).await {
+        return database_error(e);
+    }
+
+    match client
+        .query_opt(&format!("{} WHERE c.id = $1", COUPON_QUERY), &[&coupon_id])
+        .await
+    {
+        Ok(Some(row)) => HttpResponse::Ok().json(coupon_from_row(&row)),
+        Ok(None) => coupon_not_found(),
+        Err(e) => database_error(e),
+    }
+}
+
+// Delete a coupon that was never redeemed and no open order is priced with
+// (admin only). Redeemed coupons are kept for their history and can be
+// deactivated instead.
+pub async fn delete_coupon(
+    admin_user: AdminUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+
+    let coupon_id = id.into_inner();
+
+    // Locking the coupon keeps orders and redemptions from taking it between
+    // the check and the delete
+    let deleted = async {
+        let transaction = client.transaction().await?;
+        let row = transaction
+            .query_opt(
+                "SELECT c.code,
+                    (SELECT COUNT(*) FROM coupon_redemptions r WHERE r.coupon_id = c.id) AS redemptions,
+                    (SELECT COUNT(*) FROM orders o
+                     WHERE o.coupon_id = c.id AND o.status = 'pending' AND o.expires_at > NOW()) AS open_orders
+                 FROM coupons c WHERE c.id = $1 FOR UPDATE",
+                &[&coupon_id],
+            )
+            .await?;
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let allowed = coupon_deletion_allowed(row.get("redemptions"), row.get("open_orders"));
+        if allowed.is_ok() {
+            transaction.execute("DELETE FROM coupons WHERE id = $1", &[&coupon_id]).await?;
+            transaction.commit().await?;
+        }
+        Ok::<_, tokio_postgres::Error>(Some(allowed.map(|()| row.get::<_, String>("code"))))
+    };
+    let code = match deleted.await {
+        Ok(Some(Ok(code))) => code,
+        Ok(Some(Err(CouponInUse::Redeemed))) => {
+            return HttpResponse::Conflict().json(json!({
+                "error": "Coupon has been redeemed; deactivate it instead"
+            }));
+        }
+        Ok(Some(Err(CouponInUse::HeldByOpenOrders))) => {
+            return HttpResponse::Conflict().json(json!({
+                "error": "Coupon is held by an open order; deactivate it instead"
+            }));
+        }
+        Ok(None) => return coupon_not_found(),
+        Err(e) => return database_error(e),
+    };
+
+    let entry = AuditEntry {
+        event: "coupon_deleted",
+        user_id: None,
+        actor_id: Some(admin_user.user_id),
+        ip_address: None,
+        details: json!({ "coupon_id": coupon_id, "code": code }),
+    };
+
+    match audit::record(&client, entry).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Coupon deleted successfully"
+        })),
+        Err(e) => database_error(e),
+    }
+}
+
+// Why a coupon can't be deleted
+#[derive(Debug, PartialEq, Eq)]
+enum CouponInUse {
+    Redeemed,
+    // Checkouts priced with it are still waiting for payment
+    HeldByOpenOrders,
+}
+
+fn coupon_deletion_allowed(redemptions: i64, open_orders: i64) -> Result<(), CouponInUse> {
+    if redemptions > 0 {
+        return Err(CouponInUse::Redeemed);
+    }
+    if open_orders > 0 {
+        return Err(CouponInUse::HeldByOpenOrders);
+    }
+    Ok(())
+}
+
+// Codes are typed in by buyers, so they stay short and unambiguous
+fn validate_code(code: &str) -> Result<(), String> {
+    let length = code.chars().count();
+    if !(MIN_CODE_LENGTH..=MAX_CODE_LENGTH).contains(&length) {
+        return Err(format!(
+            "Code must be {} to {} characters",
+            MIN_CODE_LENGTH, MAX_CODE_LENGTH
+        ));
+    }
+    if !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("Code may only contain letters, digits, '-' and '_'".to_string());
+    }
+    Ok(())
+}
+
+fn validate_coupon(coupon: &Coupon) -> Result<(), String> {
+    match &coupon.discount {
+        Discount::Percent { percent_off } => {
+            if !(1..=100).contains(percent_off) {
+                return Err("percent_off must be between 1 and 100".to_string());
+            }
+        }
+        // A fixed discount has the same shape as a price of a paid course
+        Discount::Fixed { amount_off } => {
+            validate_price(amount_off, false).map_err(|message| format!("amount_off: {}", message))?
+        }
+    }
+
+    if coupon.max_redemptions.is_some_and(|max| max < 1) {
+        return Err("max_redemptions must be at least 1".to_string());
+    }
+    if coupon.max_redemptions_per_user.is_some_and(|max| max < 1) {
+        return Err("max_redemptions_per_user must be at least 1".to_string());
+    }
+    if let (Some(starts_at), Some(expires_at)) = (coupon.starts_at, coupon.expires_at) {
+        if expires_at <= starts_at {
+            return Err("expires_at must be after starts_at".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+// The discount as its coupons columns: type, percent_off, amount_off, currency
+fn discount_columns(discount: &Discount) -> (&'static str, Option<i32>, Option<Decimal>, Option<Currency>) {
+    match discount {
+        Discount::Percent { percent_off } => ("percent", Some(*percent_off), None, None),
+        Discount::Fixed { amount_off } => ("fixed", None, Some(amount_off.amount), Some(amount_off.currency)),
+    }
+}
+
+fn coupon_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({
+        "error": "Coupon not found"
+    }))
+}
+
+fn database_error(e: impl std::fmt::Display) -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({
+        "error": format!("Database error: {}", e)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::money::Money;
+    use chrono::Duration;
+
+    fn coupon(discount: Discount) -> Coupon {
+        Coupon {
+            id: Uuid::new_v4(),
+            code: "LAUNCH".to_string(),
+            discount,
+            course_id: None,
+            max_redemptions: Some(100),
+            max_redemptions_per_user: Some(1),
+            starts_at: None,
+            expires_at: None,
+            active: true,
+            redemptions: 0,
+            created_by: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_coupons_in_use_are_kept() {
+        assert_eq!(coupon_deletion_allowed(0, 0), Ok(()));
+        assert_eq!(coupon_deletion_allowed(3, 0), Err(CouponInUse::Redeemed));
+        assert_eq!(coupon_deletion_allowed(0, 1), Err(CouponInUse::HeldByOpenOrders));
+    }
+
+    #[test]
+    fn test_validate_code() {
+        assert_eq!(validate_code(&normalize_code(" spring-25_off ")), Ok(()));
+        assert!(validate_code("AB").is_err());
+        assert!(validate_code(&"A".repeat(33)).is_err());
+        assert!(validate_code("HALF OFF").is_err());
+        assert!(validate_code("ÉTÉ2024").is_err());
+    }
+
+    #[test]
+    fn test_validate_coupon() {
+        assert_eq!(validate_coupon(&coupon(Discount::Percent { percent_off: 100 })), Ok(()));
+        assert!(validate_coupon(&coupon(Discount::Percent { percent_off: 0 })).is_err());
+        assert!(validate_coupon(&coupon(Discount::Percent { percent_off: 101 })).is_err());
+
+        let fixed = |amount: &str| Discount::Fixed {
+            amount_off: Money::new(amount.parse().unwrap(), Currency::Sol),
+        };
+        assert_eq!(validate_coupon(&coupon(fixed("2.50"))), Ok(()));
+        assert!(validate_coupon(&coupon(fixed("0"))).is_err());
+        assert!(validate_coupon(&coupon(fixed("0.005"))).is_err());
+
+        let mut limited = coupon(fixed("1"));
+        limited.max_redemptions_per_user = Some(0);
+        assert!(validate_coupon(&limited).is_err());
+
+        let mut window = coupon(fixed("1"));
+        let now = Utc::now();
+        window.starts_at = Some(now);
+        window.expires_at = Some(now - Duration::days(1));
+        assert!(validate_coupon(&window).is_err());
+        window.expires_at = Some(now + Duration::days(7));
+        assert_eq!(validate_coupon(&window), Ok(()));
+    }
+}
@@ -1,13 +1,22 @@
 use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
 use serde_json::json;
 use uuid::Uuid;
 
+use crate::db::coupons::{apply_coupon, record_redemption};
+use crate::db::progress::{
+    load_course_progress, load_enrolled_outlines, refresh_course_completion, summarize,
+    OutlineEntry,
+};
 use crate::models::course::{
-    Course, CourseLesson, CourseSection, CourseWithSections, CreateCourseRequest,
-    CreateLessonRequest, CreateSectionRequest, SectionWithLessons, UpdateCourseRequest,
+    Course, CourseLesson, CourseSection, CourseWithSections, CreateCourseRequest, EnrolledCourse,
+    EnrollmentStatus, MyCoursesQuery, SectionWithLessons, UpdateCourseRequest,
     UpdateProgressRequest,
 };
-use crate::middleware::auth::{AdminUser, AuthenticatedUser};
+use crate::middleware::auth::{AuthenticatedUser, Authorized, CanManageCourses, VerifiedUser};
+use crate::models::coupon::CouponQuery;
+use crate::models::money::{validate_price, Money};
+use crate::security::roles::{can_manage_course, Permission};
 use crate::AppState;
 
 // Get all courses
@@ -26,7 +35,7 @@ pub async fn get_all_courses(data: web::Data<AppState>) -> impl Responder {
     // Query for all courses
     match client
         .query(
-            "SELECT id, title, description, price, is_free, created_at, updated_at, created_by FROM courses ORDER BY created_at DESC",
+            "SELECT id, title, description, price, currency, is_free, created_at, updated_at, created_by FROM courses ORDER BY created_at DESC",
             &[],
         )
         .await
@@ -34,16 +43,7 @@ pub async fn get_all_courses(data: web::Data<AppState>) -> impl Responder {
         Ok(rows) => {
             let courses: Vec<Course> = rows
                 .iter()
-                .map(|row| Course {
-                    id: row.get("id"),
-                    title: row.get("title"),
-                    description: row.get("description"),
-                    price: row.get("price"),
-                    is_free: row.get("is_free"),
-                    created_at: row.get("created_at"),
-                    updated_at: row.get("updated_at"),
-                    created_by: row.get("created_by"),
-                })
+                .map(course_from_row)
                 .collect();
             
             HttpResponse::Ok().json(courses)
@@ -72,7 +72,7 @@ pub async fn get_course_by_id(id: web::Path<Uuid>, data: web::Data<AppState>) ->
     // Query for the course
     let course_row = match client
         .query_opt(
-            "SELECT id, title, description, price, is_free, created_at, updated_at, created_by FROM courses WHERE id = $1",
+            "SELECT id, title, description, price, currency, is_free, created_at, updated_at, created_by FROM courses WHERE id = $1",
             &[&id.into_inner()],
         )
         .await
@@ -93,16 +93,7 @@ pub async fn get_course_by_id(id: web::Path<Uuid>, data: web::Data<AppState>) ->
     };
     
     // Parse the course data
-    let course = Course {
-        id: course_row.get("id"),
-        title: course_row.get("title"),
-        description: course_row.get("description"),
-        price: course_row.get("price"),
-        is_free: course_row.get("is_free"),
-        created_at: course_row.get("created_at"),
-        updated_at: course_row.get("updated_at"),
-        created_by: course_row.get("created_by"),
-    };
+    let course = course_from_row(&course_row);
     
     // Query for sections
     let section_rows = match client
@@ -177,9 +168,9 @@ pub async fn get_course_by_id(id: web::Path<Uuid>, data: web::Data<AppState>) ->
     HttpResponse::Ok().json(course_with_sections)
 }
 
-// Create a course (admin only)
+// Create a course (instructors and admins); the caller becomes its owner
 pub async fn create_course(
-    admin_user: AdminUser,
+    instructor: Authorized<CanManageCourses>,
     course_data: web::Json<CreateCourseRequest>,
     data: web::Data<AppState>,
 ) -> impl Responder {
@@ -194,6 +185,12 @@ pub async fn create_course(
         }
     };
     
+    if let Err(message) = validate_price(&course_data.price, course_data.is_free) {
+        return HttpResponse::BadRequest().json(json!({
+            "error": message
+        }));
+    }
+    
     // Create new course
     let course_id = Uuid::new_v4();
     let now = chrono::Utc::now();
@@ -201,16 +198,17 @@ pub async fn create_course(
     // Insert course into database
     match client
         .execute(
-            "INSERT INTO courses (id, title, description, price, is_free, created_at, updated_at, created_by) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            "INSERT INTO courses (id, title, description, price, currency, is_free, created_at, updated_at, created_by) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
             &[
                 &course_id,
                 &course_data.title,
                 &course_data.description,
-                &course_data.price,
+                &course_data.price.amount,
+                &course_data.price.currency,
                 &course_data.is_free,
                 &now,
                 &now,
-                &admin_user.user_id,
+                &instructor.user_id,
             ],
         )
         .await
@@ -226,22 +224,13 @@ pub async fn create_course(
     // Query for the inserted course to return
     match client
         .query_one(
-            "SELECT id, title, description, price, is_free, created_at, updated_at, created_by FROM courses WHERE id = $1",
+            "SELECT id, title, description, price, currency, is_free, created_at, updated_at, created_by FROM courses WHERE id = $1",
             &[&course_id],
         )
         .await
     {
         Ok(row) => {
-            let course = Course {
-                id: row.get("id"),
-                title: row.get("title"),
-                description: row.get("description"),
-                price: row.get("price"),
-                is_free: row.get("is_free"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-                created_by: row.get("created_by"),
-            };
+            let course = course_from_row(&row);
             
             HttpResponse::Created().json(course)
         }
@@ -253,9 +242,9 @@ pub async fn create_course(
     }
 }
 
-// Update a course (admin only)
+// Update a course (its instructor, or anyone who can manage all courses)
 pub async fn update_course(
-    admin_user: AdminUser,
+    instructor: Authorized<CanManageCourses>,
     id: web::Path<Uuid>,
     update_data: web::Json<UpdateCourseRequest>,
     data: web::Data<AppState>,
@@ -271,15 +260,20 @@ pub async fn update_course(
         }
     };
     
-    // Check if course exists
-    let course_exists = match client
-        .query_one(
-            "SELECT EXISTS(SELECT 1 FROM courses WHERE id = $1)",
+    // Check if course exists and belongs to the caller
+    let current = match client
+        .query_opt(
+            "SELECT created_by, price, currency, is_free FROM courses WHERE id = $1",
             &[&id.into_inner()],
         )
         .await
     {
-        Ok(row) => row.get::<_, bool>(0),
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Course not found"
+            }));
+        }
         Err(e) => {
             return HttpResponse::InternalServerError().json(json!({
                 "error": format!("Database error: {}", e)
@@ -287,9 +281,21 @@ pub async fn update_course(
         }
     };
     
-    if !course_exists {
-        return HttpResponse::NotFound().json(json!({
-            "error": "Course not found"
+    let can_manage_all = instructor.has_permission(Permission::ManageAllCourses);
+    if !can_manage_course(instructor.user_id, can_manage_all, current.get("created_by")) {
+        return HttpResponse::Forbidden().json(json!({
+            "error": "You can only manage your own courses"
+        }));
+    }
+    
+    // The price and free flag must still agree once the update is applied
+    let price = update_data
+        .price
+        .unwrap_or_else(|| Money::new(current.get("price"), current.get("currency")));
+    let is_free = update_data.is_free.unwrap_or_else(|| current.get("is_free"));
+    if let Err(message) = validate_price(&price, is_free) {
+        return HttpResponse::BadRequest().json(json!({
+            "error": message
         }));
     }
     
@@ -311,9 +317,10 @@ pub async fn update_course(
     }
     
     if let Some(price) = &update_data.price {
-        query.push_str(&format!(", price = ${}", param_count));
-        params.push(price);
-        param_count += 1;
+        query.push_str(&format!(", price = ${}, currency = ${}", param_count, param_count + 1));
+        params.push(&price.amount);
+        params.push(&price.currency);
+        param_count += 2;
     }
     
     if let Some(is_free) = &update_data.is_free {
@@ -338,22 +345,13 @@ pub async fn update_course(
     // Query for the updated course to return
     match client
         .query_one(
-            "SELECT id, title, description, price, is_free, created_at, updated_at, created_by FROM courses WHERE id = $1",
+            "SELECT id, title, description, price, currency, is_free, created_at, updated_at, created_by FROM courses WHERE id = $1",
             &[&id],
         )
         .await
     {
         Ok(row) => {
-            let course = Course {
-                id: row.get("id"),
-                title: row.get("title"),
-                description: row.get("description"),
-                price: row.get("price"),
-                is_free: row.get("is_free"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-                created_by: row.get("created_by"),
-            };
+            let course = course_from_row(&row);
             
             HttpResponse::Ok().json(course)
         }
@@ -365,15 +363,15 @@ pub async fn update_course(
     }
 }
 
-// Delete a course (admin only)
+// Delete a course (its instructor, or anyone who can manage all courses)
 pub async fn delete_course(
-    admin_user: AdminUser,
+    instructor: Authorized<CanManageCourses>,
     id: web::Path<Uuid>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let db = &data.pg_pool;
     
-    let client = match db.get().await {
+    let mut client = match db.get().await {
         Ok(client) => client,
         Err(e) => {
             return HttpResponse::InternalServerError().json(json!({
@@ -382,22 +380,76 @@ pub async fn delete_course(
         }
     };
     
-    // Delete course and all associated sections and lessons through cascading
-    match client
-        .execute("DELETE FROM courses WHERE id = $1", &[&id.into_inner()])
+    let id = id.into_inner();
+    
+    let created_by: Uuid = match client
+        .query_opt("SELECT created_by FROM courses WHERE id = $1", &[&id])
         .await
     {
-        Ok(count) => {
-            if count == 0 {
-                HttpResponse::NotFound().json(json!({
-                    "error": "Course not found"
-                }))
-            } else {
-                HttpResponse::Ok().json(json!({
-                    "message": "Course deleted successfully"
-                }))
-            }
+        Ok(Some(row)) => row.get("created_by"),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Course not found"
+            }));
         }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let can_manage_all = instructor.has_permission(Permission::ManageAllCourses);
+    if !can_manage_course(instructor.user_id, can_manage_all, created_by) {
+        return HttpResponse::Forbidden().json(json!({
+            "error": "You can only manage your own courses"
+        }));
+    }
+    
+    // Delete course and all associated sections and lessons through cascading.
+    // Locking the course row keeps enrollments and orders from being added
+    // between the check and the delete.
+    let deleted = async {
+        let transaction = client.transaction().await?;
+        let row = transaction
+            .query_opt(
+                "SELECT
+                    (SELECT COUNT(*) FROM user_enrollments WHERE course_id = c.id) AS enrollments,
+                    (SELECT COUNT(*) FROM orders WHERE course_id = c.id AND status = 'paid') AS paid_orders
+                 FROM courses c WHERE c.id = $1 FOR UPDATE",
+                &[&id],
+            )
+            .await?;
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        
+        let allowed = course_deletion_allowed(row.get("enrollments"), row.get("paid_orders"));
+        if allowed.is_ok() {
+            transaction.execute("DELETE FROM courses WHERE id = $1", &[&id]).await?;
+            transaction.commit().await?;
+        }
+        Ok::<_, tokio_postgres::Error>(Some(allowed))
+    };
+    
+    match deleted.await {
+        Ok(Some(Ok(()))) => HttpResponse::Ok().json(json!({
+            "message": "Course deleted successfully"
+        })),
+        Ok(Some(Err(CourseDeletionBlocked::HasPaidOrders(count)))) => {
+            HttpResponse::Conflict().json(json!({
+                "error": format!("Course has {} paid order(s) and cannot be deleted", count)
+            }))
+        }
+        Ok(Some(Err(CourseDeletionBlocked::HasEnrollments(count)))) => {
+            HttpResponse::Conflict().json(json!({
+                "error": format!("Course has {} enrolled learner(s) and cannot be deleted", count)
+            }))
+        }
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "Course not found"
+        })),
         Err(e) => {
             HttpResponse::InternalServerError().json(json!({
                 "error": format!("Database error: {}", e)
@@ -406,15 +458,35 @@ pub async fn delete_course(
     }
 }
 
-// Enroll in a course
+// Why a course can't be deleted: learners paid for it or are taking it
+#[derive(Debug, PartialEq, Eq)]
+enum CourseDeletionBlocked {
+    HasPaidOrders(i64),
+    HasEnrollments(i64),
+}
+
+fn course_deletion_allowed(enrollments: i64, paid_orders: i64) -> Result<(), CourseDeletionBlocked> {
+    if paid_orders > 0 {
+        return Err(CourseDeletionBlocked::HasPaidOrders(paid_orders));
+    }
+    if enrollments > 0 {
+        return Err(CourseDeletionBlocked::HasEnrollments(enrollments));
+    }
+    
+    Ok(())
+}
+
+// Enroll in a course. Paid courses need a coupon covering the whole price;
+// otherwise they are bought through checkout.
 pub async fn enroll_in_course(
-    auth_user: AuthenticatedUser,
+    auth_user: VerifiedUser,
     id: web::Path<Uuid>,
+    query: web::Query<CouponQuery>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let db = &data.pg_pool;
     
-    let client = match db.get().await {
+    let mut client = match db.get().await {
         Ok(client) => client,
         Err(e) => {
             return HttpResponse::InternalServerError().json(json!({
@@ -423,15 +495,35 @@ pub async fn enroll_in_course(
         }
     };
     
-    // Check if course exists
-    let course_exists = match client
-        .query_one(
-            "SELECT EXISTS(SELECT 1 FROM courses WHERE id = $1)",
-            &[&id.into_inner()],
+    let id = id.into_inner();
+    
+    // The coupon stays locked until the enrollment is committed
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // Check if course exists and what it costs
+    let (price, is_free) = match transaction
+        .query_opt(
+            "SELECT price, currency, is_free FROM courses WHERE id = $1",
+            &[&id],
         )
         .await
     {
-        Ok(row) => row.get::<_, bool>(0),
+        Ok(Some(row)) => (
+            Money::new(row.get("price"), row.get("currency")),
+            row.get::<_, bool>("is_free"),
+        ),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Course not found"
+            }));
+        }
         Err(e) => {
             return HttpResponse::InternalServerError().json(json!({
                 "error": format!("Database error: {}", e)
@@ -439,14 +531,8 @@ pub async fn enroll_in_course(
         }
     };
     
-    if !course_exists {
-        return HttpResponse::NotFound().json(json!({
-            "error": "Course not found"
-        }));
-    }
-    
     // Check if user is already enrolled
-    let already_enrolled = match client
+    let already_enrolled = match transaction
         .query_one(
             "SELECT EXISTS(SELECT 1 FROM user_enrollments WHERE user_id = $1 AND course_id = $2)",
             &[&auth_user.user_id, &id],
@@ -467,17 +553,85 @@ pub async fn enroll_in_course(
         }));
     }
     
-    // Enroll the user
-    match client
-        .execute(
-            "INSERT INTO user_enrollments (user_id, course_id, enrolled_at) VALUES ($1, $2, NOW())",
-            &[&auth_user.user_id, &id],
-        )
-        .await
-    {
-        Ok(_) => {
+    let coupon = match &query.coupon {
+        Some(_) if is_free => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Coupons only apply to paid courses"
+            }));
+        }
+        Some(code) => match apply_coupon(&transaction, code, auth_user.user_id, id, price).await {
+            Ok(Ok(coupon)) => Some(coupon),
+            Ok(Err(e)) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "error": e.to_string()
+                }));
+            }
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "error": format!("Database error: {}", e)
+                }));
+            }
+        },
+        None => None,
+    };
+    
+    // Paid courses are joined through checkout once the payment is verified
+    let amount_paid = coupon.as_ref().map_or(price, |coupon| coupon.price);
+    if !amount_paid.is_zero() {
+        let message = match coupon {
+            Some(_) => "The coupon doesn't cover the full price; use it at checkout instead",
+            None => "This course must be purchased; start a checkout instead",
+        };
+        return HttpResponse::PaymentRequired().json(json!({
+            "error": message
+        }));
+    }
+    
+    // Enroll the user. A concurrent request may have enrolled them since the
+    // check above; then nothing is inserted and the coupon is not redeemed.
+    let enrolled = async {
+        let inserted = transaction
+            .execute(
+                "INSERT INTO user_enrollments (user_id, course_id, enrolled_at, amount_paid, currency, coupon_id)
+                 VALUES ($1, $2, NOW(), $3, $4, $5)
+                 ON CONFLICT (user_id, course_id) DO NOTHING",
+                &[
+                    &auth_user.user_id,
+                    &id,
+                    &amount_paid.amount,
+                    &amount_paid.currency,
+                    &coupon.as_ref().map(|coupon| coupon.coupon_id),
+                ],
+            )
+            .await?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+        if let Some(coupon) = &coupon {
+            record_redemption(
+                &transaction,
+                coupon.coupon_id,
+                auth_user.user_id,
+                id,
+                None,
+                coupon.discount.amount,
+            )
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok::<_, tokio_postgres::Error>(true)
+    };
+    
+    match enrolled.await {
+        Ok(false) => {
+            HttpResponse::Conflict().json(json!({
+                "error": "You are already enrolled in this course"
+            }))
+        }
+        Ok(true) => {
             HttpResponse::Created().json(json!({
-                "message": "Enrolled in course successfully"
+                "message": "Enrolled in course successfully",
+                "amount_paid": amount_paid
             }))
         }
         Err(e) => {
@@ -488,11 +642,10 @@ pub async fn enroll_in_course(
     }
 }
 
-// Update lesson progress
-pub async fn update_progress(
+// Get the current user's progress through a course they are enrolled in
+pub async fn get_course_progress(
     auth_user: AuthenticatedUser,
     id: web::Path<Uuid>,
-    progress_data: web::Json<UpdateProgressRequest>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let db = &data.pg_pool;
@@ -506,15 +659,66 @@ pub async fn update_progress(
         }
     };
     
-    // Check if lesson exists
-    let lesson_exists = match client
-        .query_one(
-            "SELECT EXISTS(SELECT 1 FROM course_lessons WHERE id = $1)",
-            &[&id.into_inner()],
+    let id = id.into_inner();
+    
+    match load_course_progress(&client, auth_user.user_id, id).await {
+        Ok(Some(progress)) => return HttpResponse::Ok().json(progress),
+        Ok(None) => (),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // Not enrolled; tell a missing course apart from someone else's
+    match client
+        .query_one("SELECT EXISTS(SELECT 1 FROM courses WHERE id = $1)", &[&id])
+        .await
+    {
+        Ok(row) if row.get::<_, bool>(0) => HttpResponse::Forbidden().json(json!({
+            "error": "You are not enrolled in this course"
+        })),
+        Ok(_) => HttpResponse::NotFound().json(json!({
+            "error": "Course not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// List the courses the current user is enrolled in, most recently studied first
+pub async fn get_my_courses(
+    auth_user: AuthenticatedUser,
+    query: web::Query<MyCoursesQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
+    
+    let client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let rows = match client
+        .query(
+            &format!(
+                "SELECT c.id, c.title, c.description, c.price, c.currency, c.is_free, c.created_at, c.updated_at, c.created_by,
+                        e.enrolled_at, e.completed_at, e.amount_paid, e.currency AS paid_currency
+                 FROM user_enrollments e JOIN courses c ON c.id = e.course_id
+                 WHERE e.user_id = $1{}",
+                status_condition(query.status)
+            ),
+            &[&auth_user.user_id],
         )
         .await
     {
-        Ok(row) => row.get::<_, bool>(0),
+        Ok(rows) => rows,
         Err(e) => {
             return HttpResponse::InternalServerError().json(json!({
                 "error": format!("Database error: {}", e)
@@ -522,22 +726,122 @@ pub async fn update_progress(
         }
     };
     
-    if !lesson_exists {
-        return HttpResponse::NotFound().json(json!({
-            "error": "Lesson not found"
-        }));
+    let mut outlines = match load_enrolled_outlines(&client, auth_user.user_id).await {
+        Ok(outlines) => outlines,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let mut courses: Vec<EnrolledCourse> = rows
+        .iter()
+        .map(|row| {
+            let course = course_from_row(row);
+            let outline = outlines.remove(&course.id);
+            enrolled_course(
+                course,
+                row.get("enrolled_at"),
+                row.get("completed_at"),
+                Money::new(row.get("amount_paid"), row.get("paid_currency")),
+                outline,
+            )
+        })
+        .collect();
+    
+    sort_by_activity(&mut courses);
+    
+    HttpResponse::Ok().json(courses)
+}
+
+// Condition on the enrollment `e` for a dashboard status filter
+fn status_condition(status: Option<EnrollmentStatus>) -> &'static str {
+    match status {
+        Some(EnrollmentStatus::InProgress) => " AND e.completed_at IS NULL",
+        Some(EnrollmentStatus::Completed) => " AND e.completed_at IS NOT NULL",
+        None => "",
     }
+}
+
+// Dashboard entry for an enrollment; a course without sections has no outline
+fn enrolled_course(
+    course: Course,
+    enrolled_at: DateTime<Utc>,
+    completed_at: Option<DateTime<Utc>>,
+    amount_paid: Money,
+    outline: Option<Vec<OutlineEntry>>,
+) -> EnrolledCourse {
+    let summary = summarize(&outline.unwrap_or_default());
+    
+    EnrolledCourse {
+        course,
+        enrolled_at,
+        completed_at,
+        amount_paid,
+        total_lessons: summary.total_lessons,
+        completed_lessons: summary.completed_lessons,
+        percent_complete: summary.percent_complete,
+        last_accessed_at: summary.last_accessed_at,
+        next_lesson: summary.next_lesson,
+    }
+}
+
+// Most recently active first; courses never opened count from when the user enrolled
+fn sort_by_activity(courses: &mut [EnrolledCourse]) {
+    courses.sort_by_key(|enrolled| {
+        std::cmp::Reverse(enrolled.last_accessed_at.unwrap_or(enrolled.enrolled_at))
+    });
+}
+
+// Update lesson progress; completing the last lesson completes the course
+pub async fn update_progress(
+    auth_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    progress_data: web::Json<UpdateProgressRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let db = &data.pg_pool;
     
-    // Check if the user is enrolled in the course that contains this lesson
+    let mut client = match db.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let id = id.into_inner();
+    
+    // Find the course that contains this lesson
+    let course_id: Uuid = match client
+        .query_opt(
+            "SELECT cs.course_id FROM course_lessons cl
+             JOIN course_sections cs ON cs.id = cl.section_id
+             WHERE cl.id = $1",
+            &[&id],
+        )
+        .await
+    {
+        Ok(Some(row)) => row.get("course_id"),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Lesson not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // Check if the user is enrolled in that course
     let is_enrolled = match client
         .query_one(
-            "SELECT EXISTS(
-                SELECT 1 FROM user_enrollments ue 
-                JOIN course_sections cs ON ue.course_id = cs.course_id
-                JOIN course_lessons cl ON cs.id = cl.section_id
-                WHERE ue.user_id = $1 AND cl.id = $2
-            )",
-            &[&auth_user.user_id, &id],
+            "SELECT EXISTS(SELECT 1 FROM user_enrollments WHERE user_id = $1 AND course_id = $2)",
+            &[&auth_user.user_id, &course_id],
         )
         .await
     {
@@ -555,20 +859,37 @@ pub async fn update_progress(
         }));
     }
     
-    // Update progress
-    match client
-        .execute(
-            "INSERT INTO user_lesson_progress (user_id, lesson_id, completed, last_accessed) 
-             VALUES ($1, $2, $3, NOW())
-             ON CONFLICT (user_id, lesson_id) 
-             DO UPDATE SET completed = $3, last_accessed = NOW()",
-            &[&auth_user.user_id, &id, &progress_data.completed],
-        )
-        .await
-    {
-        Ok(_) => {
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // Update progress, then re-check whether the whole course is done
+    let updated = async {
+        transaction
+            .execute(
+                "INSERT INTO user_lesson_progress (user_id, lesson_id, completed, last_accessed) 
+                 VALUES ($1, $2, $3, NOW())
+                 ON CONFLICT (user_id, lesson_id) 
+                 DO UPDATE SET completed = $3, last_accessed = NOW()",
+                &[&auth_user.user_id, &id, &progress_data.completed],
+            )
+            .await?;
+        let completed_at =
+            refresh_course_completion(&transaction, auth_user.user_id, course_id).await?;
+        transaction.commit().await?;
+        Ok::<_, tokio_postgres::Error>(completed_at)
+    };
+    
+    match updated.await {
+        Ok(completed_at) => {
             HttpResponse::Ok().json(json!({
-                "message": "Progress updated successfully"
+                "message": "Progress updated successfully",
+                "course_completed_at": completed_at
             }))
         }
         Err(e) => {
@@ -578,3 +899,89 @@ pub async fn update_progress(
         }
     }
 }
+
+fn course_from_row(row: &tokio_postgres::Row) -> Course {
+    Course {
+        id: row.get("id"),
+        title: row.get("title"),
+        description: row.get("description"),
+        price: Money::new(row.get("price"), row.get("currency")),
+        is_free: row.get("is_free"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+        created_by: row.get("created_by"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::progress::OutlineLesson;
+    use crate::models::money::Currency;
+    use chrono::Duration;
+    use rust_decimal::Decimal;
+
+    fn enrollment(title: &str, enrolled_at: DateTime<Utc>, outline: Option<Vec<OutlineEntry>>) -> EnrolledCourse {
+        let course = Course::new(
+            title.to_string(),
+            String::new(),
+            Money::new(Decimal::ZERO, Currency::default()),
+            true,
+            Uuid::new_v4(),
+        );
+        enrolled_course(course, enrolled_at, None, Money::new(Decimal::ZERO, Currency::default()), outline)
+    }
+
+    fn opened_lesson(accessed: DateTime<Utc>) -> Vec<OutlineEntry> {
+        vec![OutlineEntry {
+            section_id: Uuid::new_v4(),
+            section_title: "Intro".to_string(),
+            lesson: Some(OutlineLesson {
+                id: Uuid::new_v4(),
+                title: "Welcome".to_string(),
+                completed: false,
+                last_accessed: Some(accessed),
+            }),
+        }]
+    }
+
+    #[test]
+    fn test_status_filter() {
+        assert_eq!(status_condition(None), "");
+        assert_eq!(status_condition(Some(EnrollmentStatus::InProgress)), " AND e.completed_at IS NULL");
+        assert_eq!(status_condition(Some(EnrollmentStatus::Completed)), " AND e.completed_at IS NOT NULL");
+    }
+
+    #[test]
+    fn test_dashboard_order() {
+        let now = Utc::now();
+        let mut courses = vec![
+            enrollment("Opened last week", now - Duration::days(30), Some(opened_lesson(now - Duration::days(7)))),
+            enrollment("Enrolled yesterday", now - Duration::days(1), None),
+            enrollment("Enrolled long ago", now - Duration::days(60), None),
+            enrollment("Opened today", now - Duration::days(90), Some(opened_lesson(now))),
+        ];
+        sort_by_activity(&mut courses);
+
+        let titles: Vec<&str> = courses.iter().map(|enrolled| enrolled.course.title.as_str()).collect();
+        assert_eq!(titles, vec!["Opened today", "Enrolled yesterday", "Opened last week", "Enrolled long ago"]);
+    }
+
+    #[test]
+    fn test_course_without_outline() {
+        let enrolled = enrollment("Empty", Utc::now(), None);
+
+        assert_eq!((enrolled.total_lessons, enrolled.completed_lessons), (0, 0));
+        assert_eq!(enrolled.percent_complete, 0);
+        assert_eq!(enrolled.last_accessed_at, None);
+        assert_eq!(enrolled.next_lesson, None);
+    }
+
+    #[test]
+    fn test_courses_with_learners_or_payments_are_kept() {
+        assert_eq!(course_deletion_allowed(0, 0), Ok(()));
+        assert_eq!(course_deletion_allowed(3, 0), Err(CourseDeletionBlocked::HasEnrollments(3)));
+        assert_eq!(course_deletion_allowed(0, 1), Err(CourseDeletionBlocked::HasPaidOrders(1)));
+        assert_eq!(course_deletion_allowed(2, 2), Err(CourseDeletionBlocked::HasPaidOrders(2)));
+    }
+}
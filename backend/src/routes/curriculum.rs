@@ -0,0 +1,671 @@
+use actix_web::{web, HttpResponse, Responder};
+use deadpool_postgres::GenericClient;
+use serde_json::json;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::db::progress::refresh_completion_for_course;
+use crate::middleware::auth::{Authorized, CanManageCourses};
+use crate::models::course::{
+    CourseLesson, CourseSection, CreateLessonRequest, CreateSectionRequest, ReorderRequest,
+    UpdateLessonRequest, UpdateSectionRequest,
+};
+use crate::routes::profiles::validate_url;
+use crate::security::roles::{can_manage_course, Permission};
+use crate::AppState;
+
+// Matches the VARCHAR(255) columns
+const MAX_TITLE_LENGTH: usize = 255;
+const MAX_VIDEO_URL_LENGTH: usize = 255;
+
+const SECTION_COLUMNS: &str = "id, course_id, title, description, position, created_at, updated_at";
+const LESSON_COLUMNS: &str = "id, section_id, title, content, video_url, position, created_at, updated_at";
+
+// What a route's id refers to; edits are authorized against the owning course
+#[derive(Clone, Copy)]
+enum Parent {
+    Course,
+    Section,
+    Lesson,
+}
+
+impl Parent {
+    // Looks up the owning course and locks it, so concurrent edits to the same
+    // course queue up instead of interleaving their position updates
+    fn query(self) -> &'static str {
+        match self {
+            Parent::Course => "SELECT created_by FROM courses WHERE id = $1 FOR UPDATE",
+            Parent::Section => {
+                "SELECT c.created_by FROM course_sections s
+                 JOIN courses c ON c.id = s.course_id
+                 WHERE s.id = $1 FOR UPDATE OF c"
+            }
+            Parent::Lesson => {
+                "SELECT c.created_by FROM course_lessons l
+                 JOIN course_sections s ON s.id = l.section_id
+                 JOIN courses c ON c.id = s.course_id
+                 WHERE l.id = $1 FOR UPDATE OF c"
+            }
+        }
+    }
+
+    fn not_found(self) -> &'static str {
+        match self {
+            Parent::Course => "Course not found",
+            Parent::Section => "Section not found",
+            Parent::Lesson => "Lesson not found",
+        }
+    }
+}
+
+// Add a section to a course
+pub async fn create_section(
+    instructor: Authorized<CanManageCourses>,
+    id: web::Path<Uuid>,
+    section_data: web::Json<CreateSectionRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let course_id = id.into_inner();
+    let section_data = section_data.into_inner();
+
+    let title = match validate_title(&section_data.title) {
+        Ok(title) => title,
+        Err(message) => return HttpResponse::BadRequest().json(json!({ "error": message })),
+    };
+
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(response) = authorize(&transaction, Parent::Course, course_id, &instructor).await {
+        return response;
+    }
+
+    let count: i64 = match transaction
+        .query_one("SELECT COUNT(*) FROM course_sections WHERE course_id = $1", &[&course_id])
+        .await
+    {
+        Ok(row) => row.get(0),
+        Err(e) => return database_error(e),
+    };
+
+    let position = insert_position(section_data.position, count);
+    let section = CourseSection::new(course_id, title, section_data.description, position);
+
+    let inserted = async {
+        transaction
+            .execute(
+                "UPDATE course_sections SET position = position + 1 WHERE course_id = $1 AND position >= $2",
+                &[&course_id, &position],
+            )
+            .await?;
+        transaction
+            .execute(
+                "INSERT INTO course_sections (id, course_id, title, description, position, created_at, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[
+                    &section.id,
+                    &section.course_id,
+                    &section.title,
+                    &section.description,
+                    &section.position,
+                    &section.created_at,
+                    &section.updated_at,
+                ],
+            )
+            .await?;
+        transaction.commit().await
+    };
+
+    match inserted.await {
+        Ok(()) => HttpResponse::Created().json(section),
+        Err(e) => database_error(e),
+    }
+}
+
+// Rename or describe a section
+pub async fn update_section(
+    instructor: Authorized<CanManageCourses>,
+    id: web::Path<Uuid>,
+    update_data: web::Json<UpdateSectionRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let section_id = id.into_inner();
+
+    let title = match update_data.title.as_deref().map(validate_title).transpose() {
+        Ok(title) => title,
+        Err(message) => return HttpResponse::BadRequest().json(json!({ "error": message })),
+    };
+
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(response) = authorize(&client, Parent::Section, section_id, &instructor).await {
+        return response;
+    }
+
+    match client
+        .query_one(
+            &format!(
+                "UPDATE course_sections
+                 SET title = COALESCE($2, title), description = COALESCE($3, description), updated_at = NOW()
+                 WHERE id = $1 RETURNING {}",
+                SECTION_COLUMNS
+            ),
+            &[&section_id, &title, &update_data.description],
+        )
+        .await
+    {
+        Ok(row) => HttpResponse::Ok().json(section_from_row(&row)),
+        Err(e) => database_error(e),
+    }
+}
+
+// Delete a section and its lessons; later sections move up to close the gap
+pub async fn delete_section(
+    instructor: Authorized<CanManageCourses>,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let section_id = id.into_inner();
+
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(response) = authorize(&transaction, Parent::Section, section_id, &instructor).await {
+        return response;
+    }
+
+    let deleted = async {
+        let row = transaction
+            .query_one(
+                "DELETE FROM course_sections WHERE id = $1 RETURNING course_id, position",
+                &[&section_id],
+            )
+            .await?;
+        let course_id: Uuid = row.get("course_id");
+        let position: i32 = row.get("position");
+
+        transaction
+            .execute(
+                "UPDATE course_sections SET position = position - 1 WHERE course_id = $1 AND position > $2",
+                &[&course_id, &position],
+            )
+            .await?;
+        // Learners may have finished everything that's left
+        refresh_completion_for_course(&transaction, course_id).await?;
+        transaction.commit().await
+    };
+
+    match deleted.await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "message": "Section deleted successfully"
+        })),
+        Err(e) => database_error(e),
+    }
+}
+
+// Put a course's sections in a new order; the request lists every section id
+pub async fn reorder_sections(
+    instructor: Authorized<CanManageCourses>,
+    id: web::Path<Uuid>,
+    order: web::Json<ReorderRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let course_id = id.into_inner();
+
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(response) = authorize(&transaction, Parent::Course, course_id, &instructor).await {
+        return response;
+    }
+
+    let current: Vec<Uuid> = match transaction
+        .query("SELECT id FROM course_sections WHERE course_id = $1", &[&course_id])
+        .await
+    {
+        Ok(rows) => rows.iter().map(|row| row.get("id")).collect(),
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(message) = check_order(&current, &order.ids) {
+        return HttpResponse::BadRequest().json(json!({ "error": message }));
+    }
+
+    let reordered = async {
+        transaction
+            .execute(
+                "UPDATE course_sections s SET position = o.ordinality - 1, updated_at = NOW()
+                 FROM unnest($1::uuid[]) WITH ORDINALITY AS o(id, ordinality)
+                 WHERE s.id = o.id",
+                &[&order.ids],
+            )
+            .await?;
+        let rows = transaction
+            .query(
+                &format!(
+                    "SELECT {} FROM course_sections WHERE course_id = $1 ORDER BY position",
+                    SECTION_COLUMNS
+                ),
+                &[&course_id],
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok::<_, tokio_postgres::Error>(rows)
+    };
+
+    match reordered.await {
+        Ok(rows) => {
+            let sections: Vec<CourseSection> = rows.iter().map(section_from_row).collect();
+            HttpResponse::Ok().json(sections)
+        }
+        Err(e) => database_error(e),
+    }
+}
+
+// Add a lesson to a section
+pub async fn create_lesson(
+    instructor: Authorized<CanManageCourses>,
+    id: web::Path<Uuid>,
+    lesson_data: web::Json<CreateLessonRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let section_id = id.into_inner();
+    let lesson_data = lesson_data.into_inner();
+
+    let (title, video_url) = match validate_title(&lesson_data.title)
+        .and_then(|title| Ok((title, validate_video_url(lesson_data.video_url)?)))
+    {
+        Ok(fields) => fields,
+        Err(message) => return HttpResponse::BadRequest().json(json!({ "error": message })),
+    };
+
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(response) = authorize(&transaction, Parent::Section, section_id, &instructor).await {
+        return response;
+    }
+
+    let count: i64 = match transaction
+        .query_one("SELECT COUNT(*) FROM course_lessons WHERE section_id = $1", &[&section_id])
+        .await
+    {
+        Ok(row) => row.get(0),
+        Err(e) => return database_error(e),
+    };
+
+    let position = insert_position(lesson_data.position, count);
+    let lesson = CourseLesson::new(section_id, title, lesson_data.content, video_url, position);
+
+    let inserted = async {
+        transaction
+            .execute(
+                "UPDATE course_lessons SET position = position + 1 WHERE section_id = $1 AND position >= $2",
+                &[&section_id, &position],
+            )
+            .await?;
+        transaction
+            .execute(
+                "INSERT INTO course_lessons (id, section_id, title, content, video_url, position, created_at, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[
+                    &lesson.id,
+                    &lesson.section_id,
+                    &lesson.title,
+                    &lesson.content,
+                    &lesson.video_url,
+                    &lesson.position,
+                    &lesson.created_at,
+                    &lesson.updated_at,
+                ],
+            )
+            .await?;
+        // Learners who had finished the course have a new lesson to do
+        let course_id: Uuid = transaction
+            .query_one("SELECT course_id FROM course_sections WHERE id = $1", &[&section_id])
+            .await?
+            .get("course_id");
+        refresh_completion_for_course(&transaction, course_id).await?;
+        transaction.commit().await
+    };
+
+    match inserted.await {
+        Ok(()) => HttpResponse::Created().json(lesson),
+        Err(e) => database_error(e),
+    }
+}
+
+// Edit a lesson's title, content or video
+pub async fn update_lesson(
+    instructor: Authorized<CanManageCourses>,
+    id: web::Path<Uuid>,
+    update_data: web::Json<UpdateLessonRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let lesson_id = id.into_inner();
+    let update_data = update_data.into_inner();
+
+    let (title, video_url) = match update_data
+        .title
+        .as_deref()
+        .map(validate_title)
+        .transpose()
+        .and_then(|title| Ok((title, validate_video_url(update_data.video_url)?)))
+    {
+        Ok(fields) => fields,
+        Err(message) => return HttpResponse::BadRequest().json(json!({ "error": message })),
+    };
+
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(response) = authorize(&client, Parent::Lesson, lesson_id, &instructor).await {
+        return response;
+    }
+
+    match client
+        .query_one(
+            &format!(
+                "UPDATE course_lessons
+                 SET title = COALESCE($2, title), content = COALESCE($3, content),
+                     video_url = COALESCE($4, video_url), updated_at = NOW()
+                 WHERE id = $1 RETURNING {}",
+                LESSON_COLUMNS
+            ),
+            &[&lesson_id, &title, &update_data.content, &video_url],
+        )
+        .await
+    {
+        Ok(row) => HttpResponse::Ok().json(lesson_from_row(&row)),
+        Err(e) => database_error(e),
+    }
+}
+
+// Delete a lesson; later lessons in its section move up to close the gap
+pub async fn delete_lesson(
+    instructor: Authorized<CanManageCourses>,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let lesson_id = id.into_inner();
+
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(response) = authorize(&transaction, Parent::Lesson, lesson_id, &instructor).await {
+        return response;
+    }
+
+    let deleted = async {
+        let row = transaction
+            .query_one(
+                "DELETE FROM course_lessons WHERE id = $1 RETURNING section_id, position",
+                &[&lesson_id],
+            )
+            .await?;
+        let section_id: Uuid = row.get("section_id");
+        let position: i32 = row.get("position");
+
+        transaction
+            .execute(
+                "UPDATE course_lessons SET position = position - 1 WHERE section_id = $1 AND position > $2",
+                &[&section_id, &position],
+            )
+            .await?;
+        // Learners may have finished everything that's left
+        let course_id: Uuid = transaction
+            .query_one("SELECT course_id FROM course_sections WHERE id = $1", &[&section_id])
+            .await?
+            .get("course_id");
+        refresh_completion_for_course(&transaction, course_id).await?;
+        transaction.commit().await
+    };
+
+    match deleted.await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "message": "Lesson deleted successfully"
+        })),
+        Err(e) => database_error(e),
+    }
+}
+
+// Put a section's lessons in a new order; the request lists every lesson id
+pub async fn reorder_lessons(
+    instructor: Authorized<CanManageCourses>,
+    id: web::Path<Uuid>,
+    order: web::Json<ReorderRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let section_id = id.into_inner();
+
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => return database_error(e),
+    };
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(response) = authorize(&transaction, Parent::Section, section_id, &instructor).await {
+        return response;
+    }
+
+    let current: Vec<Uuid> = match transaction
+        .query("SELECT id FROM course_lessons WHERE section_id = $1", &[&section_id])
+        .await
+    {
+        Ok(rows) => rows.iter().map(|row| row.get("id")).collect(),
+        Err(e) => return database_error(e),
+    };
+
+    if let Err(message) = check_order(&current, &order.ids) {
+        return HttpResponse::BadRequest().json(json!({ "error": message }));
+    }
+
+    let reordered = async {
+        transaction
+            .execute(
+                "UPDATE course_lessons l SET position = o.ordinality - 1, updated_at = NOW()
+                 FROM unnest($1::uuid[]) WITH ORDINALITY AS o(id, ordinality)
+                 WHERE l.id = o.id",
+                &[&order.ids],
+            )
+            .await?;
+        let rows = transaction
+            .query(
+                &format!(
+                    "SELECT {} FROM course_lessons WHERE section_id = $1 ORDER BY position",
+                    LESSON_COLUMNS
+                ),
+                &[&section_id],
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok::<_, tokio_postgres::Error>(rows)
+    };
+
+    match reordered.await {
+        Ok(rows) => {
+            let lessons: Vec<CourseLesson> = rows.iter().map(lesson_from_row).collect();
+            HttpResponse::Ok().json(lessons)
+        }
+        Err(e) => database_error(e),
+    }
+}
+
+// Check the caller may edit the course that `id` belongs to, locking that
+// course for the rest of the transaction
+async fn authorize<C: GenericClient>(
+    client: &C,
+    parent: Parent,
+    id: Uuid,
+    instructor: &Authorized<CanManageCourses>,
+) -> Result<(), HttpResponse> {
+    let created_by: Uuid = match client.query_opt(parent.query(), &[&id]).await {
+        Ok(Some(row)) => row.get("created_by"),
+        Ok(None) => {
+            return Err(HttpResponse::NotFound().json(json!({
+                "error": parent.not_found()
+            })));
+        }
+        Err(e) => return Err(database_error(e)),
+    };
+
+    let can_manage_all = instructor.has_permission(Permission::ManageAllCourses);
+    if !can_manage_course(instructor.user_id, can_manage_all, created_by) {
+        return Err(HttpResponse::Forbidden().json(json!({
+            "error": "You can only manage your own courses"
+        })));
+    }
+
+    Ok(())
+}
+
+// Where a new item goes: the requested slot clamped to the list, or the end
+fn insert_position(requested: Option<i32>, count: i64) -> i32 {
+    let count = i32::try_from(count).unwrap_or(i32::MAX);
+    requested.map_or(count, |position| position.clamp(0, count))
+}
+
+// A new order must name every current item exactly once
+fn check_order(current: &[Uuid], requested: &[Uuid]) -> Result<(), String> {
+    let requested_set: HashSet<&Uuid> = requested.iter().collect();
+    if requested_set.len() != requested.len() {
+        return Err("Each id may only appear once".to_string());
+    }
+
+    let current_set: HashSet<&Uuid> = current.iter().collect();
+    if requested_set != current_set {
+        return Err("The new order must list every item exactly once".to_string());
+    }
+
+    Ok(())
+}
+
+fn validate_title(title: &str) -> Result<String, String> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err("Title is required".to_string());
+    }
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(format!("Title must be at most {} characters", MAX_TITLE_LENGTH));
+    }
+    Ok(title.to_string())
+}
+
+fn validate_video_url(video_url: Option<String>) -> Result<Option<String>, String> {
+    let video_url = validate_url("video_url", video_url, &[])?;
+    if video_url.as_ref().is_some_and(|url| url.len() > MAX_VIDEO_URL_LENGTH) {
+        return Err(format!("video_url must be at most {} characters", MAX_VIDEO_URL_LENGTH));
+    }
+    Ok(video_url)
+}
+
+fn database_error(e: impl std::fmt::Display) -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({
+        "error": format!("Database error: {}", e)
+    }))
+}
+
+fn section_from_row(row: &tokio_postgres::Row) -> CourseSection {
+    CourseSection {
+        id: row.get("id"),
+        course_id: row.get("course_id"),
+        title: row.get("title"),
+        description: row.get("description"),
+        position: row.get("position"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+fn lesson_from_row(row: &tokio_postgres::Row) -> CourseLesson {
+    CourseLesson {
+        id: row.get("id"),
+        section_id: row.get("section_id"),
+        title: row.get("title"),
+        content: row.get("content"),
+        video_url: row.get("video_url"),
+        position: row.get("position"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_position() {
+        assert_eq!(insert_position(None, 3), 3);
+        assert_eq!(insert_position(Some(0), 3), 0);
+        assert_eq!(insert_position(Some(2), 3), 2);
+        assert_eq!(insert_position(Some(10), 3), 3);
+        assert_eq!(insert_position(Some(-1), 3), 0);
+        assert_eq!(insert_position(None, 0), 0);
+    }
+
+    #[test]
+    fn test_check_order() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let current = [a, b, c];
+
+        assert!(check_order(&current, &[c, a, b]).is_ok());
+        assert!(check_order(&[], &[]).is_ok());
+
+        // Missing, repeated and foreign ids are all rejected
+        assert!(check_order(&current, &[c, a]).is_err());
+        assert!(check_order(&current, &[c, a, a]).is_err());
+        assert!(check_order(&current, &[c, a, b, b]).is_err());
+        assert!(check_order(&current, &[c, a, Uuid::new_v4()]).is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(validate_title("  Intro  ").unwrap(), "Intro");
+        assert!(validate_title("   ").is_err());
+        assert!(validate_title(&"x".repeat(MAX_TITLE_LENGTH + 1)).is_err());
+
+        assert_eq!(validate_video_url(None).unwrap(), None);
+        assert!(validate_video_url(Some("https://example.com/v.mp4".to_string())).is_ok());
+        assert!(validate_video_url(Some("javascript:alert(1)".to_string())).is_err());
+        assert!(validate_video_url(Some(format!("https://example.com/{}", "v".repeat(300)))).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder, ResponseError};
+use serde_json::json;
+
+use crate::middleware::auth::AuthenticatedUser;
+use crate::models::user::{
+    AuthResponse, MfaVerifyRequest, RecoveryCodesResponse, TotpCodeRequest, TotpSetupResponse, User,
+};
+use crate::routes::auth::too_many_attempts;
+use crate::security::mfa::{
+    begin_totp_enrollment, check_second_factor, claim_mfa_attempt, confirm_totp_enrollment,
+    disable_totp, finish_mfa_challenge, replace_recovery_codes, validate_mfa_token,
+    SecondFactorRejected, TotpConfirmation,
+};
+use crate::security::refresh::{start_session, SessionDevice};
+use crate::security::totp::otpauth_uri;
+use crate::AppState;
+
+// Second login step: exchange an mfa pending token and a TOTP or recovery code for a session
+pub async fn verify(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    verify_data: web::Json<MfaVerifyRequest>,
+) -> impl Responder {
+    let pending = match validate_mfa_token(&verify_data.mfa_token, &data.config) {
+        Some(pending) => pending,
+        None => {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Invalid or expired login attempt, please sign in again"
+            }));
+        }
+    };
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let user_row = match client
+        .query_opt(
+            "SELECT id, email, full_name, is_admin, created_at, updated_at, web3_wallet, email_verified_at FROM users WHERE id = $1",
+            &[&pending.user_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Invalid or expired login attempt, please sign in again"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    // Each code tried uses up one attempt of the token's challenge
+    match claim_mfa_attempt(&client, pending).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Invalid or expired login attempt, please sign in again"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    }
+    
+    match check_second_factor(&client, pending.user_id, &verify_data.code, &data.config).await {
+        Ok(Ok(())) => (),
+        Ok(Err(SecondFactorRejected::Invalid)) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Invalid authentication code"
+            }));
+        }
+        Ok(Err(SecondFactorRejected::Throttled(throttled))) => return too_many_attempts(&throttled),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    }
+    
+    if let Err(e) = finish_mfa_challenge(&client, pending).await {
+        return HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
+    
+    let user = User {
+        id: user_row.get("id"),
+        email: user_row.get("email"),
+        password_hash: None, // Don't return password hash
+        full_name: user_row.get("full_name"),
+        is_admin: user_row.get("is_admin"),
+        created_at: user_row.get("created_at"),
+        updated_at: user_row.get("updated_at"),
+        web3_wallet: user_row.get("web3_wallet"),
+        email_verified_at: user_row.get("email_verified_at"),
+    };
+    
+    let device = SessionDevice::from_request(&req, &data.config);
+    let tokens = match start_session(&client, &user, &device, &data.config).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+    
+    HttpResponse::Ok().json(AuthResponse {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        user,
+    })
+}
+
+// Start TOTP enrollment; the secret only takes effect once confirmed with a code
+pub async fn setup_totp(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    let email: String = match client
+        .query_one("SELECT email FROM users WHERE id = $1", &[&auth_user.user_id])
+        .await
+    {
+        Ok(row) => row.get("email"),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match begin_totp_enrollment(&client, auth_user.user_id).await {
+        Ok(Some(secret)) => HttpResponse::Ok().json(TotpSetupResponse {
+            otpauth_uri: otpauth_uri(&data.config.totp_issuer, &email, &secret),
+            secret,
+        }),
+        Ok(None) => HttpResponse::Conflict().json(json!({
+            "error": "Two-factor authentication is already enabled"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Confirm TOTP enrollment with a code from the authenticator app
+pub async fn confirm_totp(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    code_data: web::Json<TotpCodeRequest>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match confirm_totp_enrollment(&mut client, auth_user.user_id, &code_data.code).await {
+        Ok(TotpConfirmation::Confirmed(recovery_codes)) => {
+            HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes })
+        }
+        Ok(TotpConfirmation::InvalidCode) => HttpResponse::BadRequest().json(json!({
+            "error": "Invalid authentication code"
+        })),
+        Ok(TotpConfirmation::NotPending) => HttpResponse::BadRequest().json(json!({
+            "error": "No two-factor enrollment is pending"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Turn off two-factor authentication; requires a current code
+pub async fn disable(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    code_data: web::Json<TotpCodeRequest>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match check_second_factor(&client, auth_user.user_id, &code_data.code, &data.config).await {
+        Ok(Ok(())) => (),
+        Ok(Err(SecondFactorRejected::Invalid)) => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Invalid authentication code"
+            }));
+        }
+        Ok(Err(SecondFactorRejected::Throttled(throttled))) => return too_many_attempts(&throttled),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    }
+    
+    match disable_totp(&mut client, auth_user.user_id).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Two-factor authentication disabled"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Replace the recovery codes; requires a current code
+pub async fn regenerate_recovery_codes(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    code_data: web::Json<TotpCodeRequest>,
+) -> impl Responder {
+    if let Err(e) = auth_user.require_session() {
+        return e.error_response();
+    }
+    
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    
+    match check_second_factor(&client, auth_user.user_id, &code_data.code, &data.config).await {
+        Ok(Ok(())) => (),
+        Ok(Err(SecondFactorRejected::Invalid)) => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Invalid authentication code"
+            }));
+        }
+        Ok(Err(SecondFactorRejected::Throttled(throttled))) => return too_many_attempts(&throttled),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    }
+    
+    match replace_recovery_codes(&client, auth_user.user_id).await {
+        Ok(recovery_codes) => HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
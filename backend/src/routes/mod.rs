@@ -3,3 +3,10 @@ pub mod portfolio;
 pub mod courses;
 pub mod blog;
 pub mod admin;
+pub mod mfa;
+pub mod account;
+pub mod profiles;
+pub mod uploads;
+pub mod curriculum;
+pub mod orders;
+pub mod coupons;
@@ -0,0 +1,461 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{Duration, Utc};
+use deadpool_postgres::GenericClient;
+use rust_decimal::Decimal;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::db::coupons::apply_coupon;
+use crate::middleware::auth::{AuthenticatedUser, VerifiedUser};
+use crate::models::coupon::CouponQuery;
+use crate::models::money::{Currency, Money};
+use crate::models::order::{Order, OrderStatus};
+use crate::web3::pay::{
+    find_payments, first_unclaimed, format_sol, new_reference, transfer_request_url,
+};
+use crate::AppState;
+
+// Shown by wallets as who is asking for the payment
+const PAYMENT_LABEL: &str = "Hex The Add Hub";
+
+const ORDER_QUERY: &str = "SELECT o.id, o.course_id, o.amount_lamports, o.recipient, o.reference,
+        o.status, o.signature, o.created_at, o.expires_at, o.paid_at, o.discount,
+        c.title AS course_title, cp.code AS coupon_code
+    FROM orders o JOIN courses c ON c.id = o.course_id
+    LEFT JOIN coupons cp ON cp.id = o.coupon_id";
+
+// Start paying for a course, optionally with `?coupon=CODE`. An unexpired
+// pending order for the same course and price is returned instead of creating another.
+pub async fn checkout(
+    auth_user: VerifiedUser,
+    id: web::Path<Uuid>,
+    query: web::Query<CouponQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let recipient = match &data.config.payment_recipient {
+        Some(recipient) => recipient,
+        None => {
+            return HttpResponse::ServiceUnavailable().json(json!({
+                "error": "Paid checkout is not available"
+            }));
+        }
+    };
+
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    let course_id = id.into_inner();
+
+    // The coupon stays locked until the order holding it is committed
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    let (price, is_free) = match transaction
+        .query_opt("SELECT price, currency, is_free FROM courses WHERE id = $1", &[&course_id])
+        .await
+    {
+        Ok(Some(row)) => (
+            Money::new(row.get("price"), row.get("currency")),
+            row.get::<_, bool>("is_free"),
+        ),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Course not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    if is_free {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "This course is free; enroll directly"
+        }));
+    }
+
+    let already_enrolled = match transaction
+        .query_one(
+            "SELECT EXISTS(SELECT 1 FROM user_enrollments WHERE user_id = $1 AND course_id = $2)",
+            &[&auth_user.user_id, &course_id],
+        )
+        .await
+    {
+        Ok(row) => row.get::<_, bool>(0),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    if already_enrolled {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "You are already enrolled in this course"
+        }));
+    }
+
+    let coupon = match &query.coupon {
+        Some(code) => match apply_coupon(&transaction, code, auth_user.user_id, course_id, price).await {
+            Ok(Ok(coupon)) => Some(coupon),
+            Ok(Err(e)) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "error": e.to_string()
+                }));
+            }
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "error": format!("Database error: {}", e)
+                }));
+            }
+        },
+        None => None,
+    };
+    let coupon_id = coupon.as_ref().map(|coupon| coupon.coupon_id);
+    let discount = coupon.as_ref().map_or(Decimal::ZERO, |coupon| coupon.discount.amount);
+    let price = coupon.as_ref().map_or(price, |coupon| coupon.price);
+
+    if price.is_zero() {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "The coupon covers the full price; enroll with it directly"
+        }));
+    }
+
+    // Solana Pay requests native SOL, so the order is kept in lamports
+    let amount_lamports = match price.currency {
+        Currency::Sol => price.to_minor_units().and_then(|lamports| i64::try_from(lamports).ok()),
+    };
+    let amount_lamports = match amount_lamports {
+        Some(lamports) if lamports > 0 => lamports,
+        _ => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Course has an invalid price: {}", price)
+            }));
+        }
+    };
+
+    // Reuse the open order so a user can't scatter payments across several references
+    match transaction
+        .query_opt(
+            &format!(
+                "{} WHERE o.user_id = $1 AND o.course_id = $2 AND o.status = 'pending'
+                   AND o.expires_at > NOW() AND o.amount_lamports = $3
+                   AND o.coupon_id IS NOT DISTINCT FROM $4
+                 ORDER BY o.created_at DESC LIMIT 1",
+                ORDER_QUERY
+            ),
+            &[&auth_user.user_id, &course_id, &amount_lamports, &coupon_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => return HttpResponse::Ok().json(order_from_row(&row)),
+        Ok(None) => (),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    let order_id = Uuid::new_v4();
+    let expires_at = Utc::now() + Duration::seconds(data.config.order_ttl_secs);
+
+    let inserted = async {
+        transaction
+            .execute(
+                "INSERT INTO orders (id, user_id, course_id, amount_lamports, recipient, reference, expires_at, coupon_id, discount)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                &[
+                    &order_id,
+                    &auth_user.user_id,
+                    &course_id,
+                    &amount_lamports,
+                    recipient,
+                    &new_reference(),
+                    &expires_at,
+                    &coupon_id,
+                    &discount,
+                ],
+            )
+            .await?;
+        transaction.commit().await
+    };
+    if let Err(e) = inserted.await {
+        return HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
+
+    match load_order(&client, auth_user.user_id, order_id).await {
+        Ok(Some(order)) => HttpResponse::Created().json(order),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "Order not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Check the chain for an order's payment and enroll the buyer once it is there.
+// Clients poll this after showing the payment request.
+pub async fn verify_order(
+    auth_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let mut client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    let order_id = id.into_inner();
+
+    let order = match load_order(&client, auth_user.user_id, order_id).await {
+        Ok(Some(order)) => order,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Order not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    if order.status == OrderStatus::Paid {
+        return HttpResponse::Ok().json(order);
+    }
+
+    // A payment that arrives after the order expired is still honored
+    let payments = match find_payments(
+        data.solana_rpc.as_ref(),
+        &order.recipient,
+        &order.reference,
+        order.amount_lamports as u64,
+    )
+    .await
+    {
+        Ok(payments) => payments,
+        Err(e) => {
+            return HttpResponse::BadGateway().json(json!({
+                "error": e.to_string()
+            }));
+        }
+    };
+    if payments.is_empty() {
+        return HttpResponse::Ok().json(order);
+    }
+
+    // A transaction already used to pay another order can't pay this one too
+    let claimed: Vec<String> = match client
+        .query(
+            "SELECT signature FROM orders WHERE signature = ANY($1) AND id <> $2",
+            &[&payments, &order_id],
+        )
+        .await
+    {
+        Ok(rows) => rows.iter().map(|row| row.get("signature")).collect(),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+    let signature = match first_unclaimed(payments, &claimed) {
+        Some(signature) => signature,
+        None => return HttpResponse::Ok().json(order),
+    };
+
+    let transaction = match client.transaction().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    let fulfilled = async {
+        // A concurrent verify may have got here first; only one marks it paid
+        let marked = transaction
+            .execute(
+                "UPDATE orders SET status = 'paid', signature = $2, paid_at = NOW()
+                 WHERE id = $1 AND status = 'pending'",
+                &[&order_id, &signature],
+            )
+            .await?;
+        if marked > 0 {
+            let amount_paid = Money::from_minor_units(order.amount_lamports, Currency::Sol);
+            transaction
+                .execute(
+                    "INSERT INTO user_enrollments (user_id, course_id, enrolled_at, amount_paid, currency, coupon_id, order_id)
+                     SELECT $1, $2, NOW(), $3, $4, coupon_id, id FROM orders WHERE id = $5
+                     ON CONFLICT (user_id, course_id) DO NOTHING",
+                    &[
+                        &auth_user.user_id,
+                        &order.course_id,
+                        &amount_paid.amount,
+                        &amount_paid.currency,
+                        &order_id,
+                    ],
+                )
+                .await?;
+            transaction
+                .execute(
+                    "INSERT INTO coupon_redemptions (id, coupon_id, user_id, course_id, order_id, discount)
+                     SELECT $1, coupon_id, user_id, course_id, id, discount FROM orders
+                     WHERE id = $2 AND coupon_id IS NOT NULL
+                     ON CONFLICT (coupon_id, user_id, course_id) DO NOTHING",
+                    &[&Uuid::new_v4(), &order_id],
+                )
+                .await?;
+        }
+        transaction.commit().await
+    };
+    if let Err(e) = fulfilled.await {
+        return HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        }));
+    }
+
+    match load_order(&client, auth_user.user_id, order_id).await {
+        Ok(Some(order)) => HttpResponse::Ok().json(order),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "Order not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Get one of the current user's orders
+pub async fn get_order(
+    auth_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    match load_order(&client, auth_user.user_id, id.into_inner()).await {
+        Ok(Some(order)) => HttpResponse::Ok().json(order),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "Order not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// List the current user's orders, newest first
+pub async fn list_orders(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    match client
+        .query(
+            &format!("{} WHERE o.user_id = $1 ORDER BY o.created_at DESC", ORDER_QUERY),
+            &[&auth_user.user_id],
+        )
+        .await
+    {
+        Ok(rows) => {
+            let orders: Vec<Order> = rows.iter().map(order_from_row).collect();
+            HttpResponse::Ok().json(orders)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+async fn load_order<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    order_id: Uuid,
+) -> Result<Option<Order>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            &format!("{} WHERE o.id = $1 AND o.user_id = $2", ORDER_QUERY),
+            &[&order_id, &user_id],
+        )
+        .await?;
+
+    Ok(row.as_ref().map(order_from_row))
+}
+
+fn order_from_row(row: &tokio_postgres::Row) -> Order {
+    let amount_lamports: i64 = row.get("amount_lamports");
+    let recipient: String = row.get("recipient");
+    let reference: String = row.get("reference");
+    let course_title: String = row.get("course_title");
+    let status: String = row.get("status");
+    let expires_at = row.get("expires_at");
+
+    let status = match status.as_str() {
+        "paid" => OrderStatus::Paid,
+        _ if expires_at <= Utc::now() => OrderStatus::Expired,
+        _ => OrderStatus::Pending,
+    };
+
+    Order {
+        id: row.get("id"),
+        course_id: row.get("course_id"),
+        status,
+        amount_lamports,
+        amount_sol: format_sol(amount_lamports as u64),
+        coupon_code: row.get("coupon_code"),
+        discount: Money::new(row.get("discount"), Currency::Sol),
+        payment_url: transfer_request_url(
+            &recipient,
+            amount_lamports as u64,
+            &reference,
+            PAYMENT_LABEL,
+            &course_title,
+        ),
+        recipient,
+        reference,
+        signature: row.get("signature"),
+        created_at: row.get("created_at"),
+        expires_at,
+        paid_at: row.get("paid_at"),
+    }
+}
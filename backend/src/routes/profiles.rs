@@ -0,0 +1,287 @@
+use actix_web::{web, HttpResponse, Responder};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use mongodb::options::{FindOptions, ReplaceOptions};
+use reqwest::Url;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::middleware::auth::AuthenticatedUser;
+use crate::models::blog::BlogPost;
+use crate::models::portfolio::PortfolioItem;
+use crate::models::user::{PublicProfile, SocialLinks, UpdateProfileRequest, UserProfile};
+use crate::AppState;
+
+const MAX_BIO_LENGTH: usize = 2000;
+const MAX_URL_LENGTH: usize = 2048;
+const MAX_SKILLS: usize = 30;
+const MAX_SKILL_LENGTH: usize = 50;
+
+// Get a user's public profile, with their portfolio and published blog posts
+pub async fn get_profile(id: web::Path<Uuid>, data: web::Data<AppState>) -> impl Responder {
+    match load_public_profile(&data, id.into_inner()).await {
+        Ok(Some(profile)) => HttpResponse::Ok().json(profile),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": e
+        })),
+    }
+}
+
+// Get the current user's public profile
+pub async fn get_my_profile(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    match load_public_profile(&data, auth_user.user_id).await {
+        Ok(Some(profile)) => HttpResponse::Ok().json(profile),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": e
+        })),
+    }
+}
+
+// Create or replace the current user's profile
+pub async fn update_my_profile(
+    auth_user: AuthenticatedUser,
+    profile_data: web::Json<UpdateProfileRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let profile = match validate_profile(auth_user.user_id, profile_data.into_inner()) {
+        Ok(profile) => profile,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": message
+            }));
+        }
+    };
+
+    let db = &data.mongo_client.database("hex_the_add_hub");
+    let collection = db.collection::<UserProfile>("user_profiles");
+
+    let filter = doc! { "user_id": auth_user.user_id.to_string() };
+    let options = ReplaceOptions::builder().upsert(true).build();
+
+    match collection.replace_one(filter, &profile, options).await {
+        Ok(_) => HttpResponse::Ok().json(profile),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Failed to save profile: {}", e)
+        })),
+    }
+}
+
+async fn load_public_profile(
+    data: &AppState,
+    user_id: Uuid,
+) -> Result<Option<PublicProfile>, String> {
+    let client = data
+        .pg_pool
+        .get()
+        .await
+        .map_err(|e| format!("Database error: {}", e))?;
+
+    let full_name: String = match client
+        .query_opt("SELECT full_name FROM users WHERE id = $1", &[&user_id])
+        .await
+        .map_err(|e| format!("Database error: {}", e))?
+    {
+        Some(row) => row.get("full_name"),
+        None => return Ok(None),
+    };
+
+    let db = data.mongo_client.database("hex_the_add_hub");
+    let id = user_id.to_string();
+
+    // Users who never saved a profile get an empty one
+    let profile = db
+        .collection::<UserProfile>("user_profiles")
+        .find_one(doc! { "user_id": &id }, None)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?
+        .unwrap_or(UserProfile {
+            user_id,
+            bio: None,
+            profile_picture: None,
+            social_links: SocialLinks::default(),
+            skills: Vec::new(),
+        });
+
+    let newest_first = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+
+    let portfolio: Vec<PortfolioItem> = db
+        .collection::<PortfolioItem>("portfolios")
+        .find(doc! { "user_id": &id }, newest_first.clone())
+        .await
+        .map_err(|e| format!("Database error: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to collect portfolio items: {}", e))?;
+
+    let blog_posts: Vec<BlogPost> = db
+        .collection::<BlogPost>("blog_posts")
+        .find(doc! { "author_id": &id, "published": true }, newest_first)
+        .await
+        .map_err(|e| format!("Database error: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to collect blog posts: {}", e))?;
+
+    Ok(Some(PublicProfile {
+        user_id,
+        full_name,
+        bio: profile.bio,
+        profile_picture: profile.profile_picture,
+        social_links: profile.social_links,
+        skills: profile.skills,
+        portfolio,
+        blog_posts,
+    }))
+}
+
+// Check and tidy a profile update: trims text, drops blanks and duplicate
+// skills, and makes sure each social link points at the right site
+fn validate_profile(user_id: Uuid, request: UpdateProfileRequest) -> Result<UserProfile, String> {
+    let bio = non_blank(request.bio);
+    if bio.as_ref().is_some_and(|bio| bio.chars().count() > MAX_BIO_LENGTH) {
+        return Err(format!("Bio must be at most {} characters", MAX_BIO_LENGTH));
+    }
+
+    let profile_picture = validate_url("profile_picture", request.profile_picture, &[])?;
+
+    let links = request.social_links;
+    let social_links = SocialLinks {
+        twitter: validate_url("twitter", links.twitter, &["twitter.com", "x.com"])?,
+        linkedin: validate_url("linkedin", links.linkedin, &["linkedin.com"])?,
+        github: validate_url("github", links.github, &["github.com"])?,
+        website: validate_url("website", links.website, &[])?,
+    };
+
+    let mut skills: Vec<String> = Vec::new();
+    for skill in request.skills {
+        let skill = skill.trim();
+        if skill.is_empty() {
+            continue;
+        }
+        if skill.chars().count() > MAX_SKILL_LENGTH {
+            return Err(format!("Skills must be at most {} characters", MAX_SKILL_LENGTH));
+        }
+        if !skills.iter().any(|existing| existing.eq_ignore_ascii_case(skill)) {
+            skills.push(skill.to_string());
+        }
+    }
+    if skills.len() > MAX_SKILLS {
+        return Err(format!("At most {} skills are allowed", MAX_SKILLS));
+    }
+
+    Ok(UserProfile {
+        user_id,
+        bio,
+        profile_picture,
+        social_links,
+        skills,
+    })
+}
+
+// An http(s) URL, optionally restricted to some hosts (and their subdomains)
+pub fn validate_url(
+    field: &str,
+    value: Option<String>,
+    allowed_hosts: &[&str],
+) -> Result<Option<String>, String> {
+    let value = match non_blank(value) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    let invalid = || format!("{} must be a valid http(s) URL", field);
+
+    if value.len() > MAX_URL_LENGTH {
+        return Err(invalid());
+    }
+
+    let url = Url::parse(&value).map_err(|_| invalid())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid());
+    }
+
+    let host = url.host_str().ok_or_else(invalid)?.to_ascii_lowercase();
+    let host_allowed = allowed_hosts.is_empty()
+        || allowed_hosts
+            .iter()
+            .any(|allowed| host == *allowed || host.ends_with(&format!(".{}", allowed)));
+    if !host_allowed {
+        return Err(format!("{} must be a link to {}", field, allowed_hosts.join(" or ")));
+    }
+
+    Ok(Some(value))
+}
+
+fn non_blank(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> UpdateProfileRequest {
+        UpdateProfileRequest {
+            bio: Some("  Rust and Solana developer  ".to_string()),
+            profile_picture: None,
+            social_links: SocialLinks {
+                twitter: Some("https://x.com/hexdev".to_string()),
+                linkedin: Some("https://www.linkedin.com/in/hexdev".to_string()),
+                github: Some("https://github.com/hexdev".to_string()),
+                website: Some("".to_string()),
+            },
+            skills: vec!["Rust".to_string(), " rust ".to_string(), "".to_string(), "Solana".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_valid_profile_is_tidied() {
+        let profile = validate_profile(Uuid::new_v4(), request()).unwrap();
+
+        assert_eq!(profile.bio.as_deref(), Some("Rust and Solana developer"));
+        assert_eq!(profile.skills, vec!["Rust", "Solana"]);
+        assert_eq!(profile.social_links.website, None);
+    }
+
+    #[test]
+    fn test_social_links_must_match_site() {
+        let mut bad = request();
+        bad.social_links.github = Some("https://gitlab.com/hexdev".to_string());
+        assert!(validate_profile(Uuid::new_v4(), bad).is_err());
+
+        // Lookalike domains don't pass as subdomains
+        let mut bad = request();
+        bad.social_links.github = Some("https://evilgithub.com/hexdev".to_string());
+        assert!(validate_profile(Uuid::new_v4(), bad).is_err());
+
+        let mut bad = request();
+        bad.social_links.website = Some("javascript:alert(1)".to_string());
+        assert!(validate_profile(Uuid::new_v4(), bad).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut bad = request();
+        bad.bio = Some("a".repeat(MAX_BIO_LENGTH + 1));
+        assert!(validate_profile(Uuid::new_v4(), bad).is_err());
+
+        let mut bad = request();
+        bad.skills = (0..=MAX_SKILLS).map(|i| format!("skill {}", i)).collect();
+        assert!(validate_profile(Uuid::new_v4(), bad).is_err());
+
+        let mut bad = request();
+        bad.skills = vec!["x".repeat(MAX_SKILL_LENGTH + 1)];
+        assert!(validate_profile(Uuid::new_v4(), bad).is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use actix_multipart::Multipart;
+use actix_web::{http::header, web, HttpResponse, Responder};
+use futures::TryStreamExt;
+use log::warn;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::media::{media_url, process_image, storage_key, thumbnail_key, MediaKind};
+use crate::middleware::auth::{AuthenticatedUser, VerifiedUser};
+use crate::models::media::MediaItem;
+use crate::AppState;
+
+// Multipart field the file is sent in
+const UPLOAD_FIELD: &str = "file";
+
+enum UploadError {
+    Missing,
+    TooLarge,
+    Malformed(String),
+}
+
+// Upload an image. The type is taken from the file contents, never the
+// client's claim; a thumbnail is generated alongside it.
+pub async fn upload_media(
+    auth_user: VerifiedUser,
+    payload: Multipart,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let max_bytes = data.config.media_max_upload_bytes;
+
+    let bytes = match read_upload(payload, max_bytes).await {
+        Ok(bytes) => bytes,
+        Err(UploadError::Missing) => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": format!("Send the file in a multipart field named \"{}\"", UPLOAD_FIELD)
+            }));
+        }
+        Err(UploadError::TooLarge) => {
+            return HttpResponse::PayloadTooLarge().json(json!({
+                "error": format!("Uploads must be at most {} bytes", max_bytes)
+            }));
+        }
+        Err(UploadError::Malformed(e)) => {
+            return HttpResponse::BadRequest().json(json!({
+                "error": format!("Invalid upload: {}", e)
+            }));
+        }
+    };
+
+    let kind = match MediaKind::sniff(&bytes) {
+        Some(kind) => kind,
+        None => {
+            return HttpResponse::UnsupportedMediaType().json(json!({
+                "error": "Only PNG, JPEG, GIF and WebP images can be uploaded"
+            }));
+        }
+    };
+
+    // Decoding is CPU-bound, so keep it off the async workers
+    let (bytes, processed) = match web::block(move || {
+        let processed = process_image(&bytes, kind);
+        (bytes, processed)
+    })
+    .await
+    {
+        Ok((bytes, Ok(processed))) => (bytes, processed),
+        Ok((_, Err(e))) => {
+            return HttpResponse::UnprocessableEntity().json(json!({
+                "error": e
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Failed to process image: {}", e)
+            }));
+        }
+    };
+
+    let id = Uuid::new_v4();
+    let key = storage_key(id, kind);
+    let thumb_key = thumbnail_key(id, kind);
+    let size_bytes = bytes.len() as i64;
+
+    let store = &data.media_store;
+    if let Err(e) = store.put(&key, kind.content_type(), bytes).await {
+        return HttpResponse::InternalServerError().json(json!({
+            "error": e.to_string()
+        }));
+    }
+    if let Err(e) = store
+        .put(&thumb_key, kind.thumbnail_kind().content_type(), processed.thumbnail)
+        .await
+    {
+        remove_files(&data, &[&key]).await;
+        return HttpResponse::InternalServerError().json(json!({
+            "error": e.to_string()
+        }));
+    }
+
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            remove_files(&data, &[&key, &thumb_key]).await;
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    match client
+        .query_one(
+            "INSERT INTO media (id, owner_id, storage_key, thumbnail_key, content_type, size_bytes, width, height)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             RETURNING id, storage_key, thumbnail_key, content_type, size_bytes, width, height, created_at",
+            &[
+                &id,
+                &auth_user.user_id,
+                &key,
+                &thumb_key,
+                &kind.content_type(),
+                &size_bytes,
+                &(processed.width as i32),
+                &(processed.height as i32),
+            ],
+        )
+        .await
+    {
+        Ok(row) => HttpResponse::Created().json(media_item_from_row(&data.config, &row)),
+        Err(e) => {
+            remove_files(&data, &[&key, &thumb_key]).await;
+            HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }))
+        }
+    }
+}
+
+// List the current user's uploads, newest first
+pub async fn list_media(
+    auth_user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    match client
+        .query(
+            "SELECT id, storage_key, thumbnail_key, content_type, size_bytes, width, height, created_at
+             FROM media WHERE owner_id = $1 ORDER BY created_at DESC",
+            &[&auth_user.user_id],
+        )
+        .await
+    {
+        Ok(rows) => {
+            let items: Vec<MediaItem> = rows
+                .iter()
+                .map(|row| media_item_from_row(&data.config, row))
+                .collect();
+            HttpResponse::Ok().json(items)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Delete one of the current user's uploads. Anything still pointing at it
+// will show a broken image.
+pub async fn delete_media(
+    auth_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    let media_id = id.into_inner();
+
+    let row = match client
+        .query_opt(
+            "SELECT storage_key, thumbnail_key FROM media WHERE id = $1 AND owner_id = $2",
+            &[&media_id, &auth_user.user_id],
+        )
+        .await
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Media not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    // Files go first so a storage failure leaves the row for a retry
+    for key in [row.get::<_, String>("storage_key"), row.get("thumbnail_key")] {
+        if let Err(e) = data.media_store.delete(&key).await {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": e.to_string()
+            }));
+        }
+    }
+
+    match client
+        .execute("DELETE FROM media WHERE id = $1", &[&media_id])
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Media deleted successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": format!("Database error: {}", e)
+        })),
+    }
+}
+
+// Serve an uploaded file or thumbnail by its storage key. Only files recorded
+// in the media table are served.
+pub async fn serve_media(
+    key: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let client = match data.pg_pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    let key = key.into_inner();
+
+    let row = match client
+        .query_opt(
+            "SELECT content_type, thumbnail_key FROM media WHERE storage_key = $1 OR thumbnail_key = $1",
+            &[&key],
+        )
+        .await
+    {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "error": "Media not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "error": format!("Database error: {}", e)
+            }));
+        }
+    };
+
+    let content_type: String = row.get("content_type");
+    let is_thumbnail = row.get::<_, String>("thumbnail_key") == key;
+    let content_type = match MediaKind::from_content_type(&content_type) {
+        Some(kind) if is_thumbnail => kind.thumbnail_kind().content_type().to_string(),
+        _ => content_type,
+    };
+
+    match data.media_store.get(&key).await {
+        // Keys are never reused, so files can be cached forever
+        Ok(Some(bytes)) => HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header((header::CACHE_CONTROL, "public, max-age=31536000, immutable"))
+            .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+            .body(bytes),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "error": "Media not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "error": e.to_string()
+        })),
+    }
+}
+
+// Read the upload field, stopping as soon as it goes over the size limit
+async fn read_upload(mut payload: Multipart, max_bytes: usize) -> Result<Vec<u8>, UploadError> {
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|e| UploadError::Malformed(e.to_string()))?
+    {
+        if field.name() != UPLOAD_FIELD {
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(|e| UploadError::Malformed(e.to_string()))?
+        {
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(UploadError::TooLarge);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok(bytes);
+    }
+
+    Err(UploadError::Missing)
+}
+
+// Best-effort cleanup after a failed upload; leftovers are only wasted space
+async fn remove_files(data: &AppState, keys: &[&str]) {
+    for key in keys {
+        if let Err(e) = data.media_store.delete(key).await {
+            warn!("Failed to remove media file {}: {}", key, e);
+        }
+    }
+}
+
+fn media_item_from_row(config: &AppConfig, row: &tokio_postgres::Row) -> MediaItem {
+    MediaItem {
+        id: row.get("id"),
+        url: media_url(config, row.get("storage_key")),
+        thumbnail_url: media_url(config, row.get("thumbnail_key")),
+        content_type: row.get("content_type"),
+        size_bytes: row.get("size_bytes"),
+        width: row.get("width"),
+        height: row.get("height"),
+        created_at: row.get("created_at"),
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use rand::RngCore;
+use uuid::Uuid;
+
+use crate::security::roles::Permission;
+use crate::security::tokens::{generate_opaque_token, hash_token};
+
+// Marks a Bearer credential as an API key rather than a JWT
+pub const API_KEY_PREFIX: &str = "hxk_";
+
+const KEY_ID_BYTES: usize = 6;
+
+// Longest lifetime a key can be created with
+pub const MAX_API_KEY_TTL_DAYS: i64 = 365;
+
+// A freshly generated key. `key` is shown to the user once; only the rest is stored.
+pub struct NewApiKey {
+    pub key: String,
+    pub prefix: String,
+    pub secret_hash: String,
+}
+
+// An API key that checked out: not revoked, not expired, secret matches
+pub struct ApiKeyCredential {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<Permission>,
+}
+
+// Keys look like `hxk_<prefix>_<secret>`. The prefix identifies the key in
+// listings and logs without revealing the secret.
+pub fn generate_api_key() -> NewApiKey {
+    let mut bytes = [0u8; KEY_ID_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let prefix = hex::encode(bytes);
+    let secret = generate_opaque_token();
+
+    NewApiKey {
+        key: format!("{}{}_{}", API_KEY_PREFIX, prefix, secret),
+        secret_hash: hash_token(&secret),
+        prefix,
+    }
+}
+
+// Split a presented key into its prefix and secret
+pub fn parse_api_key(key: &str) -> Option<(&str, &str)> {
+    let (prefix, secret) = key.strip_prefix(API_KEY_PREFIX)?.split_once('_')?;
+    if prefix.len() != KEY_ID_BYTES * 2 || secret.is_empty() {
+        return None;
+    }
+
+    Some((prefix, secret))
+}
+
+// Scopes are permission names. Unknown names are rejected with the offending name.
+pub fn parse_scopes(scopes: &[String]) -> Result<Vec<Permission>, String> {
+    let mut permissions = Vec::new();
+    for scope in scopes {
+        let permission = Permission::from_name(scope).ok_or_else(|| scope.clone())?;
+        if !permissions.contains(&permission) {
+            permissions.push(permission);
+        }
+    }
+
+    Ok(permissions)
+}
+
+// A key can never do more than its owner can do right now
+pub fn effective_permissions(scopes: &[Permission], granted: &[Permission]) -> Vec<Permission> {
+    scopes
+        .iter()
+        .copied()
+        .filter(|scope| granted.contains(scope))
+        .collect()
+}
+
+// Look up a presented key and record that it was used
+pub async fn verify_api_key<C: GenericClient>(
+    client: &C,
+    key: &str,
+) -> Result<Option<ApiKeyCredential>, tokio_postgres::Error> {
+    let (prefix, secret) = match parse_api_key(key) {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+
+    let row = client
+        .query_opt(
+            "SELECT id, user_id, scopes FROM api_keys
+             WHERE prefix = $1 AND secret_hash = $2 AND revoked_at IS NULL
+               AND (expires_at IS NULL OR expires_at > NOW())",
+            &[&prefix, &hash_token(secret)],
+        )
+        .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let id: Uuid = row.get("id");
+
+    // Coarse-grained so busy scripts don't write on every request
+    client
+        .execute(
+            "UPDATE api_keys SET last_used_at = NOW()
+             WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')",
+            &[&id],
+        )
+        .await?;
+
+    Ok(Some(ApiKeyCredential {
+        id,
+        user_id: row.get("user_id"),
+        scopes: row
+            .get::<_, Vec<String>>("scopes")
+            .iter()
+            .filter_map(|name| Permission::from_name(name))
+            .collect(),
+    }))
+}
+
+pub async fn insert_api_key<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    name: &str,
+    new_key: &NewApiKey,
+    scopes: &[Permission],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Uuid, tokio_postgres::Error> {
+    let id = Uuid::new_v4();
+    let scopes: Vec<&str> = scopes.iter().map(|scope| scope.as_str()).collect();
+
+    client
+        .execute(
+            "INSERT INTO api_keys (id, user_id, name, prefix, secret_hash, scopes, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[
+                &id,
+                &user_id,
+                &name,
+                &new_key.prefix,
+                &new_key.secret_hash,
+                &scopes,
+                &expires_at,
+            ],
+        )
+        .await?;
+
+    Ok(id)
+}
+
+// Revoke every live key a user has. Keys act with the owner's credentials and
+// roles, so they go whenever those change or the owner signs out everywhere.
+pub async fn revoke_user_api_keys<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            "UPDATE api_keys SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+            &[&user_id],
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_keys_parse() {
+        let new_key = generate_api_key();
+        let (prefix, secret) = parse_api_key(&new_key.key).unwrap();
+
+        assert!(new_key.key.starts_with(API_KEY_PREFIX));
+        assert_eq!(prefix, new_key.prefix);
+        assert_eq!(hash_token(secret), new_key.secret_hash);
+        assert_ne!(generate_api_key().key, new_key.key);
+    }
+
+    #[test]
+    fn test_rejects_malformed_keys() {
+        assert!(parse_api_key("eyJhbGciOiJIUzI1NiJ9.e30.sig").is_none());
+        assert!(parse_api_key("hxk_").is_none());
+        assert!(parse_api_key("hxk_abc_secret").is_none());
+        assert!(parse_api_key("hxk_0123456789ab_").is_none());
+        assert!(parse_api_key("hxk_0123456789ab_secret").is_some());
+    }
+
+    #[test]
+    fn test_scopes() {
+        let scopes = vec!["blog:write".to_string(), "blog:write".to_string()];
+        assert_eq!(parse_scopes(&scopes), Ok(vec![Permission::WriteBlog]));
+
+        let scopes = vec!["blog:write".to_string(), "everything".to_string()];
+        assert_eq!(parse_scopes(&scopes), Err("everything".to_string()));
+
+        // Scopes the owner has lost no longer apply
+        let scopes = [Permission::WriteBlog, Permission::ManageOwnCourses];
+        assert_eq!(
+            effective_permissions(&scopes, &[Permission::ManageOwnCourses]),
+            vec![Permission::ManageOwnCourses]
+        );
+    }
+}
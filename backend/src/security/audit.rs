@@ -0,0 +1,35 @@
+use deadpool_postgres::GenericClient;
+use uuid::Uuid;
+
+// Security-relevant event to keep in the audit log
+pub struct AuditEntry<'a> {
+    pub event: &'a str,
+    // User the event is about, if known
+    pub user_id: Option<Uuid>,
+    // User who caused the event, when different (e.g. an admin)
+    pub actor_id: Option<Uuid>,
+    pub ip_address: Option<&'a str>,
+    pub details: serde_json::Value,
+}
+
+pub async fn record<C: GenericClient>(
+    client: &C,
+    entry: AuditEntry<'_>,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "INSERT INTO audit_log (id, event, user_id, actor_id, ip_address, details)
+             VALUES ($1, $2, $3, $4, $5, $6::text::jsonb)",
+            &[
+                &Uuid::new_v4(),
+                &entry.event,
+                &entry.user_id,
+                &entry.actor_id,
+                &entry.ip_address,
+                &entry.details.to_string(),
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
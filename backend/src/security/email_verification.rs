@@ -0,0 +1,138 @@
+use chrono::{Duration, Utc};
+use deadpool_postgres::{Client, GenericClient};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::mail::MailMessage;
+use crate::security::tokens::{generate_opaque_token, hash_token};
+
+// Basic syntax check for an email address; deliverability is proven by verification
+pub fn is_valid_email(email: &str) -> bool {
+    if email.len() > 255 || email.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+
+    match email.rsplit_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !local.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains("..")
+        }
+        None => false,
+    }
+}
+
+// Issue a verification token for the user's current email, invalidating any
+// still outstanding. Only the hash is stored.
+pub async fn issue_verification_token<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    email: &str,
+    config: &AppConfig,
+) -> Result<String, tokio_postgres::Error> {
+    let token = generate_opaque_token();
+    let expires_at = Utc::now() + Duration::seconds(config.email_verification_ttl_secs);
+
+    client
+        .execute(
+            "UPDATE email_verification_tokens SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO email_verification_tokens (id, user_id, email, token_hash, expires_at) VALUES ($1, $2, $3, $4, $5)",
+            &[&Uuid::new_v4(), &user_id, &email, &hash_token(&token), &expires_at],
+        )
+        .await?;
+
+    Ok(token)
+}
+
+// Consume a verification token and mark the address it was sent to as verified.
+// Returns false when the token is unknown, used, expired, or the user has since
+// changed their email.
+pub async fn verify_email_with_token(
+    client: &mut Client,
+    presented: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "UPDATE email_verification_tokens SET used_at = NOW()
+             WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+             RETURNING user_id, email",
+            &[&hash_token(presented)],
+        )
+        .await?;
+
+    let (user_id, email): (Uuid, String) = match row {
+        Some(row) => (row.get("user_id"), row.get("email")),
+        None => return Ok(false),
+    };
+
+    let updated = transaction
+        .execute(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()), updated_at = NOW()
+             WHERE id = $1 AND email = $2",
+            &[&user_id, &email],
+        )
+        .await?;
+
+    transaction.commit().await?;
+    Ok(updated == 1)
+}
+
+// Email carrying the verification link
+pub fn verification_email(to: &str, token: &str, config: &AppConfig) -> MailMessage {
+    let link = format!(
+        "{}/verify-email?token={}",
+        config.app_base_url.trim_end_matches('/'),
+        token
+    );
+
+    MailMessage {
+        to: to.to_string(),
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Welcome to Hex The Add Hub!\n\n\
+             Please confirm your email address by opening this link:\n{}\n\n\
+             The link expires in {} hours.",
+            link,
+            config.email_verification_ttl_secs / 3600
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_email_syntax() {
+        assert!(is_valid_email("learner@example.com"));
+        assert!(is_valid_email("first.last+tag@sub.example.co.uk"));
+
+        assert!(!is_valid_email("learner"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("learner@localhost"));
+        assert!(!is_valid_email("learner@example..com"));
+        assert!(!is_valid_email("learner@@example.com"));
+        assert!(!is_valid_email("learner @example.com"));
+    }
+
+    #[test]
+    fn test_verification_email_contains_link() {
+        let config = AppConfig::for_tests();
+        let message = verification_email("learner@example.com", "abc123", &config);
+
+        assert!(message
+            .body
+            .contains("http://localhost:5000/verify-email?token=abc123"));
+        assert!(message.body.contains("48 hours"));
+    }
+}
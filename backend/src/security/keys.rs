@@ -0,0 +1,488 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+    OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::{env, fmt, fs};
+
+// DER prefix of a SubjectPublicKeyInfo holding a raw 32-byte Ed25519 key
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+// Key id used for the shared-secret fallback
+const SECRET_KEY_ID: &str = "hs256";
+
+// Claims of the token signed and verified when a key ring is loaded
+#[derive(Serialize, Deserialize)]
+struct KeyProbe {
+    sub: String,
+    exp: usize,
+}
+
+#[derive(Debug)]
+pub enum KeyRingError {
+    Config(String),
+    Io(String, std::io::Error),
+    InvalidKey(String, String),
+}
+
+impl fmt::Display for KeyRingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyRingError::Config(message) => write!(f, "Invalid JWT key configuration: {}", message),
+            KeyRingError::Io(path, e) => write!(f, "Failed to read JWT key '{}': {}", path, e),
+            KeyRingError::InvalidKey(kid, message) => {
+                write!(f, "Invalid JWT key '{}': {}", kid, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyRingError {}
+
+#[derive(Clone)]
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: EncodingKey,
+}
+
+#[derive(Clone)]
+struct VerifyingKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+    // Public keys are published through the JWKS endpoint; shared secrets are not
+    jwk: Option<Jwk>,
+}
+
+// Set of keys used to sign and verify JWTs. Tokens are signed with a single
+// active key and carry its `kid`; any configured verification key is accepted
+// so keys can be rotated without invalidating tokens that are still live.
+#[derive(Clone)]
+pub struct KeyRing {
+    signing: SigningKey,
+    verifying: HashMap<String, VerifyingKey>,
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyRing")
+            .field("signing_kid", &self.signing.kid)
+            .field("verification_kids", &self.verifying.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl KeyRing {
+    // Load keys from the environment:
+    //
+    // JWT_SIGNING_KEY_ID / JWT_SIGNING_ALG / JWT_SIGNING_KEY_PATH
+    //     active private key (PKCS#8 PEM), algorithm RS256 or EdDSA
+    // JWT_VERIFICATION_KEYS
+    //     comma-separated `kid:alg:path` entries for public keys (SPKI PEM);
+    //     must include the public half of the active signing key
+    //
+    // Without JWT_SIGNING_KEY_PATH, falls back to HS256 with JWT_SECRET,
+    // which is only suitable for local development.
+    pub fn from_env() -> Result<Self, KeyRingError> {
+        let signing_path = match env::var("JWT_SIGNING_KEY_PATH") {
+            Ok(path) => path,
+            Err(_) => {
+                let secret = env::var("JWT_SECRET").map_err(|_| {
+                    KeyRingError::Config(
+                        "set JWT_SIGNING_KEY_PATH, or JWT_SECRET for HS256".to_string(),
+                    )
+                })?;
+                return Ok(KeyRing::from_secret(&secret));
+            }
+        };
+
+        let signing_kid = env::var("JWT_SIGNING_KEY_ID")
+            .map_err(|_| KeyRingError::Config("JWT_SIGNING_KEY_ID must be set".to_string()))?;
+        let signing_alg = parse_algorithm(&env::var("JWT_SIGNING_ALG").unwrap_or_else(|_| "EdDSA".to_string()))?;
+        let signing_pem = read_key_file(&signing_path)?;
+
+        let mut verification_keys = Vec::new();
+        for entry in env::var("JWT_VERIFICATION_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let mut parts = entry.splitn(3, ':');
+            let (kid, alg, path) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(kid), Some(alg), Some(path)) => (kid, alg, path),
+                _ => {
+                    return Err(KeyRingError::Config(format!(
+                        "expected kid:alg:path in JWT_VERIFICATION_KEYS, got '{}'",
+                        entry
+                    )))
+                }
+            };
+            verification_keys.push((kid.to_string(), parse_algorithm(alg)?, read_key_file(path)?));
+        }
+
+        KeyRing::from_pems(&signing_kid, signing_alg, &signing_pem, &verification_keys)
+    }
+
+    // Symmetric HS256 key ring; nothing is published in the JWKS
+    pub fn from_secret(secret: &str) -> Self {
+        let mut verifying = HashMap::new();
+        verifying.insert(
+            SECRET_KEY_ID.to_string(),
+            VerifyingKey {
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+                jwk: None,
+            },
+        );
+
+        KeyRing {
+            signing: SigningKey {
+                kid: SECRET_KEY_ID.to_string(),
+                algorithm: Algorithm::HS256,
+                key: EncodingKey::from_secret(secret.as_bytes()),
+            },
+            verifying,
+        }
+    }
+
+    // Build a key ring from PEM-encoded keys
+    pub fn from_pems(
+        signing_kid: &str,
+        signing_alg: Algorithm,
+        signing_pem: &[u8],
+        verification_keys: &[(String, Algorithm, Vec<u8>)],
+    ) -> Result<Self, KeyRingError> {
+        let invalid = |kid: &str, e: &dyn fmt::Display| KeyRingError::InvalidKey(kid.to_string(), e.to_string());
+
+        let signing_key = match signing_alg {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(signing_pem),
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(signing_pem),
+            _ => return Err(unsupported(signing_alg)),
+        }
+        .map_err(|e| invalid(signing_kid, &e))?;
+
+        let mut verifying = HashMap::new();
+        for (kid, algorithm, pem) in verification_keys {
+            let jwk = public_pem_to_jwk(kid, *algorithm, pem)?;
+            let key = DecodingKey::from_jwk(&jwk).map_err(|e| invalid(kid, &e))?;
+
+            if verifying
+                .insert(
+                    kid.clone(),
+                    VerifyingKey {
+                        algorithm: *algorithm,
+                        key,
+                        jwk: Some(jwk),
+                    },
+                )
+                .is_some()
+            {
+                return Err(KeyRingError::Config(format!("duplicate key id '{}'", kid)));
+            }
+        }
+
+        match verifying.get(signing_kid) {
+            Some(key) if key.algorithm == signing_alg => (),
+            Some(_) => {
+                return Err(KeyRingError::Config(format!(
+                    "verification key '{}' does not match the signing algorithm",
+                    signing_kid
+                )))
+            }
+            None => {
+                return Err(KeyRingError::Config(format!(
+                    "no verification key configured for signing key '{}'",
+                    signing_kid
+                )))
+            }
+        }
+
+        let ring = KeyRing {
+            signing: SigningKey {
+                kid: signing_kid.to_string(),
+                algorithm: signing_alg,
+                key: signing_key,
+            },
+            verifying,
+        };
+
+        // A private key paired with the wrong public key would issue tokens
+        // nothing accepts; find out now rather than at the first login
+        let probe = KeyProbe {
+            sub: "key-check".to_string(),
+            exp: (chrono::Utc::now().timestamp() + 60) as usize,
+        };
+        ring.encode(&probe)
+            .and_then(|token| ring.decode::<KeyProbe>(&token))
+            .map_err(|_| {
+                KeyRingError::InvalidKey(
+                    signing_kid.to_string(),
+                    "private key does not match the public key configured under this id".to_string(),
+                )
+            })?;
+
+        Ok(ring)
+    }
+
+    // Sign claims with the active key, tagging the token with its `kid`
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        let mut header = Header::new(self.signing.algorithm);
+        header.kid = Some(self.signing.kid.clone());
+        encode(&header, claims, &self.signing.key)
+    }
+
+    // Verify a token against the key named by its `kid` header
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+        let key = header
+            .kid
+            .as_deref()
+            .and_then(|kid| self.verifying.get(kid))
+            .ok_or(ErrorKind::InvalidSignature)?;
+
+        // Never let the token pick a different algorithm than the key was configured for
+        if header.alg != key.algorithm {
+            return Err(ErrorKind::InvalidAlgorithm.into());
+        }
+
+        let validation = Validation::new(key.algorithm);
+        Ok(decode::<T>(token, &key.key, &validation)?.claims)
+    }
+
+    // Public verification keys in JWKS form
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys: Vec<Jwk> = self
+            .verifying
+            .values()
+            .filter_map(|key| key.jwk.clone())
+            .collect();
+        keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+
+        JwkSet { keys }
+    }
+}
+
+fn parse_algorithm(name: &str) -> Result<Algorithm, KeyRingError> {
+    match name {
+        "RS256" => Ok(Algorithm::RS256),
+        "EdDSA" => Ok(Algorithm::EdDSA),
+        other => Err(KeyRingError::Config(format!(
+            "unsupported algorithm '{}', expected RS256 or EdDSA",
+            other
+        ))),
+    }
+}
+
+fn unsupported(algorithm: Algorithm) -> KeyRingError {
+    KeyRingError::Config(format!("unsupported algorithm {:?}", algorithm))
+}
+
+fn read_key_file(path: &str) -> Result<Vec<u8>, KeyRingError> {
+    fs::read(path).map_err(|e| KeyRingError::Io(path.to_string(), e))
+}
+
+// Convert a PEM public key into its JWK representation
+fn public_pem_to_jwk(kid: &str, algorithm: Algorithm, pem: &[u8]) -> Result<Jwk, KeyRingError> {
+    let invalid = |message: String| KeyRingError::InvalidKey(kid.to_string(), message);
+    let pem = pem::parse(pem).map_err(|e| invalid(e.to_string()))?;
+
+    let parameters = match (algorithm, pem.tag.as_str()) {
+        (Algorithm::RS256, "PUBLIC KEY" | "RSA PUBLIC KEY") => {
+            let key = if pem.tag == "PUBLIC KEY" {
+                RsaPublicKey::from_public_key_der(&pem.contents).map_err(|e| invalid(e.to_string()))?
+            } else {
+                RsaPublicKey::from_pkcs1_der(&pem.contents).map_err(|e| invalid(e.to_string()))?
+            };
+
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+            })
+        }
+        (Algorithm::EdDSA, "PUBLIC KEY") => {
+            let raw = pem
+                .contents
+                .strip_prefix(&ED25519_SPKI_PREFIX[..])
+                .filter(|raw| raw.len() == 32)
+                .ok_or_else(|| invalid("not an Ed25519 public key".to_string()))?;
+
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(raw),
+            })
+        }
+        (Algorithm::RS256 | Algorithm::EdDSA, tag) => {
+            return Err(invalid(format!("expected a public key PEM, got '{}'", tag)))
+        }
+        (other, _) => return Err(unsupported(other)),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+    use rsa::RsaPrivateKey;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct TestClaims {
+        sub: String,
+        exp: usize,
+    }
+
+    fn claims() -> TestClaims {
+        TestClaims {
+            sub: "user-1".to_string(),
+            exp: (chrono::Utc::now().timestamp() + 600) as usize,
+        }
+    }
+
+    fn to_pem(tag: &str, der: &[u8]) -> Vec<u8> {
+        format!(
+            "-----BEGIN {tag}-----\n{}\n-----END {tag}-----\n",
+            STANDARD.encode(der)
+        )
+        .into_bytes()
+    }
+
+    // RFC 8032 test vector 1, wrapped as PKCS#8 / SPKI PEM
+    fn ed25519_pems() -> (Vec<u8>, Vec<u8>) {
+        let seed = hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+        let public = hex::decode("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a").unwrap();
+
+        let mut private_der = hex::decode("302e020100300506032b657004220420").unwrap();
+        private_der.extend_from_slice(&seed);
+        let mut public_der = ED25519_SPKI_PREFIX.to_vec();
+        public_der.extend_from_slice(&public);
+
+        (to_pem("PRIVATE KEY", &private_der), to_pem("PUBLIC KEY", &public_der))
+    }
+
+    fn rsa_pems() -> (Vec<u8>, Vec<u8>) {
+        let private = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let public = private.to_public_key();
+
+        (
+            private.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes().to_vec(),
+            public.to_public_key_pem(LineEnding::LF).unwrap().into_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_eddsa_round_trip_and_jwks() {
+        let (private, public) = ed25519_pems();
+        let ring = KeyRing::from_pems(
+            "ed-1",
+            Algorithm::EdDSA,
+            &private,
+            &[("ed-1".to_string(), Algorithm::EdDSA, public)],
+        )
+        .unwrap();
+
+        let token = ring.encode(&claims()).unwrap();
+        assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("ed-1"));
+        assert_eq!(ring.decode::<TestClaims>(&token).unwrap(), claims());
+
+        let jwks = serde_json::to_value(ring.jwks()).unwrap();
+        assert_eq!(jwks["keys"][0]["kty"], "OKP");
+        assert_eq!(jwks["keys"][0]["crv"], "Ed25519");
+        assert_eq!(jwks["keys"][0]["kid"], "ed-1");
+        assert_eq!(jwks["keys"][0]["x"], "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo");
+    }
+
+    #[test]
+    fn test_rotation_accepts_old_and_new_keys() {
+        let (ed_private, ed_public) = ed25519_pems();
+        let (rsa_private, rsa_public) = rsa_pems();
+        let verification_keys = vec![
+            ("old-rsa".to_string(), Algorithm::RS256, rsa_public),
+            ("new-ed".to_string(), Algorithm::EdDSA, ed_public),
+        ];
+
+        let old_ring =
+            KeyRing::from_pems("old-rsa", Algorithm::RS256, &rsa_private, &verification_keys).unwrap();
+        let new_ring =
+            KeyRing::from_pems("new-ed", Algorithm::EdDSA, &ed_private, &verification_keys).unwrap();
+
+        // A token signed before the rotation still verifies afterwards
+        let old_token = old_ring.encode(&claims()).unwrap();
+        assert_eq!(new_ring.decode::<TestClaims>(&old_token).unwrap(), claims());
+
+        let jwks = serde_json::to_value(new_ring.jwks()).unwrap();
+        assert_eq!(jwks["keys"].as_array().unwrap().len(), 2);
+        assert_eq!(jwks["keys"][1]["kid"], "old-rsa");
+        assert_eq!(jwks["keys"][1]["kty"], "RSA");
+        assert_eq!(jwks["keys"][1]["e"], "AQAB");
+    }
+
+    #[test]
+    fn test_rejects_unknown_kid_and_secret_keys() {
+        let (private, public) = ed25519_pems();
+        let ring = KeyRing::from_pems(
+            "ed-1",
+            Algorithm::EdDSA,
+            &private,
+            &[("ed-1".to_string(), Algorithm::EdDSA, public)],
+        )
+        .unwrap();
+
+        // Signed by a key the ring has never heard of
+        let other = KeyRing::from_secret("dev-secret");
+        let token = other.encode(&claims()).unwrap();
+        assert!(ring.decode::<TestClaims>(&token).is_err());
+
+        // Shared secrets are never published
+        assert!(other.jwks().keys.is_empty());
+        assert_eq!(other.decode::<TestClaims>(&token).unwrap(), claims());
+    }
+
+    #[test]
+    fn test_rejects_mismatched_key_pair() {
+        let (private, _) = ed25519_pems();
+        // RFC 8032 test vector 2's public key, which belongs to a different seed
+        let mut other_public = ED25519_SPKI_PREFIX.to_vec();
+        other_public.extend(
+            hex::decode("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c").unwrap(),
+        );
+
+        let result = KeyRing::from_pems(
+            "ed-1",
+            Algorithm::EdDSA,
+            &private,
+            &[("ed-1".to_string(), Algorithm::EdDSA, to_pem("PUBLIC KEY", &other_public))],
+        );
+        assert!(matches!(result, Err(KeyRingError::InvalidKey(kid, _)) if kid == "ed-1"));
+    }
+
+    #[test]
+    fn test_signing_key_must_be_verifiable() {
+        let (private, _) = ed25519_pems();
+        let result = KeyRing::from_pems("ed-1", Algorithm::EdDSA, &private, &[]);
+        assert!(matches!(result, Err(KeyRingError::Config(_))));
+    }
+}
@@ -0,0 +1,430 @@
+use chrono::{Duration, Utc};
+use deadpool_postgres::{Client, GenericClient};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::security::throttle::{
+    check_login_allowed, record_login_failure, record_login_success, Throttled,
+};
+use crate::security::tokens::hash_token;
+use crate::security::totp::{generate_secret, verify_code};
+
+// `purpose` claim of tokens that only prove the first login factor
+const MFA_PENDING_PURPOSE: &str = "mfa_pending";
+
+// Codes that may be tried with one pending token before the user has to sign in again
+const MFA_MAX_ATTEMPTS: i32 = 5;
+
+// Throttle key for second-factor failures. They are counted per account from
+// any address, since whoever gets this far already has the password.
+pub const MFA_THROTTLE_KEY: &str = "mfa";
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+// Claims of the short-lived token handed out after the password check when the
+// user still has to present a second factor. It lacks the access token claims,
+// so it can never be used as one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaClaims {
+    pub sub: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub purpose: String,
+    // Id of the mfa_challenges row that counts attempts made with the token
+    pub jti: String,
+}
+
+// A validated mfa pending token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MfaPending {
+    pub user_id: Uuid,
+    pub challenge_id: Uuid,
+}
+
+#[derive(Debug)]
+pub enum MfaTokenError {
+    Database(tokio_postgres::Error),
+    Token(jsonwebtoken::errors::Error),
+}
+
+impl fmt::Display for MfaTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MfaTokenError::Database(e) => write!(f, "Database error: {}", e),
+            MfaTokenError::Token(e) => write!(f, "Failed to generate token: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MfaTokenError {}
+
+impl From<tokio_postgres::Error> for MfaTokenError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        MfaTokenError::Database(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for MfaTokenError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        MfaTokenError::Token(e)
+    }
+}
+
+// Result of confirming a TOTP enrollment
+pub enum TotpConfirmation {
+    // Enrollment is active; recovery codes are shown to the user once
+    Confirmed(Vec<String>),
+    InvalidCode,
+    NotPending,
+}
+
+// Record a second-factor challenge for the user and hand out its pending token
+pub async fn start_mfa_challenge<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    config: &AppConfig,
+) -> Result<String, MfaTokenError> {
+    let challenge_id = Uuid::new_v4();
+    let expires_at = Utc::now() + Duration::seconds(config.mfa_token_ttl_secs);
+
+    client
+        .execute(
+            "DELETE FROM mfa_challenges WHERE user_id = $1 AND expires_at < NOW()",
+            &[&user_id],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO mfa_challenges (id, user_id, expires_at) VALUES ($1, $2, $3)",
+            &[&challenge_id, &user_id, &expires_at],
+        )
+        .await?;
+
+    Ok(issue_mfa_token(user_id, challenge_id, config)?)
+}
+
+pub fn issue_mfa_token(
+    user_id: Uuid,
+    challenge_id: Uuid,
+    config: &AppConfig,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now().timestamp();
+    let claims = MfaClaims {
+        sub: user_id.to_string(),
+        exp: (now + config.mfa_token_ttl_secs) as usize,
+        iat: now as usize,
+        purpose: MFA_PENDING_PURPOSE.to_string(),
+        jti: challenge_id.to_string(),
+    };
+
+    config.jwt_keys.encode(&claims)
+}
+
+// Validate an mfa pending token's signature and purpose. Whether its challenge
+// is still open is checked by `claim_mfa_attempt`.
+pub fn validate_mfa_token(token: &str, config: &AppConfig) -> Option<MfaPending> {
+    let claims = config.jwt_keys.decode::<MfaClaims>(token).ok()?;
+    if claims.purpose != MFA_PENDING_PURPOSE {
+        return None;
+    }
+
+    Some(MfaPending {
+        user_id: Uuid::parse_str(&claims.sub).ok()?,
+        challenge_id: Uuid::parse_str(&claims.jti).ok()?,
+    })
+}
+
+// Use up one attempt of a pending challenge before a code is checked. Returns
+// false once the challenge is used, expired or out of attempts, so the token
+// can't be replayed to guess codes.
+pub async fn claim_mfa_attempt<C: GenericClient>(
+    client: &C,
+    pending: MfaPending,
+) -> Result<bool, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "UPDATE mfa_challenges SET attempts = attempts + 1
+             WHERE id = $1 AND user_id = $2 AND expires_at > NOW() AND attempts < $3
+             RETURNING attempts",
+            &[&pending.challenge_id, &pending.user_id, &MFA_MAX_ATTEMPTS],
+        )
+        .await?;
+
+    Ok(row.is_some())
+}
+
+// Close a challenge once its code was accepted, making the token single use
+pub async fn finish_mfa_challenge<C: GenericClient>(
+    client: &C,
+    pending: MfaPending,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute("DELETE FROM mfa_challenges WHERE id = $1", &[&pending.challenge_id])
+        .await?;
+
+    Ok(())
+}
+
+// Whether the user has a confirmed TOTP enrollment
+pub async fn is_mfa_enabled<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<bool, tokio_postgres::Error> {
+    let row = client
+        .query_one(
+            "SELECT EXISTS(SELECT 1 FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL)",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(row.get(0))
+}
+
+// Start (or restart) TOTP enrollment with a fresh secret. Returns None when
+// the user already has a confirmed enrollment.
+pub async fn begin_totp_enrollment<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Option<String>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, created_at = NOW()
+             WHERE user_totp.confirmed_at IS NULL
+             RETURNING secret",
+            &[&user_id, &generate_secret()],
+        )
+        .await?;
+
+    Ok(row.map(|row| row.get("secret")))
+}
+
+// Activate a pending enrollment once the user proves their app produces valid codes
+pub async fn confirm_totp_enrollment(
+    client: &mut Client,
+    user_id: Uuid,
+    code: &str,
+) -> Result<TotpConfirmation, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    let secret: String = match transaction
+        .query_opt(
+            "SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NULL FOR UPDATE",
+            &[&user_id],
+        )
+        .await?
+    {
+        Some(row) => row.get("secret"),
+        None => return Ok(TotpConfirmation::NotPending),
+    };
+
+    let step = match verify_code(&secret, code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return Ok(TotpConfirmation::InvalidCode),
+    };
+
+    transaction
+        .execute(
+            "UPDATE user_totp SET confirmed_at = NOW(), last_used_step = $2 WHERE user_id = $1",
+            &[&user_id, &step],
+        )
+        .await?;
+    let recovery_codes = replace_recovery_codes(&transaction, user_id).await?;
+
+    transaction.commit().await?;
+    Ok(TotpConfirmation::Confirmed(recovery_codes))
+}
+
+// Check a second factor: a current TOTP code (each accepted at most once) or
+// an unused recovery code, which is then spent
+pub async fn verify_second_factor<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let secret: Option<String> = client
+        .query_opt(
+            "SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL",
+            &[&user_id],
+        )
+        .await?
+        .map(|row| row.get("secret"));
+
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return Ok(false),
+    };
+
+    if let Some(step) = verify_code(&secret, code, Utc::now().timestamp()) {
+        let accepted = client
+            .execute(
+                "UPDATE user_totp SET last_used_step = $2
+                 WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+                &[&user_id, &step],
+            )
+            .await?;
+        return Ok(accepted == 1);
+    }
+
+    let spent = client
+        .execute(
+            "UPDATE user_recovery_codes SET used_at = NOW()
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+            &[&user_id, &hash_token(&normalize_recovery_code(code))],
+        )
+        .await?;
+
+    Ok(spent == 1)
+}
+
+// Why a second-factor code was not accepted
+#[derive(Debug)]
+pub enum SecondFactorRejected {
+    Invalid,
+    Throttled(Throttled),
+}
+
+// `verify_second_factor` behind the per-account throttle, so codes can't be
+// guessed by retrying, whether signing in or from an existing session
+pub async fn check_second_factor<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    code: &str,
+    config: &AppConfig,
+) -> Result<Result<(), SecondFactorRejected>, tokio_postgres::Error> {
+    let email: String = client
+        .query_one("SELECT email FROM users WHERE id = $1", &[&user_id])
+        .await?
+        .get("email");
+
+    if let Err(throttled) = check_login_allowed(client, &email, MFA_THROTTLE_KEY).await? {
+        return Ok(Err(SecondFactorRejected::Throttled(throttled)));
+    }
+
+    if !verify_second_factor(client, user_id, code).await? {
+        record_login_failure(client, &email, MFA_THROTTLE_KEY, config).await?;
+        return Ok(Err(SecondFactorRejected::Invalid));
+    }
+
+    record_login_success(client, &email, MFA_THROTTLE_KEY).await?;
+    Ok(Ok(()))
+}
+
+// Replace all of a user's recovery codes with a new set
+pub async fn replace_recovery_codes<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Vec<String>, tokio_postgres::Error> {
+    let codes = generate_recovery_codes();
+
+    client
+        .execute("DELETE FROM user_recovery_codes WHERE user_id = $1", &[&user_id])
+        .await?;
+    for code in &codes {
+        client
+            .execute(
+                "INSERT INTO user_recovery_codes (id, user_id, code_hash) VALUES ($1, $2, $3)",
+                &[&Uuid::new_v4(), &user_id, &hash_token(&normalize_recovery_code(code))],
+            )
+            .await?;
+    }
+
+    Ok(codes)
+}
+
+// Remove the user's TOTP enrollment and recovery codes
+pub async fn disable_totp(client: &mut Client, user_id: Uuid) -> Result<(), tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    transaction
+        .execute("DELETE FROM user_recovery_codes WHERE user_id = $1", &[&user_id])
+        .await?;
+    transaction
+        .execute("DELETE FROM user_totp WHERE user_id = $1", &[&user_id])
+        .await?;
+
+    transaction.commit().await
+}
+
+// Recovery codes look like `k7m2p-x9qrt`; ambiguous characters are left out
+fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+// Users may type recovery codes with different case or without the dash
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::auth::validate_token;
+
+    #[test]
+    fn test_mfa_token_round_trip() {
+        let config = AppConfig::for_tests();
+        let pending = MfaPending {
+            user_id: Uuid::new_v4(),
+            challenge_id: Uuid::new_v4(),
+        };
+
+        let token = issue_mfa_token(pending.user_id, pending.challenge_id, &config).unwrap();
+        assert_eq!(validate_mfa_token(&token, &config), Some(pending));
+
+        // A pending token must not pass as an access token
+        assert!(validate_token(&token, &config.jwt_keys).is_err());
+        assert_eq!(validate_mfa_token("garbage", &config), None);
+    }
+
+    #[test]
+    fn test_mfa_token_needs_a_challenge() {
+        let config = AppConfig::for_tests();
+        let now = Utc::now().timestamp();
+
+        // Without a challenge there is nothing to count attempts against
+        let token = config
+            .jwt_keys
+            .encode(&serde_json::json!({
+                "sub": Uuid::new_v4().to_string(),
+                "exp": now + 300,
+                "iat": now,
+                "purpose": MFA_PENDING_PURPOSE,
+            }))
+            .unwrap();
+        assert_eq!(validate_mfa_token(&token, &config), None);
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        for code in &codes {
+            assert_eq!(code.len(), 11);
+            assert_eq!(&code[5..6], "-");
+        }
+
+        assert_eq!(normalize_recovery_code(" K7M2P-x9qrt "), "k7m2px9qrt");
+        assert_eq!(
+            hash_token(&normalize_recovery_code(&codes[0].to_uppercase())),
+            hash_token(&normalize_recovery_code(&codes[0]))
+        );
+    }
+}
@@ -0,0 +1,12 @@
+pub mod api_keys;
+pub mod audit;
+pub mod email_verification;
+pub mod keys;
+pub mod mfa;
+pub mod oauth;
+pub mod password_reset;
+pub mod refresh;
+pub mod roles;
+pub mod throttle;
+pub mod tokens;
+pub mod totp;
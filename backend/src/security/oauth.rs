@@ -0,0 +1,516 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Client;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::security::throttle::normalize_email;
+
+// Identity providers we support for social login
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OAuthProviderKind {
+    // OpenID Connect; identity comes from the standard userinfo endpoint
+    Google,
+    // Plain OAuth2; identity comes from the REST API
+    GitHub,
+}
+
+impl OAuthProviderKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "google" => Some(OAuthProviderKind::Google),
+            "github" => Some(OAuthProviderKind::GitHub),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OAuthProviderKind::Google => "google",
+            OAuthProviderKind::GitHub => "github",
+        }
+    }
+}
+
+// Client registration and endpoints for one provider. Endpoints are
+// configurable so tests and local development can point at a mock IdP.
+#[derive(Debug, Clone)]
+pub struct OAuthProvider {
+    pub kind: OAuthProviderKind,
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    // Google: the OIDC userinfo endpoint; GitHub: the API base URL
+    pub userinfo_url: String,
+    pub scopes: String,
+}
+
+// Who the provider says the user is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuthIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum OAuthError {
+    Http(reqwest::Error),
+    // The provider answered, but not with what we asked for
+    Provider(String),
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OAuthError::Http(e) => write!(f, "Identity provider request failed: {}", e),
+            OAuthError::Provider(message) => write!(f, "Identity provider error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for OAuthError {}
+
+impl From<reqwest::Error> for OAuthError {
+    fn from(e: reqwest::Error) -> Self {
+        OAuthError::Http(e)
+    }
+}
+
+// Why a provider identity can't be signed in with
+#[derive(Debug, PartialEq, Eq)]
+pub enum UnresolvedIdentity {
+    // The provider hasn't verified the identity's email
+    UnverifiedEmail,
+    // A local account has the email but never proved it owns it. Linking
+    // would let whoever registered it first keep a way in (e.g. its password).
+    UnverifiedAccount,
+}
+
+impl fmt::Display for UnresolvedIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnresolvedIdentity::UnverifiedEmail => {
+                write!(f, "Your account at the provider has no verified email address")
+            }
+            UnresolvedIdentity::UnverifiedAccount => write!(
+                f,
+                "An account with this email already exists; verify its email address, then sign in with this provider again"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnresolvedIdentity {}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OidcUserInfo {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubUser {
+    id: i64,
+    login: String,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+// PKCE S256 code challenge for a verifier (RFC 7636)
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+impl OAuthProvider {
+    // URL to send the browser to for consent
+    pub fn authorization_url(
+        &self,
+        redirect_uri: &str,
+        state: &str,
+        code_verifier: &str,
+    ) -> Result<String, OAuthError> {
+        let challenge = pkce_challenge(code_verifier);
+        let params = [
+            ("response_type", "code"),
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", redirect_uri),
+            ("scope", self.scopes.as_str()),
+            ("state", state),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ];
+
+        reqwest::Url::parse_with_params(&self.authorize_url, &params)
+            .map(String::from)
+            .map_err(|e| OAuthError::Provider(format!("invalid authorize URL: {}", e)))
+    }
+
+    // Exchange an authorization code for an access token
+    pub async fn exchange_code(
+        &self,
+        http: &reqwest::Client,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+    ) -> Result<String, OAuthError> {
+        let response: TokenResponse = http
+            .post(&self.token_url)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        match (response.access_token, response.error) {
+            (Some(token), None) => Ok(token),
+            (_, error) => Err(OAuthError::Provider(
+                response
+                    .error_description
+                    .or(error)
+                    .unwrap_or_else(|| "no access token in response".to_string()),
+            )),
+        }
+    }
+
+    // Look up the signed-in user with the access token
+    pub async fn fetch_identity(
+        &self,
+        http: &reqwest::Client,
+        access_token: &str,
+    ) -> Result<OAuthIdentity, OAuthError> {
+        match self.kind {
+            OAuthProviderKind::Google => {
+                let info: OidcUserInfo = http
+                    .get(&self.userinfo_url)
+                    .bearer_auth(access_token)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+
+                Ok(OAuthIdentity {
+                    subject: info.sub,
+                    email: info.email,
+                    email_verified: info.email_verified,
+                    name: info.name,
+                })
+            }
+            OAuthProviderKind::GitHub => {
+                let base = self.userinfo_url.trim_end_matches('/');
+                let user: GitHubUser = github_get(http, &format!("{}/user", base), access_token).await?;
+                let emails: Vec<GitHubEmail> =
+                    github_get(http, &format!("{}/user/emails", base), access_token).await?;
+
+                // Only the primary address counts, and only once GitHub has verified it
+                let primary = emails.into_iter().find(|email| email.primary);
+
+                Ok(OAuthIdentity {
+                    subject: user.id.to_string(),
+                    email_verified: primary.as_ref().is_some_and(|email| email.verified),
+                    email: primary.map(|email| email.email),
+                    name: user.name.or(Some(user.login)),
+                })
+            }
+        }
+    }
+}
+
+// The email an unknown identity is linked or registered under, once the
+// provider has verified it
+fn verified_email(identity: &OAuthIdentity) -> Result<&str, UnresolvedIdentity> {
+    match (&identity.email, identity.email_verified) {
+        (Some(email), true) => Ok(email),
+        _ => Err(UnresolvedIdentity::UnverifiedEmail),
+    }
+}
+
+// An existing account only takes the identity if it has verified the email too
+fn check_account_verified(email_verified_at: Option<DateTime<Utc>>) -> Result<(), UnresolvedIdentity> {
+    match email_verified_at {
+        Some(_) => Ok(()),
+        None => Err(UnresolvedIdentity::UnverifiedAccount),
+    }
+}
+
+// Find the user behind a provider identity. Unknown identities are linked to
+// the account with the same email, or get a new account, but only when the
+// provider has verified that email and an existing account has verified it too.
+pub async fn resolve_user(
+    client: &mut Client,
+    provider: OAuthProviderKind,
+    identity: &OAuthIdentity,
+) -> Result<Result<Uuid, UnresolvedIdentity>, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    let linked = transaction
+        .query_opt(
+            "SELECT user_id FROM user_identities WHERE provider = $1 AND subject = $2",
+            &[&provider.as_str(), &identity.subject],
+        )
+        .await?;
+    if let Some(row) = linked {
+        return Ok(Ok(row.get("user_id")));
+    }
+
+    let email = &match verified_email(identity) {
+        Ok(email) => normalize_email(email),
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let existing = transaction
+        .query_opt(
+            "SELECT id, email_verified_at FROM users WHERE LOWER(email) = $1 FOR UPDATE",
+            &[email],
+        )
+        .await?;
+    if let Some(row) = &existing {
+        if let Err(e) = check_account_verified(row.get("email_verified_at")) {
+            return Ok(Err(e));
+        }
+    }
+
+    let user_id: Uuid = match existing {
+        Some(row) => row.get("id"),
+        None => {
+            let user_id = Uuid::new_v4();
+            let now = Utc::now();
+            let full_name = identity
+                .name
+                .clone()
+                .filter(|name| !name.trim().is_empty())
+                .unwrap_or_else(|| email.split('@').next().unwrap_or(email).to_string());
+
+            transaction
+                .execute(
+                    "INSERT INTO users (id, email, password_hash, full_name, is_admin, created_at, updated_at, email_verified_at)
+                     VALUES ($1, $2, NULL, $3, false, $4, $4, $4)",
+                    &[&user_id, email, &full_name, &now],
+                )
+                .await?;
+            user_id
+        }
+    };
+
+    transaction
+        .execute(
+            "INSERT INTO user_identities (provider, subject, user_id, email) VALUES ($1, $2, $3, $4)",
+            &[&provider.as_str(), &identity.subject, &user_id, email],
+        )
+        .await?;
+
+    transaction.commit().await?;
+    Ok(Ok(user_id))
+}
+
+async fn github_get<T: serde::de::DeserializeOwned>(
+    http: &reqwest::Client,
+    url: &str,
+    access_token: &str,
+) -> Result<T, OAuthError> {
+    Ok(http
+        .get(url)
+        .bearer_auth(access_token)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .header(reqwest::header::USER_AGENT, "hex-the-add-hub")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::collections::HashMap;
+
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+    // Minimal IdP: accepts one code with the matching PKCE verifier
+    async fn mock_token(form: web::Form<HashMap<String, String>>) -> HttpResponse {
+        let valid = form.get("code").map(String::as_str) == Some("good-code")
+            && form.get("code_verifier").map(String::as_str) == Some(VERIFIER)
+            && form.get("client_secret").map(String::as_str) == Some("secret");
+
+        if valid {
+            HttpResponse::Ok().json(serde_json::json!({ "access_token": "mock-access", "token_type": "bearer" }))
+        } else {
+            HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid_grant" }))
+        }
+    }
+
+    fn authorized(req: &HttpRequest) -> bool {
+        req.headers()
+            .get("authorization")
+            .is_some_and(|value| value == "Bearer mock-access")
+    }
+
+    async fn mock_userinfo(req: HttpRequest) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        HttpResponse::Ok().json(serde_json::json!({
+            "sub": "google-123",
+            "email": "learner@example.com",
+            "email_verified": true,
+            "name": "Test Learner"
+        }))
+    }
+
+    async fn mock_github_user(req: HttpRequest) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        HttpResponse::Ok().json(serde_json::json!({ "id": 42, "login": "octo", "name": null }))
+    }
+
+    async fn mock_github_emails(req: HttpRequest) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        HttpResponse::Ok().json(serde_json::json!([
+            { "email": "old@example.com", "primary": false, "verified": true },
+            { "email": "octo@example.com", "primary": true, "verified": false }
+        ]))
+    }
+
+    async fn start_mock_idp() -> String {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route("/token", web::post().to(mock_token))
+                .route("/userinfo", web::get().to(mock_userinfo))
+                .route("/api/user", web::get().to(mock_github_user))
+                .route("/api/user/emails", web::get().to(mock_github_emails))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        format!("http://{}", addr)
+    }
+
+    fn provider(kind: OAuthProviderKind, base: &str) -> OAuthProvider {
+        OAuthProvider {
+            kind,
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            authorize_url: format!("{}/authorize", base),
+            token_url: format!("{}/token", base),
+            userinfo_url: match kind {
+                OAuthProviderKind::Google => format!("{}/userinfo", base),
+                OAuthProviderKind::GitHub => format!("{}/api", base),
+            },
+            scopes: "openid email profile".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_only_verified_emails_link_identities() {
+        let identity = |email: Option<&str>, email_verified| OAuthIdentity {
+            subject: "42".to_string(),
+            email: email.map(str::to_string),
+            email_verified,
+            name: None,
+        };
+
+        assert_eq!(verified_email(&identity(Some("octo@example.com"), true)), Ok("octo@example.com"));
+        assert_eq!(verified_email(&identity(Some("octo@example.com"), false)), Err(UnresolvedIdentity::UnverifiedEmail));
+        assert_eq!(verified_email(&identity(None, true)), Err(UnresolvedIdentity::UnverifiedEmail));
+
+        assert_eq!(check_account_verified(Some(Utc::now())), Ok(()));
+        assert_eq!(check_account_verified(None), Err(UnresolvedIdentity::UnverifiedAccount));
+    }
+
+    #[test]
+    fn test_pkce_and_authorization_url() {
+        // RFC 7636 appendix B
+        assert_eq!(pkce_challenge(VERIFIER), "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+
+        let url = provider(OAuthProviderKind::Google, "https://idp.example.com").authorization_url(
+            "http://localhost:5000/oauth/google/callback",
+            "state123",
+            VERIFIER,
+        ).unwrap();
+        assert!(url.starts_with("https://idp.example.com/authorize?response_type=code&client_id=client&"));
+        assert!(url.contains("redirect_uri=http%3A%2F%2Flocalhost%3A5000%2Foauth%2Fgoogle%2Fcallback"));
+        assert!(url.contains("scope=openid+email+profile"));
+        assert!(url.contains("state=state123"));
+        assert!(url.contains("code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM&code_challenge_method=S256"));
+    }
+
+    #[actix_web::test]
+    async fn test_oidc_flow_against_mock_idp() {
+        let base = start_mock_idp().await;
+        let google = provider(OAuthProviderKind::Google, &base);
+        let http = reqwest::Client::new();
+
+        let token = google
+            .exchange_code(&http, "good-code", "http://localhost/cb", VERIFIER)
+            .await
+            .unwrap();
+        let identity = google.fetch_identity(&http, &token).await.unwrap();
+        assert_eq!(
+            identity,
+            OAuthIdentity {
+                subject: "google-123".to_string(),
+                email: Some("learner@example.com".to_string()),
+                email_verified: true,
+                name: Some("Test Learner".to_string()),
+            }
+        );
+
+        // A wrong PKCE verifier is refused by the provider
+        let err = google
+            .exchange_code(&http, "good-code", "http://localhost/cb", "wrong-verifier")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, OAuthError::Provider(message) if message == "invalid_grant"));
+    }
+
+    #[actix_web::test]
+    async fn test_github_uses_primary_email() {
+        let base = start_mock_idp().await;
+        let github = provider(OAuthProviderKind::GitHub, &base);
+        let http = reqwest::Client::new();
+
+        let identity = github.fetch_identity(&http, "mock-access").await.unwrap();
+        assert_eq!(identity.subject, "42");
+        assert_eq!(identity.email.as_deref(), Some("octo@example.com"));
+        // Primary address is not verified on GitHub, so it must not be trusted
+        assert!(!identity.email_verified);
+        assert_eq!(identity.name.as_deref(), Some("octo"));
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::{Duration, Utc};
+use deadpool_postgres::{Client, GenericClient};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::mail::MailMessage;
+use crate::security::api_keys::revoke_user_api_keys;
+use crate::security::tokens::{generate_opaque_token, hash_token};
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+// Reject passwords that are too weak to accept
+pub fn validate_new_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "Password must be at least {} characters long",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+
+    if password.trim().is_empty() {
+        return Err("Password must not be blank".to_string());
+    }
+
+    Ok(())
+}
+
+// Issue a new reset token for a user, invalidating any still outstanding.
+// Only the hash is stored; the returned plaintext goes into the email.
+pub async fn issue_reset_token<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    config: &AppConfig,
+) -> Result<String, tokio_postgres::Error> {
+    let token = generate_opaque_token();
+    let expires_at = Utc::now() + Duration::seconds(config.password_reset_ttl_secs);
+
+    client
+        .execute(
+            "UPDATE password_reset_tokens SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    client
+        .execute(
+            "INSERT INTO password_reset_tokens (id, user_id, token_hash, expires_at) VALUES ($1, $2, $3, $4)",
+            &[&Uuid::new_v4(), &user_id, &hash_token(&token), &expires_at],
+        )
+        .await?;
+
+    Ok(token)
+}
+
+// Consume a reset token and set the user's new password. Every session and API
+// key the user had is revoked; the token_version trigger invalidates their
+// access tokens.
+// Returns false when the token is unknown, used or expired.
+pub async fn reset_password_with_token(
+    client: &mut Client,
+    presented: &str,
+    password_hash: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "UPDATE password_reset_tokens SET used_at = NOW()
+             WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+             RETURNING user_id",
+            &[&hash_token(presented)],
+        )
+        .await?;
+
+    let user_id: Uuid = match row {
+        Some(row) => row.get("user_id"),
+        None => return Ok(false),
+    };
+
+    transaction
+        .execute(
+            "UPDATE users SET password_hash = $1, updated_at = NOW() WHERE id = $2",
+            &[&password_hash, &user_id],
+        )
+        .await?;
+    transaction
+        .execute(
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+            &[&user_id],
+        )
+        .await?;
+    revoke_user_api_keys(&transaction, user_id).await?;
+
+    transaction.commit().await?;
+    Ok(true)
+}
+
+// Email carrying the reset link
+pub fn reset_email(to: &str, token: &str, config: &AppConfig) -> MailMessage {
+    let link = format!(
+        "{}/reset-password?token={}",
+        config.app_base_url.trim_end_matches('/'),
+        token
+    );
+
+    MailMessage {
+        to: to.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Someone asked to reset the password for your Hex The Add Hub account.\n\n\
+             Use this link to choose a new password:\n{}\n\n\
+             The link expires in {} minutes and can only be used once. \
+             If you did not ask for this, you can ignore this email.",
+            link,
+            config.password_reset_ttl_secs / 60
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_rules() {
+        assert!(validate_new_password("correct horse").is_ok());
+        assert!(validate_new_password("short").is_err());
+        assert!(validate_new_password("         ").is_err());
+    }
+
+    #[test]
+    fn test_reset_email_contains_link() {
+        let mut config = AppConfig::for_tests();
+        config.app_base_url = "https://hub.example.com/".to_string();
+
+        let message = reset_email("learner@example.com", "abc123", &config);
+        assert_eq!(message.to, "learner@example.com");
+        assert!(message
+            .body
+            .contains("https://hub.example.com/reset-password?token=abc123"));
+        assert!(message.body.contains("60 minutes"));
+    }
+}
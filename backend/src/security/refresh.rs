@@ -0,0 +1,380 @@
+use actix_web::{http::header, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::{Client, GenericClient};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::middleware::auth::generate_token;
+use crate::models::user::User;
+use crate::security::throttle::client_ip;
+use crate::security::tokens::{generate_opaque_token, hash_token};
+
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+// How stale `sessions.last_seen_at` may get before a request refreshes it
+const LAST_SEEN_RESOLUTION_SECS: i64 = 60;
+
+// Where a session was started from, shown to the user in their session list
+pub struct SessionDevice {
+    pub user_agent: Option<String>,
+    pub ip_address: String,
+}
+
+impl SessionDevice {
+    pub fn from_request(req: &HttpRequest, config: &AppConfig) -> Self {
+        let user_agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(truncate_user_agent);
+
+        SessionDevice {
+            user_agent,
+            ip_address: client_ip(req, config),
+        }
+    }
+}
+
+fn truncate_user_agent(user_agent: &str) -> String {
+    user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect()
+}
+
+// Access and refresh token pair handed out for a session
+pub struct SessionTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    // Token is unknown, or its session has been revoked
+    Invalid,
+    // Token was already rotated; its whole family has been revoked
+    Reused,
+    Expired,
+    Database(tokio_postgres::Error),
+    Pool(deadpool_postgres::PoolError),
+    Token(jsonwebtoken::errors::Error),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Invalid => write!(f, "Invalid refresh token"),
+            SessionError::Reused => write!(f, "Refresh token reuse detected; session revoked"),
+            SessionError::Expired => write!(f, "Refresh token has expired"),
+            SessionError::Database(e) => write!(f, "Database error: {}", e),
+            SessionError::Pool(e) => write!(f, "Database error: {}", e),
+            SessionError::Token(e) => write!(f, "Failed to generate token: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<tokio_postgres::Error> for SessionError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        SessionError::Database(e)
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for SessionError {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        SessionError::Pool(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for SessionError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        SessionError::Token(e)
+    }
+}
+
+impl SessionError {
+    // Whether the error is the client's fault rather than ours
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(
+            self,
+            SessionError::Invalid | SessionError::Reused | SessionError::Expired
+        )
+    }
+}
+
+// Start a new session (refresh token family) for a user that just logged in
+pub async fn start_session<C: GenericClient>(
+    client: &C,
+    user: &User,
+    device: &SessionDevice,
+    config: &AppConfig,
+) -> Result<SessionTokens, SessionError> {
+    let family_id = Uuid::new_v4();
+    let token_version: i32 = client
+        .query_one("SELECT token_version FROM users WHERE id = $1", &[&user.id])
+        .await?
+        .get("token_version");
+
+    client
+        .execute(
+            "INSERT INTO sessions (id, user_id, user_agent, ip_address) VALUES ($1, $2, $3, $4)",
+            &[&family_id, &user.id, &device.user_agent, &device.ip_address],
+        )
+        .await?;
+
+    let refresh_token = insert_refresh_token(client, user.id, family_id, config).await?;
+    let access_token = generate_token(user, family_id, token_version, config)?;
+
+    Ok(SessionTokens {
+        access_token,
+        refresh_token,
+    })
+}
+
+// Exchange a refresh token for a new token pair, rotating the refresh token.
+// Presenting a token that was already rotated revokes the whole family.
+pub async fn rotate_session(
+    client: &mut Client,
+    presented: &str,
+    device: &SessionDevice,
+    config: &AppConfig,
+) -> Result<(User, SessionTokens), SessionError> {
+    let transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "SELECT rt.id, rt.family_id, rt.expires_at, rt.rotated_at, rt.revoked_at,
+                    u.id AS user_id, u.email, u.full_name, u.is_admin, u.created_at, u.updated_at, u.web3_wallet, u.email_verified_at,
+                    u.token_version
+             FROM refresh_tokens rt
+             JOIN users u ON u.id = rt.user_id
+             WHERE rt.token_hash = $1
+             FOR UPDATE OF rt",
+            &[&hash_token(presented)],
+        )
+        .await?
+        .ok_or(SessionError::Invalid)?;
+
+    let token_id: Uuid = row.get("id");
+    let family_id: Uuid = row.get("family_id");
+
+    if let Err(e) = check_refresh_token_state(
+        row.get("expires_at"),
+        row.get("rotated_at"),
+        row.get("revoked_at"),
+        Utc::now(),
+    ) {
+        if let SessionError::Reused = e {
+            transaction
+                .execute(
+                    "UPDATE refresh_tokens SET revoked_at = NOW() WHERE family_id = $1 AND revoked_at IS NULL",
+                    &[&family_id],
+                )
+                .await?;
+            transaction.commit().await?;
+        }
+        return Err(e);
+    }
+
+    let user = User {
+        id: row.get("user_id"),
+        email: row.get("email"),
+        password_hash: None, // Don't return password hash
+        full_name: row.get("full_name"),
+        is_admin: row.get("is_admin"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+        web3_wallet: row.get("web3_wallet"),
+        email_verified_at: row.get("email_verified_at"),
+    };
+
+    transaction
+        .execute(
+            "UPDATE refresh_tokens SET rotated_at = NOW() WHERE id = $1",
+            &[&token_id],
+        )
+        .await?;
+    let refresh_token = insert_refresh_token(&transaction, user.id, family_id, config).await?;
+    let access_token = generate_token(&user, family_id, row.get("token_version"), config)?;
+
+    // Sessions that predate device tracking get their row on first refresh
+    transaction
+        .execute(
+            "INSERT INTO sessions (id, user_id, user_agent, ip_address) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (id) DO UPDATE SET user_agent = EXCLUDED.user_agent,
+                 ip_address = EXCLUDED.ip_address, last_seen_at = NOW()",
+            &[&family_id, &user.id, &device.user_agent, &device.ip_address],
+        )
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok((
+        user,
+        SessionTokens {
+            access_token,
+            refresh_token,
+        },
+    ))
+}
+
+// Revoke every refresh token in a session so it can no longer be refreshed
+pub async fn revoke_session<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    family_id: Uuid,
+) -> Result<u64, SessionError> {
+    let count = client
+        .execute(
+            "UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND family_id = $2 AND revoked_at IS NULL",
+            &[&user_id, &family_id],
+        )
+        .await?;
+
+    Ok(count)
+}
+
+// Revoke every session of a user, except `keep` when given (usually the caller's own)
+pub async fn revoke_other_sessions<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    keep: Option<Uuid>,
+) -> Result<u64, SessionError> {
+    let count = client
+        .execute(
+            "UPDATE refresh_tokens SET revoked_at = NOW()
+             WHERE user_id = $1 AND ($2::UUID IS NULL OR family_id <> $2) AND revoked_at IS NULL",
+            &[&user_id, &keep],
+        )
+        .await?;
+
+    Ok(count)
+}
+
+// Record activity on a session, at most once per resolution window
+pub async fn touch_session<C: GenericClient>(
+    client: &C,
+    session_id: Uuid,
+    last_seen_at: Option<DateTime<Utc>>,
+) -> Result<(), tokio_postgres::Error> {
+    if !should_touch_session(last_seen_at, Utc::now()) {
+        return Ok(());
+    }
+
+    client
+        .execute(
+            "UPDATE sessions SET last_seen_at = NOW() WHERE id = $1",
+            &[&session_id],
+        )
+        .await?;
+
+    Ok(())
+}
+
+// Sessions without a row (from before device tracking) are left alone
+fn should_touch_session(last_seen_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    match last_seen_at {
+        Some(last_seen_at) => now - last_seen_at >= Duration::seconds(LAST_SEEN_RESOLUTION_SECS),
+        None => false,
+    }
+}
+
+async fn insert_refresh_token<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    family_id: Uuid,
+    config: &AppConfig,
+) -> Result<String, SessionError> {
+    let token = generate_opaque_token();
+    let expires_at = Utc::now() + Duration::seconds(config.refresh_token_ttl_secs);
+
+    client
+        .execute(
+            "INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at) VALUES ($1, $2, $3, $4, $5)",
+            &[&Uuid::new_v4(), &user_id, &family_id, &hash_token(&token), &expires_at],
+        )
+        .await?;
+
+    Ok(token)
+}
+
+// Decide whether a stored refresh token may still be exchanged
+fn check_refresh_token_state(
+    expires_at: DateTime<Utc>,
+    rotated_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<(), SessionError> {
+    if rotated_at.is_some() {
+        return Err(SessionError::Reused);
+    }
+
+    if revoked_at.is_some() {
+        return Err(SessionError::Invalid);
+    }
+
+    if expires_at <= now {
+        return Err(SessionError::Expired);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_token_can_be_rotated() {
+        let now = Utc::now();
+        assert!(check_refresh_token_state(now + Duration::days(1), None, None, now).is_ok());
+    }
+
+    #[test]
+    fn test_rotated_token_is_reuse() {
+        let now = Utc::now();
+        let result = check_refresh_token_state(
+            now + Duration::days(1),
+            Some(now - Duration::minutes(5)),
+            None,
+            now,
+        );
+        assert!(matches!(result, Err(SessionError::Reused)));
+
+        // Reuse wins even if the family was already revoked
+        let result = check_refresh_token_state(
+            now + Duration::days(1),
+            Some(now - Duration::minutes(5)),
+            Some(now - Duration::minutes(1)),
+            now,
+        );
+        assert!(matches!(result, Err(SessionError::Reused)));
+    }
+
+    #[test]
+    fn test_last_seen_is_coarse() {
+        let now = Utc::now();
+
+        assert!(!should_touch_session(Some(now - Duration::seconds(5)), now));
+        assert!(should_touch_session(Some(now - Duration::minutes(2)), now));
+        assert!(!should_touch_session(None, now));
+    }
+
+    #[test]
+    fn test_user_agent_is_truncated() {
+        let long = "Mozilla/5.0 ".repeat(100);
+        assert_eq!(truncate_user_agent(&long).chars().count(), MAX_USER_AGENT_LENGTH);
+        assert_eq!(truncate_user_agent("curl/8.4.0"), "curl/8.4.0");
+    }
+
+    #[test]
+    fn test_revoked_and_expired_tokens() {
+        let now = Utc::now();
+
+        let result = check_refresh_token_state(now + Duration::days(1), None, Some(now), now);
+        assert!(matches!(result, Err(SessionError::Invalid)));
+
+        let result = check_refresh_token_state(now - Duration::seconds(1), None, None, now);
+        assert!(matches!(result, Err(SessionError::Expired)));
+        assert!(SessionError::Expired.is_unauthorized());
+    }
+}
@@ -0,0 +1,159 @@
+use deadpool_postgres::GenericClient;
+use uuid::Uuid;
+
+// Role backed by `users.is_admin` rather than a `user_roles` row; it carries
+// every permission and is reflected in the token's `role` claim
+pub const ADMIN_ROLE: &str = "admin";
+
+// Something a role allows. Stored by name in `role_permissions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    // Create courses and manage the ones you created
+    ManageOwnCourses,
+    // Manage every course regardless of who created it
+    ManageAllCourses,
+    WriteBlog,
+    ModerateComments,
+    ManageUsers,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 5] = [
+        Permission::ManageOwnCourses,
+        Permission::ManageAllCourses,
+        Permission::WriteBlog,
+        Permission::ModerateComments,
+        Permission::ManageUsers,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ManageOwnCourses => "courses:write",
+            Permission::ManageAllCourses => "courses:write_any",
+            Permission::WriteBlog => "blog:write",
+            Permission::ModerateComments => "comments:moderate",
+            Permission::ManageUsers => "users:manage",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == name)
+    }
+}
+
+// Instructors may only touch courses they created, unless they can manage all courses
+pub fn can_manage_course(user_id: Uuid, can_manage_all: bool, created_by: Uuid) -> bool {
+    can_manage_all || created_by == user_id
+}
+
+// Names of every role a user holds, including the admin flag
+pub async fn roles_for_user<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+) -> Result<Option<Vec<String>>, tokio_postgres::Error> {
+    let row = client
+        .query_opt(
+            "SELECT is_admin,
+                    ARRAY(SELECT role FROM user_roles WHERE user_id = users.id ORDER BY role)::text[] AS roles
+             FROM users WHERE id = $1",
+            &[&user_id],
+        )
+        .await?;
+
+    Ok(row.map(|row| {
+        let mut roles: Vec<String> = row.get("roles");
+        if row.get::<_, bool>("is_admin") {
+            roles.insert(0, ADMIN_ROLE.to_string());
+        }
+        roles
+    }))
+}
+
+pub async fn role_exists<C: GenericClient>(
+    client: &C,
+    role: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let row = client
+        .query_one("SELECT EXISTS(SELECT 1 FROM roles WHERE name = $1)", &[&role])
+        .await?;
+
+    Ok(row.get(0))
+}
+
+// Give a user a role. Returns false when they already had it.
+pub async fn grant_role<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    role: &str,
+    granted_by: Uuid,
+) -> Result<bool, tokio_postgres::Error> {
+    // Flipping is_admin also bumps token_version, so old tokens lose the old role claim
+    let changed = if role == ADMIN_ROLE {
+        client
+            .execute(
+                "UPDATE users SET is_admin = true, updated_at = NOW() WHERE id = $1 AND NOT is_admin",
+                &[&user_id],
+            )
+            .await?
+    } else {
+        client
+            .execute(
+                "INSERT INTO user_roles (user_id, role, granted_by) VALUES ($1, $2, $3)
+                 ON CONFLICT (user_id, role) DO NOTHING",
+                &[&user_id, &role, &granted_by],
+            )
+            .await?
+    };
+
+    Ok(changed > 0)
+}
+
+// Take a role away from a user. Returns false when they didn't have it.
+pub async fn revoke_role<C: GenericClient>(
+    client: &C,
+    user_id: Uuid,
+    role: &str,
+) -> Result<bool, tokio_postgres::Error> {
+    let changed = if role == ADMIN_ROLE {
+        client
+            .execute(
+                "UPDATE users SET is_admin = false, updated_at = NOW() WHERE id = $1 AND is_admin",
+                &[&user_id],
+            )
+            .await?
+    } else {
+        client
+            .execute(
+                "DELETE FROM user_roles WHERE user_id = $1 AND role = $2",
+                &[&user_id, &role],
+            )
+            .await?
+    };
+
+    Ok(changed > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_names_round_trip() {
+        for permission in Permission::ALL {
+            assert_eq!(Permission::from_name(permission.as_str()), Some(permission));
+        }
+        assert_eq!(Permission::from_name("courses:delete_everything"), None);
+    }
+
+    #[test]
+    fn test_instructors_only_manage_own_courses() {
+        let instructor = Uuid::new_v4();
+        let someone_else = Uuid::new_v4();
+
+        assert!(can_manage_course(instructor, false, instructor));
+        assert!(!can_manage_course(instructor, false, someone_else));
+        assert!(can_manage_course(instructor, true, someone_else));
+    }
+}
@@ -0,0 +1,268 @@
+use actix_web::HttpRequest;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::GenericClient;
+
+use crate::config::AppConfig;
+use crate::security::audit::{self, AuditEntry};
+
+// Failures allowed before backoff starts
+const FREE_ATTEMPTS: i32 = 3;
+const BACKOFF_BASE_SECS: i64 = 1;
+const BACKOFF_MAX_SECS: i64 = 60;
+
+// Failed-login bookkeeping for one (email, client IP) pair
+#[derive(Debug, Clone)]
+pub struct ThrottleState {
+    pub failed_count: i32,
+    pub last_failed_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+// Why a login attempt is refused before the password is even checked
+#[derive(Debug, PartialEq, Eq)]
+pub enum Throttled {
+    Backoff { retry_after: Duration },
+    LockedOut { retry_after: Duration },
+}
+
+impl Throttled {
+    pub fn retry_after_secs(&self) -> i64 {
+        match self {
+            Throttled::Backoff { retry_after } | Throttled::LockedOut { retry_after } => {
+                retry_after.num_seconds().max(1)
+            }
+        }
+    }
+}
+
+// The client address used for throttling. Proxy headers are only trusted
+// when the app is configured to sit behind a proxy that sets them.
+pub fn client_ip(req: &HttpRequest, config: &AppConfig) -> String {
+    let info = req.connection_info();
+    let addr = if config.trust_proxy_headers {
+        info.realip_remote_addr()
+    } else {
+        info.peer_addr()
+    };
+
+    addr.map(strip_port).unwrap_or("unknown").to_string()
+}
+
+fn strip_port(addr: &str) -> &str {
+    // [v6]:port, v4:port, or a bare address
+    if let Some(rest) = addr.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match addr.rsplit_once(':') {
+        Some((host, _)) if !host.contains(':') => host,
+        _ => addr,
+    }
+}
+
+// Emails are matched case-insensitively so casing cannot dodge the limit
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// Delay enforced after `failed_count` consecutive failures:
+// nothing for the first few, then doubling up to a cap
+pub fn backoff_delay(failed_count: i32) -> Duration {
+    if failed_count < FREE_ATTEMPTS {
+        return Duration::zero();
+    }
+
+    let exponent = (failed_count - FREE_ATTEMPTS).min(16) as u32;
+    Duration::seconds((BACKOFF_BASE_SECS << exponent).min(BACKOFF_MAX_SECS))
+}
+
+// Decide whether an attempt may proceed given the stored state
+pub fn check(state: Option<&ThrottleState>, now: DateTime<Utc>) -> Result<(), Throttled> {
+    let state = match state {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+
+    if let Some(locked_until) = state.locked_until {
+        if locked_until > now {
+            return Err(Throttled::LockedOut {
+                retry_after: locked_until - now,
+            });
+        }
+    }
+
+    let allowed_at = state.last_failed_at + backoff_delay(state.failed_count);
+    if allowed_at > now {
+        return Err(Throttled::Backoff {
+            retry_after: allowed_at - now,
+        });
+    }
+
+    Ok(())
+}
+
+pub async fn check_login_allowed<C: GenericClient>(
+    client: &C,
+    email: &str,
+    ip_address: &str,
+) -> Result<Result<(), Throttled>, tokio_postgres::Error> {
+    let state = client
+        .query_opt(
+            "SELECT failed_count, last_failed_at, locked_until FROM login_throttles
+             WHERE email = $1 AND ip_address = $2",
+            &[&normalize_email(email), &ip_address],
+        )
+        .await?
+        .map(|row| ThrottleState {
+            failed_count: row.get("failed_count"),
+            last_failed_at: row.get("last_failed_at"),
+            locked_until: row.get("locked_until"),
+        });
+
+    Ok(check(state.as_ref(), Utc::now()))
+}
+
+// Count a failed attempt; locks the pair out once the limit is reached and
+// records the lockout in the audit log. Failures older than the window are forgotten.
+pub async fn record_login_failure<C: GenericClient>(
+    client: &C,
+    email: &str,
+    ip_address: &str,
+    config: &AppConfig,
+) -> Result<(), tokio_postgres::Error> {
+    let email = normalize_email(email);
+
+    let failed_count: i32 = client
+        .query_one(
+            "INSERT INTO login_throttles (email, ip_address, failed_count, last_failed_at)
+             VALUES ($1, $2, 1, NOW())
+             ON CONFLICT (email, ip_address) DO UPDATE SET
+                 failed_count = CASE
+                     WHEN login_throttles.last_failed_at < NOW() - make_interval(secs => $3) THEN 1
+                     ELSE login_throttles.failed_count + 1
+                 END,
+                 last_failed_at = NOW()
+             RETURNING failed_count",
+            &[&email, &ip_address, &(config.login_failure_window_secs as f64)],
+        )
+        .await?
+        .get("failed_count");
+
+    if failed_count < config.login_max_failures {
+        return Ok(());
+    }
+
+    let locked_until = Utc::now() + Duration::seconds(config.login_lockout_secs);
+    client
+        .execute(
+            "UPDATE login_throttles SET locked_until = $3, failed_count = 0
+             WHERE email = $1 AND ip_address = $2",
+            &[&email, &ip_address, &locked_until],
+        )
+        .await?;
+
+    let user_id = client
+        .query_opt("SELECT id FROM users WHERE LOWER(email) = $1", &[&email])
+        .await?
+        .map(|row| row.get("id"));
+
+    audit::record(
+        client,
+        AuditEntry {
+            event: "login_lockout",
+            user_id,
+            actor_id: None,
+            ip_address: Some(ip_address),
+            details: serde_json::json!({
+                "email": email,
+                "failed_count": failed_count,
+                "locked_until": locked_until,
+            }),
+        },
+    )
+    .await
+}
+
+// Forget failures for the pair after a successful login
+pub async fn record_login_success<C: GenericClient>(
+    client: &C,
+    email: &str,
+    ip_address: &str,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            "DELETE FROM login_throttles WHERE email = $1 AND ip_address = $2",
+            &[&normalize_email(email), &ip_address],
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn state(failed_count: i32, last_failed_at: DateTime<Utc>) -> ThrottleState {
+        ThrottleState {
+            failed_count,
+            last_failed_at,
+            locked_until: None,
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_exponentially() {
+        assert_eq!(backoff_delay(0), Duration::zero());
+        assert_eq!(backoff_delay(FREE_ATTEMPTS - 1), Duration::zero());
+        assert_eq!(backoff_delay(FREE_ATTEMPTS), Duration::seconds(1));
+        assert_eq!(backoff_delay(FREE_ATTEMPTS + 1), Duration::seconds(2));
+        assert_eq!(backoff_delay(FREE_ATTEMPTS + 3), Duration::seconds(8));
+        assert_eq!(backoff_delay(1000), Duration::seconds(BACKOFF_MAX_SECS));
+    }
+
+    #[test]
+    fn test_check_backoff_and_lockout() {
+        let now = Utc::now();
+        assert!(check(None, now).is_ok());
+        assert!(check(Some(&state(1, now)), now).is_ok());
+
+        let result = check(Some(&state(FREE_ATTEMPTS + 2, now - Duration::seconds(1))), now);
+        assert_eq!(
+            result,
+            Err(Throttled::Backoff {
+                retry_after: Duration::seconds(3)
+            })
+        );
+        assert!(check(Some(&state(FREE_ATTEMPTS + 2, now - Duration::seconds(4))), now).is_ok());
+
+        let mut locked = state(0, now - Duration::minutes(5));
+        locked.locked_until = Some(now + Duration::minutes(10));
+        let err = check(Some(&locked), now).unwrap_err();
+        assert!(matches!(err, Throttled::LockedOut { .. }));
+        assert_eq!(err.retry_after_secs(), 600);
+
+        // Expired lockouts no longer apply
+        locked.locked_until = Some(now - Duration::seconds(1));
+        assert!(check(Some(&locked), now).is_ok());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let mut config = AppConfig::for_tests();
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.7:51234".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.9"))
+            .to_http_request();
+
+        // Forwarded headers are ignored unless the proxy is trusted
+        assert_eq!(client_ip(&req, &config), "10.0.0.7");
+
+        config.trust_proxy_headers = true;
+        assert_eq!(client_ip(&req, &config), "203.0.113.9");
+
+        assert_eq!(strip_port("[2001:db8::1]:443"), "2001:db8::1");
+        assert_eq!(strip_port("2001:db8::1"), "2001:db8::1");
+        assert_eq!(normalize_email(" Learner@Example.COM "), "learner@example.com");
+    }
+}
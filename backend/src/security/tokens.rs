@@ -0,0 +1,39 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+const TOKEN_BYTES: usize = 32;
+
+// Generate an opaque random token to hand to a client
+pub fn generate_opaque_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Hash a token for storage; only the hash is ever persisted
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_random() {
+        let first = generate_opaque_token();
+        let second = generate_opaque_token();
+
+        assert_ne!(first, second);
+        assert_eq!(first.len(), TOKEN_BYTES * 2);
+    }
+
+    #[test]
+    fn test_hash_is_stable_and_hides_token() {
+        let token = generate_opaque_token();
+
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+        assert_eq!(hash_token(&token).len(), 64);
+    }
+}
@@ -0,0 +1,123 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+// RFC 6238 parameters understood by common authenticator apps
+pub const TOTP_DIGITS: u32 = 6;
+pub const TOTP_STEP_SECS: i64 = 30;
+// Accept codes from one step either side to tolerate clock drift
+const TOTP_SKEW_STEPS: i64 = 1;
+
+// Generate a random 160-bit shared secret, base32 encoded
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+// Provisioning URI that authenticator apps read from a QR code
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        url_encode(issuer),
+        url_encode(account),
+        secret,
+        url_encode(issuer),
+        TOTP_DIGITS,
+        TOTP_STEP_SECS
+    )
+}
+
+// Check a code against a base32 secret at `now` (unix seconds). Returns the
+// time step the code matched so callers can refuse to accept it twice.
+pub fn verify_code(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let current = now.div_euclid(TOTP_STEP_SECS);
+    (current - TOTP_SKEW_STEPS..=current + TOTP_SKEW_STEPS)
+        .find(|&step| step >= 0 && hotp(&key, step as u64, TOTP_DIGITS) == code)
+}
+
+// RFC 4226 HOTP value for a counter
+fn hotp(key: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(digits)
+}
+
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA-1 key
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+        ];
+
+        for (time, expected) in vectors {
+            assert_eq!(hotp(RFC_KEY, (time / TOTP_STEP_SECS) as u64, 8), expected);
+        }
+    }
+
+    #[test]
+    fn test_verify_code_window() {
+        let secret = BASE32_NOPAD.encode(RFC_KEY);
+
+        // 6-digit code for T = 1234567890
+        assert_eq!(verify_code(&secret, "005924", 1234567890), Some(1234567890 / 30));
+        // Still accepted one step later, but not two
+        assert!(verify_code(&secret, "005924", 1234567890 + 30).is_some());
+        assert!(verify_code(&secret, "005924", 1234567890 + 90).is_none());
+
+        assert!(verify_code(&secret, "5924", 1234567890).is_none());
+        assert!(verify_code(&secret, "00592a", 1234567890).is_none());
+        assert!(verify_code("not base32!", "005924", 1234567890).is_none());
+    }
+
+    #[test]
+    fn test_generated_secret_round_trips() {
+        let secret = generate_secret();
+        assert_eq!(BASE32_NOPAD.decode(secret.as_bytes()).unwrap().len(), 20);
+
+        let uri = otpauth_uri("Hex The Add Hub", "learner@example.com", &secret);
+        assert!(uri.starts_with("otpauth://totp/Hex%20The%20Add%20Hub:learner%40example.com?secret="));
+        assert!(uri.contains("&issuer=Hex%20The%20Add%20Hub&"));
+    }
+}
@@ -0,0 +1,4 @@
+pub mod pay;
+pub mod rpc;
+pub mod signature;
+pub mod siws;
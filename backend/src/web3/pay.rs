@@ -0,0 +1,238 @@
+use rand::RngCore;
+use solana_sdk::pubkey::Pubkey;
+use std::fmt;
+
+use crate::web3::rpc::{ConfirmedTransaction, RpcError, SolanaRpc};
+
+pub const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+
+// Why a transaction doesn't count as payment for an order
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaymentError {
+    Failed,
+    MissingReference,
+    MissingRecipient,
+    Underpaid { expected: u64, received: u64 },
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentError::Failed => write!(f, "Transaction failed"),
+            PaymentError::MissingReference => write!(f, "Transaction does not carry the order reference"),
+            PaymentError::MissingRecipient => write!(f, "Transaction does not pay the recipient"),
+            PaymentError::Underpaid { expected, received } => write!(
+                f,
+                "Transaction paid {} SOL, expected {} SOL",
+                format_sol(*received),
+                format_sol(*expected)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PaymentError {}
+
+// A fresh reference key for a transfer request. It only has to be unique and
+// valid base58 public key material; nobody holds its private key.
+pub fn new_reference() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Pubkey::new_from_array(bytes).to_string()
+}
+
+// Lamports as a decimal SOL amount with no trailing zeros, e.g. 1500000000 -> "1.5"
+pub fn format_sol(lamports: u64) -> String {
+    let whole = lamports / LAMPORTS_PER_SOL;
+    let fraction = lamports % LAMPORTS_PER_SOL;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+
+    let fraction = format!("{:09}", fraction);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+// A Solana Pay transfer request URL (native SOL), which wallets turn into a transaction
+pub fn transfer_request_url(
+    recipient: &str,
+    lamports: u64,
+    reference: &str,
+    label: &str,
+    message: &str,
+) -> String {
+    format!(
+        "solana:{}?amount={}&reference={}&label={}&message={}",
+        recipient,
+        format_sol(lamports),
+        reference,
+        percent_encode(label),
+        percent_encode(message)
+    )
+}
+
+// Check a confirmed transaction pays at least `lamports` to `recipient` and
+// carries `reference` among its accounts
+pub fn validate_transfer(
+    transaction: &ConfirmedTransaction,
+    recipient: &str,
+    reference: &str,
+    lamports: u64,
+) -> Result<(), PaymentError> {
+    let meta = match &transaction.meta {
+        Some(meta) if meta.err.is_none() => meta,
+        _ => return Err(PaymentError::Failed),
+    };
+
+    let keys = transaction.account_keys();
+    if !keys.contains(&reference) {
+        return Err(PaymentError::MissingReference);
+    }
+
+    let index = keys
+        .iter()
+        .position(|key| *key == recipient)
+        .ok_or(PaymentError::MissingRecipient)?;
+    let (pre, post) = match (meta.pre_balances.get(index), meta.post_balances.get(index)) {
+        (Some(pre), Some(post)) => (*pre, *post),
+        _ => return Err(PaymentError::MissingRecipient),
+    };
+
+    let received = post.saturating_sub(pre);
+    if received < lamports {
+        return Err(PaymentError::Underpaid {
+            expected: lamports,
+            received,
+        });
+    }
+
+    Ok(())
+}
+
+// Look on chain for transactions paying a transfer request. Returns the
+// signatures of those that qualify, earliest first. One transaction can carry
+// several references, so callers skip signatures already used by another order.
+pub async fn find_payments(
+    rpc: &dyn SolanaRpc,
+    recipient: &str,
+    reference: &str,
+    lamports: u64,
+) -> Result<Vec<String>, RpcError> {
+    let signatures = rpc.signatures_for_address(reference).await?;
+    let mut payments = Vec::new();
+
+    // Newest first from the node
+    for info in signatures.iter().rev().filter(|info| info.err.is_none()) {
+        let transaction = match rpc.transaction(&info.signature).await? {
+            Some(transaction) => transaction,
+            None => continue,
+        };
+        if validate_transfer(&transaction, recipient, reference, lamports).is_ok() {
+            payments.push(info.signature.clone());
+        }
+    }
+
+    Ok(payments)
+}
+
+// The earliest payment not already recorded on another order
+pub fn first_unclaimed(payments: Vec<String>, claimed: &[String]) -> Option<String> {
+    payments.into_iter().find(|signature| !claimed.contains(signature))
+}
+
+// encodeURIComponent-style escaping for query values
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web3::rpc::FakeSolanaRpc;
+    use std::str::FromStr;
+
+    const SHOP: &str = "GvHeR432g7MjN9uKyX3Dzg66TqwrEWgANLnnFZXMeyyj";
+    const PAYER: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+
+    #[test]
+    fn test_format_sol() {
+        assert_eq!(format_sol(0), "0");
+        assert_eq!(format_sol(LAMPORTS_PER_SOL), "1");
+        assert_eq!(format_sol(1_500_000_000), "1.5");
+        assert_eq!(format_sol(1), "0.000000001");
+        assert_eq!(format_sol(12_340_000_000), "12.34");
+    }
+
+    #[test]
+    fn test_transfer_request_url() {
+        let reference = new_reference();
+        assert!(Pubkey::from_str(&reference).is_ok());
+        assert_ne!(reference, new_reference());
+
+        assert_eq!(
+            transfer_request_url(SHOP, 250_000_000, &reference, "Hex The Add Hub", "Rust & Solana 101"),
+            format!(
+                "solana:{}?amount=0.25&reference={}&label=Hex%20The%20Add%20Hub&message=Rust%20%26%20Solana%20101",
+                SHOP, reference
+            )
+        );
+    }
+
+    #[test]
+    fn test_claimed_payments_are_skipped() {
+        let payments = vec!["first".to_string(), "second".to_string()];
+
+        assert_eq!(first_unclaimed(payments.clone(), &[]), Some("first".to_string()));
+        assert_eq!(first_unclaimed(payments.clone(), &["first".to_string()]), Some("second".to_string()));
+        assert_eq!(first_unclaimed(payments, &["second".to_string(), "first".to_string()]), None);
+    }
+
+    #[tokio::test]
+    async fn test_find_payments() {
+        let rpc = FakeSolanaRpc::default();
+        let reference = new_reference();
+        let price = LAMPORTS_PER_SOL / 2;
+
+        // Nothing on chain yet
+        assert!(find_payments(&rpc, SHOP, &reference, price).await.unwrap().is_empty());
+
+        // Payments that don't qualify are skipped
+        rpc.add_transfer(PAYER, SHOP, price, &[&reference], true);
+        rpc.add_transfer(PAYER, SHOP, price - 1, &[&reference], false);
+        rpc.add_transfer(PAYER, PAYER, price, &[&reference], false);
+        rpc.add_transfer(PAYER, SHOP, price, &[], false);
+        assert!(find_payments(&rpc, SHOP, &reference, price).await.unwrap().is_empty());
+
+        let first = rpc.add_transfer(PAYER, SHOP, price, &[&reference], false);
+        let second = rpc.add_transfer(PAYER, SHOP, price, &[&reference], false);
+        assert_eq!(
+            find_payments(&rpc, SHOP, &reference, price).await.unwrap(),
+            vec![first, second]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_transfer_reasons() {
+        let rpc = FakeSolanaRpc::default();
+        let reference = new_reference();
+
+        let short = rpc.add_transfer(PAYER, SHOP, 10, &[&reference], false);
+        let short = rpc.transaction(&short).await.unwrap().unwrap();
+        assert_eq!(
+            validate_transfer(&short, SHOP, &reference, 11),
+            Err(PaymentError::Underpaid { expected: 11, received: 10 })
+        );
+        assert_eq!(validate_transfer(&short, SHOP, &new_reference(), 10), Err(PaymentError::MissingReference));
+        assert_eq!(validate_transfer(&short, PAYER, &reference, 10), Err(PaymentError::Underpaid { expected: 10, received: 0 }));
+        assert_eq!(validate_transfer(&short, &new_reference(), &reference, 10), Err(PaymentError::MissingRecipient));
+        assert_eq!(validate_transfer(&short, SHOP, &reference, 10), Ok(()));
+    }
+}
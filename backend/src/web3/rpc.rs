@@ -0,0 +1,274 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_request::RpcRequest;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::AppConfig;
+
+// Most signatures looked at when searching for a payment by reference
+const SIGNATURE_SEARCH_LIMIT: usize = 100;
+
+#[derive(Debug)]
+pub struct RpcError(pub String);
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Solana RPC error: {}", self.0)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+// An entry from getSignaturesForAddress
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureInfo {
+    pub signature: String,
+    // Set when the transaction failed
+    pub err: Option<Value>,
+}
+
+// The parts of a getTransaction ("json" encoding) result payments are checked against
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfirmedTransaction {
+    pub slot: u64,
+    pub meta: Option<TransactionMeta>,
+    pub transaction: EncodedTransaction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionMeta {
+    pub err: Option<Value>,
+    pub pre_balances: Vec<u64>,
+    pub post_balances: Vec<u64>,
+    // Accounts pulled in through address lookup tables (versioned transactions)
+    #[serde(default)]
+    pub loaded_addresses: Option<LoadedAddresses>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LoadedAddresses {
+    #[serde(default)]
+    pub writable: Vec<String>,
+    #[serde(default)]
+    pub readonly: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EncodedTransaction {
+    pub message: EncodedMessage,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodedMessage {
+    pub account_keys: Vec<String>,
+}
+
+impl ConfirmedTransaction {
+    // Every account the transaction touches, in the order balances are reported:
+    // static keys, then writable and read-only lookup table addresses
+    pub fn account_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self
+            .transaction
+            .message
+            .account_keys
+            .iter()
+            .map(String::as_str)
+            .collect();
+        if let Some(loaded) = self.meta.as_ref().and_then(|meta| meta.loaded_addresses.as_ref()) {
+            keys.extend(loaded.writable.iter().map(String::as_str));
+            keys.extend(loaded.readonly.iter().map(String::as_str));
+        }
+        keys
+    }
+}
+
+// The Solana RPC calls payment verification needs, so tests can swap in a fake
+#[async_trait]
+pub trait SolanaRpc: Send + Sync {
+    // Confirmed transactions that mention `address`, newest first
+    async fn signatures_for_address(&self, address: &str) -> Result<Vec<SignatureInfo>, RpcError>;
+    // None when the cluster doesn't know the signature (yet)
+    async fn transaction(&self, signature: &str) -> Result<Option<ConfirmedTransaction>, RpcError>;
+}
+
+// Talks to a real cluster (or solana-test-validator) through solana-client
+pub struct SolanaClientRpc {
+    client: RpcClient,
+}
+
+impl SolanaClientRpc {
+    pub fn new(url: String) -> Self {
+        SolanaClientRpc {
+            client: RpcClient::new(url),
+        }
+    }
+}
+
+#[async_trait]
+impl SolanaRpc for SolanaClientRpc {
+    async fn signatures_for_address(&self, address: &str) -> Result<Vec<SignatureInfo>, RpcError> {
+        self.client
+            .send(
+                RpcRequest::GetSignaturesForAddress,
+                json!([address, { "commitment": "confirmed", "limit": SIGNATURE_SEARCH_LIMIT }]),
+            )
+            .await
+            .map_err(|e| RpcError(e.to_string()))
+    }
+
+    async fn transaction(&self, signature: &str) -> Result<Option<ConfirmedTransaction>, RpcError> {
+        self.client
+            .send(
+                RpcRequest::GetTransaction,
+                json!([
+                    signature,
+                    { "encoding": "json", "commitment": "confirmed", "maxSupportedTransactionVersion": 0 }
+                ]),
+            )
+            .await
+            .map_err(|e| RpcError(e.to_string()))
+    }
+}
+
+pub fn solana_rpc_from_config(config: &AppConfig) -> Arc<dyn SolanaRpc> {
+    Arc::new(SolanaClientRpc::new(config.solana_rpc_url.clone()))
+}
+
+// In-memory cluster for tests: holds transactions and answers lookups from them
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeSolanaRpc {
+    transactions: std::sync::Mutex<Vec<(String, ConfirmedTransaction)>>,
+}
+
+#[cfg(test)]
+impl FakeSolanaRpc {
+    // Record a transfer of `lamports` from `payer` to `recipient` that also lists
+    // `extra_keys` (e.g. a Solana Pay reference); returns its signature
+    pub fn add_transfer(
+        &self,
+        payer: &str,
+        recipient: &str,
+        lamports: u64,
+        extra_keys: &[&str],
+        failed: bool,
+    ) -> String {
+        let mut account_keys = vec![payer.to_string(), recipient.to_string()];
+        account_keys.extend(extra_keys.iter().map(|key| key.to_string()));
+        account_keys.push("11111111111111111111111111111111".to_string());
+
+        let mut pre_balances = vec![10_000_000_000, 1_000_000_000];
+        let mut post_balances = vec![10_000_000_000 - lamports - 5000, 1_000_000_000 + lamports];
+        pre_balances.resize(account_keys.len(), 1);
+        post_balances.resize(account_keys.len(), 1);
+
+        let mut transactions = self.transactions.lock().unwrap();
+        let slot = transactions.len() as u64 + 1;
+        let signature = format!("fake-signature-{}", slot);
+        transactions.push((
+            signature.clone(),
+            ConfirmedTransaction {
+                slot,
+                meta: Some(TransactionMeta {
+                    err: failed.then(|| json!({ "InstructionError": [0, "Custom"] })),
+                    pre_balances,
+                    post_balances,
+                    loaded_addresses: None,
+                }),
+                transaction: EncodedTransaction {
+                    message: EncodedMessage { account_keys },
+                },
+            },
+        ));
+        signature
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl SolanaRpc for FakeSolanaRpc {
+    async fn signatures_for_address(&self, address: &str) -> Result<Vec<SignatureInfo>, RpcError> {
+        let transactions = self.transactions.lock().unwrap();
+        Ok(transactions
+            .iter()
+            .rev()
+            .filter(|(_, transaction)| transaction.account_keys().contains(&address))
+            .map(|(signature, transaction)| SignatureInfo {
+                signature: signature.clone(),
+                err: transaction.meta.as_ref().and_then(|meta| meta.err.clone()),
+            })
+            .collect())
+    }
+
+    async fn transaction(&self, signature: &str) -> Result<Option<ConfirmedTransaction>, RpcError> {
+        let transactions = self.transactions.lock().unwrap();
+        Ok(transactions
+            .iter()
+            .find(|(known, _)| known == signature)
+            .map(|(_, transaction)| transaction.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_versioned_transaction() {
+        // Trimmed getTransaction response for a v0 transaction using a lookup table
+        let response = json!({
+            "slot": 245_000_123,
+            "blockTime": 1_700_000_000,
+            "version": 0,
+            "meta": {
+                "err": null,
+                "fee": 5000,
+                "preBalances": [2_000_000_000u64, 0, 1],
+                "postBalances": [1_499_995_000u64, 500_000_000, 1],
+                "loadedAddresses": { "writable": [], "readonly": ["Ref1111111111111111111111111111111111111111"] }
+            },
+            "transaction": {
+                "signatures": ["5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW"],
+                "message": {
+                    "accountKeys": ["Payer111111111111111111111111111111111111111", "Shop1111111111111111111111111111111111111111", "11111111111111111111111111111111"],
+                    "recentBlockhash": "EkSnNWid2cvwEVnVx9aBqawnmiCNiDgp3gUdkDPTKN1N",
+                    "instructions": []
+                }
+            }
+        });
+
+        let transaction: ConfirmedTransaction = serde_json::from_value(response).unwrap();
+        assert_eq!(transaction.slot, 245_000_123);
+        assert_eq!(
+            transaction.account_keys(),
+            vec![
+                "Payer111111111111111111111111111111111111111",
+                "Shop1111111111111111111111111111111111111111",
+                "11111111111111111111111111111111",
+                "Ref1111111111111111111111111111111111111111",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fake_finds_transactions_by_account() {
+        let rpc = FakeSolanaRpc::default();
+        let first = rpc.add_transfer("payer", "shop", 10, &["ref-a"], false);
+        let second = rpc.add_transfer("payer", "shop", 20, &["ref-b"], true);
+
+        let found = rpc.signatures_for_address("shop").await.unwrap();
+        let signatures: Vec<&str> = found.iter().map(|info| info.signature.as_str()).collect();
+        assert_eq!(signatures, vec![second.as_str(), first.as_str()]);
+        assert!(found[0].err.is_some());
+
+        assert_eq!(rpc.signatures_for_address("ref-a").await.unwrap().len(), 1);
+        assert!(rpc.transaction(&first).await.unwrap().is_some());
+        assert!(rpc.transaction("unknown").await.unwrap().is_none());
+    }
+}
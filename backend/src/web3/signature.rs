@@ -0,0 +1,226 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use chrono::Utc;
+use deadpool_postgres::GenericClient;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config::AppConfig;
+use crate::web3::siws::{SiwsError, SiwsMessage};
+
+// Errors that can occur while checking a wallet signature
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    InvalidWalletAddress,
+    InvalidSignature,
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::InvalidWalletAddress => write!(f, "Invalid wallet address"),
+            SignatureError::InvalidSignature => write!(f, "Invalid signature encoding"),
+            SignatureError::Mismatch => write!(f, "Signature does not match wallet and message"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+// Why a signed sign-in challenge was not accepted as proof of wallet ownership
+#[derive(Debug)]
+pub enum WalletProofError {
+    Message(SiwsError),
+    Signature(SignatureError),
+    ChallengeUnavailable,
+    Database(tokio_postgres::Error),
+}
+
+impl fmt::Display for WalletProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletProofError::Message(e) => write!(f, "{}", e),
+            WalletProofError::Signature(e) => write!(f, "{}", e),
+            WalletProofError::ChallengeUnavailable => {
+                write!(f, "Challenge is invalid, expired or already used")
+            }
+            WalletProofError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WalletProofError {}
+
+impl From<tokio_postgres::Error> for WalletProofError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        WalletProofError::Database(e)
+    }
+}
+
+impl ResponseError for WalletProofError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WalletProofError::Message(SiwsError::Malformed(_))
+            | WalletProofError::Signature(SignatureError::InvalidWalletAddress)
+            | WalletProofError::Signature(SignatureError::InvalidSignature) => StatusCode::BAD_REQUEST,
+            WalletProofError::Message(_)
+            | WalletProofError::Signature(SignatureError::Mismatch)
+            | WalletProofError::ChallengeUnavailable => StatusCode::UNAUTHORIZED,
+            WalletProofError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+// Check that a wallet signed one of our sign-in challenges and consume the
+// challenge. Used both to sign in and to link a wallet to an account.
+pub async fn verify_wallet_proof<C: GenericClient>(
+    client: &C,
+    config: &AppConfig,
+    wallet_address: &str,
+    message: &str,
+    signature: &str,
+) -> Result<(), WalletProofError> {
+    // The message must be a sign-in challenge issued by this server
+    let challenge = SiwsMessage::parse(message).map_err(WalletProofError::Message)?;
+    challenge
+        .validate(&config.siws_domain, wallet_address, &config.siws_chain_id, Utc::now())
+        .map_err(WalletProofError::Message)?;
+
+    // Verify that the message was signed by the wallet's private key
+    verify_wallet_signature(wallet_address, message, signature)
+        .map_err(WalletProofError::Signature)?;
+
+    // Consume the challenge so the signature cannot be replayed
+    let consumed = client
+        .execute(
+            "UPDATE web3_challenges SET used_at = NOW()
+             WHERE nonce = $1 AND wallet_address = $2 AND domain = $3 AND message = $4
+               AND used_at IS NULL AND expires_at > NOW()",
+            &[&challenge.nonce, &wallet_address, &config.siws_domain, &message],
+        )
+        .await?;
+
+    if consumed == 0 {
+        return Err(WalletProofError::ChallengeUnavailable);
+    }
+
+    Ok(())
+}
+
+// Check that a string is a well-formed base58 Solana public key
+pub fn is_valid_wallet_address(wallet_address: &str) -> bool {
+    Pubkey::from_str(wallet_address).is_ok()
+}
+
+// Verify that `signature` (base58) is a valid ed25519 signature of `message`
+// produced by the key behind `wallet_address` (base58 Solana public key)
+pub fn verify_wallet_signature(
+    wallet_address: &str,
+    message: &str,
+    signature: &str,
+) -> Result<(), SignatureError> {
+    let pubkey =
+        Pubkey::from_str(wallet_address).map_err(|_| SignatureError::InvalidWalletAddress)?;
+    let signature =
+        Signature::from_str(signature).map_err(|_| SignatureError::InvalidSignature)?;
+
+    if signature.verify(pubkey.as_ref(), message.as_bytes()) {
+        Ok(())
+    } else {
+        Err(SignatureError::Mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::{Keypair, Signer};
+
+    #[test]
+    fn test_valid_signature() {
+        let keypair = Keypair::new();
+        let message = "Sign in to Hex The Add Hub";
+        let signature = keypair.sign_message(message.as_bytes());
+
+        assert_eq!(
+            verify_wallet_signature(&keypair.pubkey().to_string(), message, &signature.to_string()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_tampered_message() {
+        let keypair = Keypair::new();
+        let signature = keypair.sign_message(b"Sign in to Hex The Add Hub");
+
+        assert_eq!(
+            verify_wallet_signature(
+                &keypair.pubkey().to_string(),
+                "Sign in to Hex The Add Hub as admin",
+                &signature.to_string()
+            ),
+            Err(SignatureError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let signer = Keypair::new();
+        let other = Keypair::new();
+        let message = "Sign in to Hex The Add Hub";
+        let signature = signer.sign_message(message.as_bytes());
+
+        assert_eq!(
+            verify_wallet_signature(&other.pubkey().to_string(), message, &signature.to_string()),
+            Err(SignatureError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn test_wallet_proof_error_status() {
+        assert_eq!(
+            WalletProofError::Message(SiwsError::Malformed("nonce")).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            WalletProofError::Message(SiwsError::Expired).status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            WalletProofError::Signature(SignatureError::InvalidSignature).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            WalletProofError::Signature(SignatureError::Mismatch).status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            WalletProofError::ChallengeUnavailable.status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn test_malformed_inputs() {
+        let keypair = Keypair::new();
+        let message = "Sign in to Hex The Add Hub";
+        let signature = keypair.sign_message(message.as_bytes());
+
+        assert_eq!(
+            verify_wallet_signature("not-a-wallet", message, &signature.to_string()),
+            Err(SignatureError::InvalidWalletAddress)
+        );
+        assert_eq!(
+            verify_wallet_signature(&keypair.pubkey().to_string(), message, "0OIl"),
+            Err(SignatureError::InvalidSignature)
+        );
+    }
+}
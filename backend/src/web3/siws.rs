@@ -0,0 +1,320 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use std::fmt;
+
+// Sign-In-With-Solana message, modelled on the EIP-4361 text format:
+//
+// {domain} wants you to sign in with your Solana account:
+// {address}
+//
+// {statement}
+//
+// URI: {uri}
+// Version: 1
+// Chain ID: {chain_id}
+// Nonce: {nonce}
+// Issued At: {issued_at}
+// Expiration Time: {expiration_time}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiwsMessage {
+    pub domain: String,
+    pub address: String,
+    pub statement: String,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: String,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: DateTime<Utc>,
+}
+
+pub const SIWS_VERSION: &str = "1";
+pub const SIWS_STATEMENT: &str = "Sign in to Hex The Add Hub.";
+const HEADER_SUFFIX: &str = " wants you to sign in with your Solana account:";
+const NONCE_LENGTH: usize = 24;
+
+// Allowed clock drift between us and the signer when checking `Issued At`
+const MAX_CLOCK_SKEW_SECS: i64 = 60;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SiwsError {
+    Malformed(&'static str),
+    DomainMismatch,
+    AddressMismatch,
+    UnsupportedVersion,
+    ChainMismatch,
+    NotYetValid,
+    Expired,
+}
+
+impl fmt::Display for SiwsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SiwsError::Malformed(field) => write!(f, "Malformed sign-in message: {}", field),
+            SiwsError::DomainMismatch => write!(f, "Sign-in message is for a different domain"),
+            SiwsError::AddressMismatch => write!(f, "Sign-in message is for a different wallet"),
+            SiwsError::UnsupportedVersion => write!(f, "Unsupported sign-in message version"),
+            SiwsError::ChainMismatch => write!(f, "Sign-in message is for a different chain"),
+            SiwsError::NotYetValid => write!(f, "Sign-in message is not yet valid"),
+            SiwsError::Expired => write!(f, "Sign-in message has expired"),
+        }
+    }
+}
+
+impl std::error::Error for SiwsError {}
+
+impl SiwsMessage {
+    // Build a fresh challenge for `address` with a random nonce
+    pub fn new_challenge(
+        domain: &str,
+        address: &str,
+        uri: &str,
+        chain_id: &str,
+        ttl: Duration,
+    ) -> Self {
+        // Timestamps are rendered with second precision, so truncate up front
+        // to keep the struct identical to what parsing the text gives back
+        let issued_at = DateTime::parse_from_rfc3339(
+            &Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        )
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now());
+
+        SiwsMessage {
+            domain: domain.to_string(),
+            address: address.to_string(),
+            statement: SIWS_STATEMENT.to_string(),
+            uri: uri.to_string(),
+            version: SIWS_VERSION.to_string(),
+            chain_id: chain_id.to_string(),
+            nonce: generate_nonce(),
+            issued_at,
+            expiration_time: issued_at + ttl,
+        }
+    }
+
+    // Parse the text format field by field
+    pub fn parse(message: &str) -> Result<Self, SiwsError> {
+        let mut lines = message.split('\n');
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(HEADER_SUFFIX))
+            .filter(|domain| !domain.is_empty())
+            .ok_or(SiwsError::Malformed("header"))?;
+
+        let address = lines
+            .next()
+            .filter(|address| !address.is_empty())
+            .ok_or(SiwsError::Malformed("address"))?;
+
+        expect_blank(lines.next(), "statement")?;
+        let statement = lines
+            .next()
+            .filter(|statement| !statement.is_empty())
+            .ok_or(SiwsError::Malformed("statement"))?;
+        expect_blank(lines.next(), "statement")?;
+
+        let uri = field(lines.next(), "URI: ", "URI")?;
+        let version = field(lines.next(), "Version: ", "Version")?;
+        let chain_id = field(lines.next(), "Chain ID: ", "Chain ID")?;
+        let nonce = field(lines.next(), "Nonce: ", "Nonce")?;
+        let issued_at = timestamp(field(lines.next(), "Issued At: ", "Issued At")?, "Issued At")?;
+        let expiration_time = timestamp(
+            field(lines.next(), "Expiration Time: ", "Expiration Time")?,
+            "Expiration Time",
+        )?;
+
+        if lines.next().is_some() {
+            return Err(SiwsError::Malformed("trailing content"));
+        }
+
+        if !nonce.chars().all(|c| c.is_ascii_alphanumeric()) || nonce.len() < 8 {
+            return Err(SiwsError::Malformed("Nonce"));
+        }
+
+        Ok(SiwsMessage {
+            domain: domain.to_string(),
+            address: address.to_string(),
+            statement: statement.to_string(),
+            uri: uri.to_string(),
+            version: version.to_string(),
+            chain_id: chain_id.to_string(),
+            nonce: nonce.to_string(),
+            issued_at,
+            expiration_time,
+        })
+    }
+
+    // Check the parsed fields against what this server expects
+    pub fn validate(
+        &self,
+        expected_domain: &str,
+        expected_address: &str,
+        expected_chain_id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), SiwsError> {
+        if self.domain != expected_domain {
+            return Err(SiwsError::DomainMismatch);
+        }
+
+        if self.address != expected_address {
+            return Err(SiwsError::AddressMismatch);
+        }
+
+        if self.version != SIWS_VERSION {
+            return Err(SiwsError::UnsupportedVersion);
+        }
+
+        if self.chain_id != expected_chain_id {
+            return Err(SiwsError::ChainMismatch);
+        }
+
+        if self.issued_at > now + Duration::seconds(MAX_CLOCK_SKEW_SECS) {
+            return Err(SiwsError::NotYetValid);
+        }
+
+        if self.expiration_time <= now {
+            return Err(SiwsError::Expired);
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SiwsMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}\n{}\n\n{}\n\nURI: {}\nVersion: {}\nChain ID: {}\nNonce: {}\nIssued At: {}\nExpiration Time: {}",
+            self.domain,
+            HEADER_SUFFIX,
+            self.address,
+            self.statement,
+            self.uri,
+            self.version,
+            self.chain_id,
+            self.nonce,
+            self.issued_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.expiration_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        )
+    }
+}
+
+// Random alphanumeric nonce for a single challenge
+pub fn generate_nonce() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(NONCE_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn expect_blank(line: Option<&str>, context: &'static str) -> Result<(), SiwsError> {
+    match line {
+        Some("") => Ok(()),
+        _ => Err(SiwsError::Malformed(context)),
+    }
+}
+
+fn field<'a>(
+    line: Option<&'a str>,
+    prefix: &str,
+    name: &'static str,
+) -> Result<&'a str, SiwsError> {
+    line.and_then(|line| line.strip_prefix(prefix))
+        .filter(|value| !value.is_empty())
+        .ok_or(SiwsError::Malformed(name))
+}
+
+fn timestamp(value: &str, name: &'static str) -> Result<DateTime<Utc>, SiwsError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| SiwsError::Malformed(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+
+    fn challenge() -> SiwsMessage {
+        SiwsMessage::new_challenge(
+            "localhost:5000",
+            ADDRESS,
+            "http://localhost:5000",
+            "mainnet",
+            Duration::minutes(5),
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let message = challenge();
+        let parsed = SiwsMessage::parse(&message.to_string()).unwrap();
+
+        assert_eq!(parsed, message);
+        assert_eq!(
+            parsed.validate("localhost:5000", ADDRESS, "mainnet", Utc::now()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed_messages() {
+        let text = challenge().to_string();
+
+        assert_eq!(
+            SiwsMessage::parse("Please sign this"),
+            Err(SiwsError::Malformed("header"))
+        );
+        assert_eq!(
+            SiwsMessage::parse(&text.replace("Nonce: ", "Nonce:")),
+            Err(SiwsError::Malformed("Nonce"))
+        );
+        assert_eq!(
+            SiwsMessage::parse(&format!("{}\nRequest ID: 1", text)),
+            Err(SiwsError::Malformed("trailing content"))
+        );
+
+        let without_expiry: Vec<&str> = text.lines().take(10).collect();
+        assert_eq!(
+            SiwsMessage::parse(&without_expiry.join("\n")),
+            Err(SiwsError::Malformed("Expiration Time"))
+        );
+    }
+
+    #[test]
+    fn test_validate_fields() {
+        let message = challenge();
+        let now = Utc::now();
+
+        assert_eq!(
+            message.validate("evil.example", ADDRESS, "mainnet", now),
+            Err(SiwsError::DomainMismatch)
+        );
+        assert_eq!(
+            message.validate("localhost:5000", "11111111111111111111111111111111", "mainnet", now),
+            Err(SiwsError::AddressMismatch)
+        );
+        assert_eq!(
+            message.validate("localhost:5000", ADDRESS, "devnet", now),
+            Err(SiwsError::ChainMismatch)
+        );
+        assert_eq!(
+            message.validate("localhost:5000", ADDRESS, "mainnet", now + Duration::minutes(10)),
+            Err(SiwsError::Expired)
+        );
+        assert_eq!(
+            message.validate("localhost:5000", ADDRESS, "mainnet", now - Duration::minutes(10)),
+            Err(SiwsError::NotYetValid)
+        );
+    }
+
+    #[test]
+    fn test_nonces_are_unique() {
+        assert_ne!(generate_nonce(), generate_nonce());
+        assert_eq!(generate_nonce().len(), NONCE_LENGTH);
+    }
+}